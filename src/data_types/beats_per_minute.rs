@@ -1,5 +1,11 @@
+use std::fmt;
+
+pub(crate) const MIN_BPM: f32 = 20.0;
+pub(crate) const MAX_BPM: f32 = 300.0;
+
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug)]
 pub(crate) struct BeatsPerMinute(f32);
-pub(crate) struct BeatsPerSecond(f32);
+pub(crate) struct BeatsPerSecond(f64);
 
 impl From<u32> for BeatsPerMinute {
     fn from(value: u32) -> Self {
@@ -7,14 +13,75 @@ impl From<u32> for BeatsPerMinute {
     }
 }
 
+impl TryFrom<f32> for BeatsPerMinute {
+    type Error = &'static str;
+
+    fn try_from(value: f32) -> Result<Self, Self::Error> {
+        if !(MIN_BPM..=MAX_BPM).contains(&value) {
+            return Err("Tempo out of range");
+        }
+        Ok(BeatsPerMinute(value))
+    }
+}
+
+impl From<BeatsPerMinute> for f32 {
+    fn from(value: BeatsPerMinute) -> Self {
+        value.0
+    }
+}
+
 impl BeatsPerMinute {
+    /// Worked out in double precision, as small errors here add up over a long song
     pub(crate) fn beats_per_second(&self) -> BeatsPerSecond {
-        BeatsPerSecond(self.0 / 60.0)
+        BeatsPerSecond(self.0 as f64 / 60.0)
+    }
+
+    /// Change the tempo by `amount` BPM, staying within the supported range.
+    pub(crate) fn nudged(&self, amount: f32) -> BeatsPerMinute {
+        BeatsPerMinute((self.0 + amount).clamp(MIN_BPM, MAX_BPM))
     }
 }
 
-impl From<BeatsPerSecond> for f32 {
+impl From<BeatsPerSecond> for f64 {
     fn from(value: BeatsPerSecond) -> Self {
         value.0
     }
 }
+
+impl fmt::Display for BeatsPerMinute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Only show the fraction when there is one
+        if self.0.fract() == 0.0 {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "{:.2}", self.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_types::beats_per_minute::BeatsPerMinute;
+
+    #[test]
+    fn fractional_tempo() {
+        let bpm = BeatsPerMinute::try_from(97.5).unwrap();
+        assert_eq!(f64::from(bpm.beats_per_second()), 1.625);
+        assert_eq!(bpm.to_string(), "97.50");
+        assert_eq!(BeatsPerMinute::from(120).to_string(), "120");
+    }
+
+    #[test]
+    fn tempo_out_of_range() {
+        assert!(BeatsPerMinute::try_from(0.0).is_err());
+        assert!(BeatsPerMinute::try_from(f32::NAN).is_err());
+        assert!(BeatsPerMinute::try_from(1000.0).is_err());
+    }
+
+    #[test]
+    fn nudge_stays_in_range() {
+        let bpm = BeatsPerMinute::try_from(299.5).unwrap();
+        assert_eq!(f32::from(bpm.nudged(1.0)), 300.0);
+        assert_eq!(f32::from(bpm.nudged(-0.5)), 299.0);
+    }
+}
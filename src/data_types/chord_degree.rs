@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, PartialEq, Debug)]
@@ -12,8 +12,72 @@ pub(crate) enum ChordDegree {
     VII,
 }
 
+impl ChordDegree {
+    pub(crate) const ALL: [ChordDegree; 7] = [
+        ChordDegree::I,
+        ChordDegree::II,
+        ChordDegree::III,
+        ChordDegree::IV,
+        ChordDegree::V,
+        ChordDegree::VI,
+        ChordDegree::VII,
+    ];
+
+    /// Move the chord `steps` degrees up the scale, or down when negative, wrapping round the
+    /// octave.
+    pub(crate) fn shifted(&self, steps: i32) -> ChordDegree {
+        let index = ChordDegree::ALL.iter().position(|d| d == self).unwrap() as i32;
+        ChordDegree::ALL[(index + steps).rem_euclid(7) as usize]
+    }
+}
+
 impl fmt::Display for ChordDegree {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         fmt::Debug::fmt(self, f)
     }
 }
+
+impl FromStr for ChordDegree {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "I" => Ok(ChordDegree::I),
+            "II" => Ok(ChordDegree::II),
+            "III" => Ok(ChordDegree::III),
+            "IV" => Ok(ChordDegree::IV),
+            "V" => Ok(ChordDegree::V),
+            "VI" => Ok(ChordDegree::VI),
+            "VII" => Ok(ChordDegree::VII),
+            _ => Err("Not a chord degree"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_types::chord_degree::ChordDegree;
+
+    #[test]
+    fn parse_chord_degree_round_trips_display() {
+        for degree in [ChordDegree::I, ChordDegree::IV, ChordDegree::VII] {
+            assert_eq!(degree.to_string().parse::<ChordDegree>(), Ok(degree));
+        }
+    }
+
+    #[test]
+    fn parse_lower_case_chord_degree() {
+        assert_eq!("vi".parse::<ChordDegree>(), Ok(ChordDegree::VI));
+    }
+
+    #[test]
+    fn shift_wraps_round_octave() {
+        assert_eq!(ChordDegree::VI.shifted(3), ChordDegree::II);
+        assert_eq!(ChordDegree::I.shifted(-1), ChordDegree::VII);
+    }
+
+    #[test]
+    fn parse_invalid_chord_degree() {
+        assert!("VIII".parse::<ChordDegree>().is_err());
+    }
+}
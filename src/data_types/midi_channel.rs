@@ -0,0 +1,31 @@
+/// One of the sixteen MIDI channels, counted from zero as they are sent.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub(crate) struct MidiChannel(u8);
+
+impl TryFrom<u8> for MidiChannel {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        if value > 15 {
+            return Err("MIDI channel larger than 15");
+        }
+        Ok(MidiChannel(value))
+    }
+}
+
+impl From<MidiChannel> for u8 {
+    fn from(value: MidiChannel) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_types::midi_channel::MidiChannel;
+
+    #[test]
+    fn channel_is_four_bit() {
+        assert_eq!(u8::from(MidiChannel::try_from(15).unwrap()), 15);
+        assert!(MidiChannel::try_from(16).is_err());
+    }
+}
@@ -1,4 +1,8 @@
 pub mod beats_per_minute;
 pub mod chord_degree;
+pub mod midi_channel;
+pub mod musical_position;
 pub mod note;
 pub mod tatum;
+pub mod time_signature;
+pub mod velocity;
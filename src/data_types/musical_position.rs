@@ -0,0 +1,49 @@
+use std::ops::Add;
+
+/// Ticks in a tatum, fine enough to place notes between steps. 960 to the quarter note, the
+/// same resolution most DAWs use.
+pub(crate) const TICKS_PER_TATUM: u64 = 240;
+
+/// A position in the song counted in whole ticks from the start. Being an integer, positions
+/// add up exactly however long the song plays, and are only rounded when turned into frames.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Default)]
+pub(crate) struct MusicalPosition(u64);
+
+impl MusicalPosition {
+    pub(crate) fn from_ticks(ticks: u64) -> MusicalPosition {
+        MusicalPosition(ticks)
+    }
+
+    pub(crate) fn from_tatums(tatums: u64) -> MusicalPosition {
+        MusicalPosition(tatums * TICKS_PER_TATUM)
+    }
+
+    pub(crate) fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Tatums as a fraction, exact for any realistic song length.
+    pub(crate) fn tatums(&self) -> f64 {
+        self.0 as f64 / TICKS_PER_TATUM as f64
+    }
+}
+
+impl Add for MusicalPosition {
+    type Output = MusicalPosition;
+
+    fn add(self, rhs: MusicalPosition) -> Self::Output {
+        MusicalPosition(self.0 + rhs.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_types::musical_position::MusicalPosition;
+
+    #[test]
+    fn tatums_and_ticks() {
+        let position = MusicalPosition::from_tatums(3) + MusicalPosition::from_ticks(120);
+        assert_eq!(position.ticks(), 840);
+        assert_eq!(position.tatums(), 3.5);
+    }
+}
@@ -1,5 +1,10 @@
 // TODO: in future Tatum should be derived from the ChordSequence
+/// Tatums in a bar of 4/4, which is how long a new lane is by default.
 pub(crate) const TATUM_SUBDIVDISONS_PER_BAR: usize = 16;
+/// A tatum is a sixteenth note whatever the time signature.
+pub(crate) const TATUMS_PER_QUARTER_NOTE: usize = 4;
+/// Long enough for a bar of 12/8 and a lane of up to two bars of 4/4.
+pub(crate) const MAX_TATUMS_PER_LANE: usize = 32;
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
 pub(crate) struct Tatum(usize);
@@ -8,7 +13,7 @@ impl TryFrom<usize> for Tatum {
     type Error = &'static str;
 
     fn try_from(value: usize) -> Result<Self, Self::Error> {
-        if value >= TATUM_SUBDIVDISONS_PER_BAR {
+        if value >= MAX_TATUMS_PER_LANE {
             return Err("Tatum index that is larger than number of subdivisions");
         }
         Ok(Tatum(value))
@@ -22,10 +27,10 @@ impl From<Tatum> for usize {
 }
 
 impl Tatum {
-    pub fn add(&self, offset: i32) -> Tatum {
+    /// Offset the tatum, wrapping around a loop of `steps` tatums.
+    pub fn add_within(&self, offset: i32, steps: usize) -> Tatum {
         let new_selected_chord = self.0 as i32 + offset;
-        let new_selected_modulo_chord =
-            new_selected_chord.rem_euclid(TATUM_SUBDIVDISONS_PER_BAR as i32);
+        let new_selected_modulo_chord = new_selected_chord.rem_euclid(steps as i32);
         Tatum::try_from(new_selected_modulo_chord as usize).unwrap()
     }
 }
@@ -38,35 +43,46 @@ fn test_create_valid_tatums() {
 
 #[cfg(test)]
 mod tests {
-    use crate::data_types::tatum::Tatum;
+    use crate::data_types::tatum::{Tatum, MAX_TATUMS_PER_LANE, TATUM_SUBDIVDISONS_PER_BAR};
 
     #[test]
     fn test_create_invalid_tatum() {
-        assert!(Tatum::try_from(16).is_err());
+        assert!(Tatum::try_from(MAX_TATUMS_PER_LANE).is_err());
+    }
+
+    #[test]
+    fn test_create_tatum_past_a_bar_of_four_four() {
+        assert!(Tatum::try_from(23).is_ok());
     }
 
     #[test]
     fn add_offset_to_tatum_increments() {
-        assert_eq!(Tatum(1).add(1).0, 2);
+        assert_eq!(Tatum(1).add_within(1, TATUM_SUBDIVDISONS_PER_BAR).0, 2);
     }
 
     #[test]
     fn subtract_offset_to_tatum_decrements() {
-        assert_eq!(Tatum(1).add(-1).0, 0);
+        assert_eq!(Tatum(1).add_within(-1, TATUM_SUBDIVDISONS_PER_BAR).0, 0);
     }
 
     #[test]
     fn subtract_offset_to_tatum_wraps_around() {
-        assert_eq!(Tatum(0).add(-1).0, 15);
+        assert_eq!(Tatum(0).add_within(-1, TATUM_SUBDIVDISONS_PER_BAR).0, 15);
     }
 
     #[test]
     fn add_offset_to_tatum_wraps_around() {
-        assert_eq!(Tatum(15).add(1).0, 0);
+        assert_eq!(Tatum(15).add_within(1, TATUM_SUBDIVDISONS_PER_BAR).0, 0);
     }
 
     #[test]
     fn add_large_offset_to_tatum_wraps_around() {
-        assert_eq!(Tatum(15).add(32).0, 15);
+        assert_eq!(Tatum(15).add_within(32, TATUM_SUBDIVDISONS_PER_BAR).0, 15);
+    }
+
+    #[test]
+    fn add_offset_within_short_loop_wraps_around() {
+        assert_eq!(Tatum(6).add_within(1, 7).0, 0);
+        assert_eq!(Tatum(0).add_within(-1, 7).0, 6);
     }
 }
@@ -0,0 +1,157 @@
+use std::fmt;
+
+use super::tatum::{MAX_TATUMS_PER_LANE, TATUM_SUBDIVDISONS_PER_BAR};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct TimeSignature {
+    numerator: u32,
+    denominator: u32,
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self {
+            numerator: 4,
+            denominator: 4,
+        }
+    }
+}
+
+impl TimeSignature {
+    pub fn new(numerator: u32, denominator: u32) -> Result<TimeSignature, &'static str> {
+        if numerator == 0 || numerator > 32 {
+            return Err("Time signature must have between 1 and 32 beats");
+        }
+        if ![1, 2, 4, 8, 16].contains(&denominator) {
+            return Err("Time signature beat unit must be a whole note down to a sixteenth");
+        }
+        let time_signature = TimeSignature {
+            numerator,
+            denominator,
+        };
+        // A lane one bar long has to fit the whole bar
+        if time_signature.tatums_per_bar() > MAX_TATUMS_PER_LANE {
+            return Err("Time signature bars can be at most 32 sixteenth notes long");
+        }
+        Ok(time_signature)
+    }
+
+    pub fn numerator(&self) -> u32 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> u32 {
+        self.denominator
+    }
+
+    pub fn tatums_per_bar(&self) -> usize {
+        self.numerator as usize * TATUM_SUBDIVDISONS_PER_BAR / self.denominator as usize
+    }
+
+    /// How the tatums of a bar group into felt beats. Compound meters like 6/8 and 12/8 group
+    /// in dotted quarters, and odd eighth meters like 7/8 group in twos with a three at the end.
+    pub fn beat_groups(&self) -> Vec<usize> {
+        let tatums_per_bar = self.tatums_per_bar();
+        let tatums_per_eighth = TATUM_SUBDIVDISONS_PER_BAR / 8;
+        if self.denominator == 8 {
+            if self.numerator.is_multiple_of(3) {
+                return vec![3 * tatums_per_eighth; self.numerator as usize / 3];
+            }
+            if self.numerator > 1 {
+                let mut groups = vec![2 * tatums_per_eighth; self.numerator as usize / 2];
+                if self.numerator % 2 == 1 {
+                    *groups.last_mut().unwrap() += tatums_per_eighth;
+                }
+                return groups;
+            }
+        }
+        let tatums_per_beat = (TATUM_SUBDIVDISONS_PER_BAR / self.denominator as usize).max(4);
+        let mut groups = vec![tatums_per_beat; tatums_per_bar / tatums_per_beat];
+        if !tatums_per_bar.is_multiple_of(tatums_per_beat) {
+            groups.push(tatums_per_bar % tatums_per_beat);
+        }
+        groups
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.denominator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_types::time_signature::TimeSignature;
+
+    #[test]
+    fn tatums_per_bar() {
+        assert_eq!(TimeSignature::new(4, 4).unwrap().tatums_per_bar(), 16);
+        assert_eq!(TimeSignature::new(3, 4).unwrap().tatums_per_bar(), 12);
+        assert_eq!(TimeSignature::new(7, 8).unwrap().tatums_per_bar(), 14);
+        assert_eq!(TimeSignature::new(12, 8).unwrap().tatums_per_bar(), 24);
+        assert_eq!(TimeSignature::new(5, 16).unwrap().tatums_per_bar(), 5);
+    }
+
+    #[test]
+    fn invalid_time_signatures() {
+        assert!(TimeSignature::new(0, 4).is_err());
+        assert!(TimeSignature::new(4, 3).is_err());
+        assert!(TimeSignature::new(4, 32).is_err());
+    }
+
+    #[test]
+    fn bars_longer_than_a_lane_are_rejected() {
+        assert!(TimeSignature::new(9, 4).is_err());
+        assert!(TimeSignature::new(5, 2).is_err());
+        assert!(TimeSignature::new(32, 1).is_err());
+        assert!(TimeSignature::new(17, 8).is_err());
+        for (numerator, denominator) in [(2, 1), (8, 4), (16, 8), (32, 16)] {
+            let time_signature = TimeSignature::new(numerator, denominator).unwrap();
+            assert_eq!(time_signature.tatums_per_bar(), 32);
+        }
+    }
+
+    #[test]
+    fn simple_meters_group_by_beat() {
+        assert_eq!(
+            TimeSignature::new(3, 4).unwrap().beat_groups(),
+            vec![4, 4, 4]
+        );
+        assert_eq!(TimeSignature::new(2, 2).unwrap().beat_groups(), vec![8, 8]);
+    }
+
+    #[test]
+    fn compound_meters_group_by_dotted_quarter() {
+        assert_eq!(TimeSignature::new(6, 8).unwrap().beat_groups(), vec![6, 6]);
+        assert_eq!(
+            TimeSignature::new(12, 8).unwrap().beat_groups(),
+            vec![6, 6, 6, 6]
+        );
+    }
+
+    #[test]
+    fn odd_eighth_meters_end_with_a_group_of_three() {
+        assert_eq!(
+            TimeSignature::new(7, 8).unwrap().beat_groups(),
+            vec![4, 4, 6]
+        );
+        assert_eq!(TimeSignature::new(5, 8).unwrap().beat_groups(), vec![4, 6]);
+    }
+
+    #[test]
+    fn sixteenth_meters_group_in_fours() {
+        assert_eq!(TimeSignature::new(7, 16).unwrap().beat_groups(), vec![4, 3]);
+    }
+
+    #[test]
+    fn beat_groups_fill_the_bar() {
+        for (numerator, denominator) in [(4, 4), (3, 4), (7, 8), (12, 8), (9, 16), (1, 8)] {
+            let time_signature = TimeSignature::new(numerator, denominator).unwrap();
+            assert_eq!(
+                time_signature.beat_groups().iter().sum::<usize>(),
+                time_signature.tatums_per_bar()
+            );
+        }
+    }
+}
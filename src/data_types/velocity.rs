@@ -0,0 +1,37 @@
+/// How hard a note is played, as a MIDI velocity.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Copy, Clone, Hash)]
+pub(crate) struct Velocity(u8);
+
+impl Default for Velocity {
+    fn default() -> Self {
+        Velocity(120)
+    }
+}
+
+impl TryFrom<u8> for Velocity {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        if value > 127 {
+            return Err("Velocity larger than 127");
+        }
+        Ok(Velocity(value))
+    }
+}
+
+impl From<Velocity> for u8 {
+    fn from(value: Velocity) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data_types::velocity::Velocity;
+
+    #[test]
+    fn velocity_is_seven_bit() {
+        assert_eq!(u8::from(Velocity::try_from(127).unwrap()), 127);
+        assert!(Velocity::try_from(128).is_err());
+    }
+}
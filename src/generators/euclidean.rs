@@ -0,0 +1,147 @@
+use crate::{
+    data_types::{chord_degree::ChordDegree, tatum::Tatum},
+    model::chord_sequence::ChordSequence,
+};
+
+/// Spread `pulses` hits as evenly as possible over `steps` using Bjorklund's algorithm,
+/// then rotate the pattern `rotation` steps later.
+pub(crate) fn euclidean_rhythm(
+    pulses: usize,
+    steps: usize,
+    rotation: usize,
+) -> Result<Vec<bool>, &'static str> {
+    if steps == 0 {
+        return Err("Euclidean rhythm needs at least one step");
+    }
+    if pulses > steps {
+        return Err("Euclidean rhythm can't have more pulses than steps");
+    }
+
+    let mut heads: Vec<Vec<bool>> = vec![vec![true]; pulses];
+    let mut remainders: Vec<Vec<bool>> = vec![vec![false]; steps - pulses];
+    while remainders.len() > 1 && !heads.is_empty() {
+        let pairs = heads.len().min(remainders.len());
+        let leftover = if heads.len() > pairs {
+            heads.split_off(pairs)
+        } else {
+            remainders.split_off(pairs)
+        };
+        for (head, remainder) in heads.iter_mut().zip(remainders) {
+            head.extend(remainder);
+        }
+        remainders = leftover;
+    }
+
+    let mut rhythm: Vec<bool> = heads.into_iter().chain(remainders).flatten().collect();
+    rhythm.rotate_right(rotation % steps);
+    Ok(rhythm)
+}
+
+/// Place the chords of `progression` in turn on each pulse of a euclidean rhythm.
+pub(crate) fn euclidean_sequence(
+    progression: &[ChordDegree],
+    pulses: usize,
+    steps: usize,
+    rotation: usize,
+) -> Result<ChordSequence, &'static str> {
+    if progression.is_empty() {
+        return Err("Euclidean rhythm needs at least one chord to place");
+    }
+    let rhythm = euclidean_rhythm(pulses, steps, rotation)?;
+    let mut sequence = ChordSequence::with_steps(steps)?;
+    let mut chords = progression.iter().cycle();
+    for (index, is_pulse) in rhythm.into_iter().enumerate() {
+        if is_pulse {
+            sequence[Tatum::try_from(index)?] = chords.next().copied();
+        }
+    }
+    Ok(sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{chord_degree::ChordDegree, tatum::Tatum},
+        generators::euclidean::{euclidean_rhythm, euclidean_sequence},
+    };
+
+    fn pattern(text: &str) -> Vec<bool> {
+        text.chars().map(|c| c == 'x').collect()
+    }
+
+    #[test]
+    fn tresillo() {
+        assert_eq!(euclidean_rhythm(3, 8, 0).unwrap(), pattern("x..x..x."));
+    }
+
+    #[test]
+    fn cinquillo() {
+        assert_eq!(euclidean_rhythm(5, 8, 0).unwrap(), pattern("x.xx.xx."));
+    }
+
+    #[test]
+    fn seven_in_sixteen() {
+        assert_eq!(
+            euclidean_rhythm(7, 16, 0).unwrap(),
+            pattern("x..x.x.x..x.x.x.")
+        );
+    }
+
+    #[test]
+    fn no_pulses_is_silent() {
+        assert_eq!(euclidean_rhythm(0, 4, 0).unwrap(), pattern("...."));
+    }
+
+    #[test]
+    fn every_step_a_pulse() {
+        assert_eq!(euclidean_rhythm(4, 4, 0).unwrap(), pattern("xxxx"));
+    }
+
+    #[test]
+    fn rotation_shifts_pattern_later() {
+        assert_eq!(euclidean_rhythm(3, 8, 1).unwrap(), pattern(".x..x..x"));
+        assert_eq!(euclidean_rhythm(3, 8, 9).unwrap(), pattern(".x..x..x"));
+    }
+
+    #[test]
+    fn too_many_pulses_is_an_error() {
+        assert!(euclidean_rhythm(5, 4, 0).is_err());
+        assert!(euclidean_rhythm(0, 0, 0).is_err());
+    }
+
+    #[test]
+    fn sequence_cycles_through_progression_on_pulses() {
+        let sequence = euclidean_sequence(&[ChordDegree::I, ChordDegree::V], 3, 8, 0).unwrap();
+        let chords: Vec<Option<ChordDegree>> = sequence.iter().copied().collect();
+        assert_eq!(
+            chords,
+            vec![
+                Some(ChordDegree::I),
+                None,
+                None,
+                Some(ChordDegree::V),
+                None,
+                None,
+                Some(ChordDegree::I),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn sequence_has_requested_steps() {
+        let sequence = euclidean_sequence(&[ChordDegree::II], 5, 12, 2).unwrap();
+        assert_eq!(sequence.steps(), 12);
+        assert_eq!(sequence[Tatum::try_from(2).unwrap()], Some(ChordDegree::II));
+    }
+
+    #[test]
+    fn sequence_needs_a_chord() {
+        assert!(euclidean_sequence(&[], 3, 8, 0).is_err());
+    }
+
+    #[test]
+    fn sequence_longer_than_a_lane_is_an_error() {
+        assert!(euclidean_sequence(&[ChordDegree::I], 3, 33, 0).is_err());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod euclidean;
+pub mod random_progression;
+pub mod transforms;
@@ -0,0 +1,320 @@
+use std::fmt;
+
+use crate::{
+    data_types::{chord_degree::ChordDegree, tatum::Tatum},
+    model::chord_sequence::ChordSequence,
+};
+
+use super::euclidean::euclidean_rhythm;
+
+/// Small seedable generator (SplitMix64) so a take can be regenerated exactly from its seed.
+pub(crate) struct SeededRandom(u64);
+
+impl SeededRandom {
+    pub(crate) fn new(seed: u64) -> SeededRandom {
+        SeededRandom(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub(crate) fn next_below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Cadence {
+    None,
+    /// V to I
+    Authentic,
+    /// IV to I
+    Plagal,
+    /// Ends on V
+    Half,
+    /// V to VI
+    Deceptive,
+}
+
+impl Cadence {
+    pub(crate) const ALL: [Cadence; 5] = [
+        Cadence::None,
+        Cadence::Authentic,
+        Cadence::Plagal,
+        Cadence::Half,
+        Cadence::Deceptive,
+    ];
+
+    fn chords(&self) -> &'static [ChordDegree] {
+        match self {
+            Cadence::None => &[],
+            Cadence::Authentic => &[ChordDegree::V, ChordDegree::I],
+            Cadence::Plagal => &[ChordDegree::IV, ChordDegree::I],
+            Cadence::Half => &[ChordDegree::V],
+            Cadence::Deceptive => &[ChordDegree::V, ChordDegree::VI],
+        }
+    }
+}
+
+impl fmt::Display for Cadence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct ProgressionConstraints {
+    pub start_on_tonic: bool,
+    pub end_on_tonic: bool,
+    pub allowed_degrees: Vec<ChordDegree>,
+    pub cadence: Cadence,
+    /// How many of the lane's steps get a chord
+    pub density: usize,
+    pub seed: u64,
+}
+
+impl Default for ProgressionConstraints {
+    fn default() -> Self {
+        Self {
+            start_on_tonic: true,
+            end_on_tonic: true,
+            allowed_degrees: ChordDegree::ALL.to_vec(),
+            cadence: Cadence::Authentic,
+            density: 4,
+            seed: 0,
+        }
+    }
+}
+
+fn pick_chord(
+    random: &mut SeededRandom,
+    allowed_degrees: &[ChordDegree],
+    previous: Option<ChordDegree>,
+) -> ChordDegree {
+    // Avoid repeating the previous chord where there is anything else to choose
+    let candidates: Vec<ChordDegree> = allowed_degrees
+        .iter()
+        .copied()
+        .filter(|&degree| allowed_degrees.len() == 1 || Some(degree) != previous)
+        .collect();
+    candidates[random.next_below(candidates.len())]
+}
+
+fn random_progression(
+    constraints: &ProgressionConstraints,
+) -> Result<Vec<ChordDegree>, &'static str> {
+    if constraints.allowed_degrees.is_empty() {
+        return Err("Need at least one allowed chord");
+    }
+    let mut ending = constraints.cadence.chords().to_vec();
+    if constraints.end_on_tonic {
+        match ending.last() {
+            Some(ChordDegree::I) => {}
+            Some(_) => return Err("Cadence doesn't end on the tonic"),
+            None => ending.push(ChordDegree::I),
+        }
+    }
+    let starting = if constraints.start_on_tonic {
+        vec![ChordDegree::I]
+    } else {
+        vec![]
+    };
+    if starting.len() + ending.len() > constraints.density {
+        return Err("Density too low to fit the cadence");
+    }
+    if starting
+        .iter()
+        .chain(ending.iter())
+        .any(|degree| !constraints.allowed_degrees.contains(degree))
+    {
+        return Err("Required chords aren't allowed");
+    }
+
+    let mut random = SeededRandom::new(constraints.seed);
+    let mut progression = starting;
+    let free_chords = constraints.density - progression.len() - ending.len();
+    for _ in 0..free_chords {
+        let chord = pick_chord(
+            &mut random,
+            &constraints.allowed_degrees,
+            progression.last().copied(),
+        );
+        progression.push(chord);
+    }
+    progression.extend(ending);
+    Ok(progression)
+}
+
+/// Generate a lane of `steps` tatums whose chords satisfy `constraints`, with the chords
+/// spread evenly through the lane. The same constraints always produce the same sequence.
+pub(crate) fn generate_sequence(
+    constraints: &ProgressionConstraints,
+    steps: usize,
+) -> Result<ChordSequence, &'static str> {
+    if constraints.density > steps {
+        return Err("More chords than steps");
+    }
+    let progression = random_progression(constraints)?;
+    let rhythm = euclidean_rhythm(constraints.density, steps, 0)?;
+    let mut sequence = ChordSequence::with_steps(steps)?;
+    let mut chords = progression.into_iter();
+    for (index, is_pulse) in rhythm.into_iter().enumerate() {
+        if is_pulse {
+            sequence[Tatum::try_from(index)?] = chords.next();
+        }
+    }
+    Ok(sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{chord_degree::ChordDegree, tatum::Tatum},
+        generators::random_progression::{
+            generate_sequence, random_progression, Cadence, ProgressionConstraints, SeededRandom,
+        },
+    };
+
+    #[test]
+    fn seeded_random_is_reproducible() {
+        let mut first = SeededRandom::new(42);
+        let mut second = SeededRandom::new(42);
+        for _ in 0..10 {
+            assert_eq!(first.next_u64(), second.next_u64());
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_sequence() {
+        let constraints = ProgressionConstraints {
+            density: 8,
+            seed: 1234,
+            ..ProgressionConstraints::default()
+        };
+        assert_eq!(
+            generate_sequence(&constraints, 16),
+            generate_sequence(&constraints, 16)
+        );
+    }
+
+    #[test]
+    fn different_seeds_give_different_progressions() {
+        let progressions: Vec<Vec<ChordDegree>> = (0..8)
+            .map(|seed| {
+                random_progression(&ProgressionConstraints {
+                    density: 8,
+                    seed,
+                    ..ProgressionConstraints::default()
+                })
+                .unwrap()
+            })
+            .collect();
+        assert!(progressions.iter().any(|p| p != &progressions[0]));
+    }
+
+    #[test]
+    fn progression_starts_and_ends_with_cadence() {
+        for seed in 0..20 {
+            let progression = random_progression(&ProgressionConstraints {
+                density: 6,
+                cadence: Cadence::Plagal,
+                seed,
+                ..ProgressionConstraints::default()
+            })
+            .unwrap();
+            assert_eq!(progression.len(), 6);
+            assert_eq!(progression[0], ChordDegree::I);
+            assert_eq!(progression[4..], [ChordDegree::IV, ChordDegree::I]);
+        }
+    }
+
+    #[test]
+    fn progression_only_uses_allowed_degrees() {
+        let allowed_degrees = vec![ChordDegree::I, ChordDegree::IV, ChordDegree::V];
+        for seed in 0..20 {
+            let progression = random_progression(&ProgressionConstraints {
+                allowed_degrees: allowed_degrees.clone(),
+                density: 8,
+                seed,
+                ..ProgressionConstraints::default()
+            })
+            .unwrap();
+            assert!(progression.iter().all(|d| allowed_degrees.contains(d)));
+        }
+    }
+
+    #[test]
+    fn progression_without_tonic_constraints() {
+        let progression = random_progression(&ProgressionConstraints {
+            start_on_tonic: false,
+            end_on_tonic: false,
+            allowed_degrees: vec![ChordDegree::II],
+            cadence: Cadence::None,
+            density: 3,
+            seed: 7,
+        })
+        .unwrap();
+        assert_eq!(progression, vec![ChordDegree::II; 3]);
+    }
+
+    #[test]
+    fn half_cadence_cannot_end_on_tonic() {
+        assert!(random_progression(&ProgressionConstraints {
+            cadence: Cadence::Half,
+            ..ProgressionConstraints::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn cadence_chord_must_be_allowed() {
+        assert!(random_progression(&ProgressionConstraints {
+            allowed_degrees: vec![ChordDegree::I, ChordDegree::IV],
+            cadence: Cadence::Authentic,
+            ..ProgressionConstraints::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn density_must_fit_required_chords() {
+        assert!(random_progression(&ProgressionConstraints {
+            density: 2,
+            ..ProgressionConstraints::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn generated_sequence_spreads_chords_through_lane() {
+        let sequence = generate_sequence(
+            &ProgressionConstraints {
+                density: 4,
+                ..ProgressionConstraints::default()
+            },
+            16,
+        )
+        .unwrap();
+        assert_eq!(sequence[Tatum::try_from(0).unwrap()], Some(ChordDegree::I));
+        assert_eq!(sequence[Tatum::try_from(8).unwrap()], Some(ChordDegree::V));
+        assert_eq!(sequence[Tatum::try_from(12).unwrap()], Some(ChordDegree::I));
+        assert_eq!(sequence.iter().filter(|c| c.is_some()).count(), 4);
+    }
+
+    #[test]
+    fn generated_sequence_needs_enough_steps() {
+        assert!(generate_sequence(
+            &ProgressionConstraints {
+                density: 8,
+                ..ProgressionConstraints::default()
+            },
+            7,
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt;
+
+use crate::{
+    data_types::tatum::{Tatum, MAX_TATUMS_PER_LANE},
+    model::{
+        chord_sequence::ChordSequence,
+        clip::{Clip, ClipStep},
+        step_condition::StepCondition,
+        step_timing::StepTiming,
+    },
+    music_theory::chords::relative,
+};
+
+/// Bulk changes to a whole lane, each made by one of the functions below.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) enum Transform {
+    RotateLeft,
+    RotateRight,
+    Reverse,
+    Retrograde,
+    DegreeDown,
+    DegreeUp,
+    DoubleTime,
+    HalfTime,
+    SwapRelatives,
+}
+
+impl Transform {
+    pub(crate) const ALL: [Transform; 9] = [
+        Transform::RotateLeft,
+        Transform::RotateRight,
+        Transform::Reverse,
+        Transform::Retrograde,
+        Transform::DegreeDown,
+        Transform::DegreeUp,
+        Transform::DoubleTime,
+        Transform::HalfTime,
+        Transform::SwapRelatives,
+    ];
+
+    pub(crate) fn apply(&self, sequence: &ChordSequence) -> ChordSequence {
+        match self {
+            Transform::RotateLeft => rotate(sequence, -1),
+            Transform::RotateRight => rotate(sequence, 1),
+            Transform::Reverse => reverse(sequence),
+            Transform::Retrograde => retrograde(sequence),
+            Transform::DegreeDown => shift_degrees(sequence, -1),
+            Transform::DegreeUp => shift_degrees(sequence, 1),
+            Transform::DoubleTime => double_time(sequence),
+            Transform::HalfTime => half_time(sequence),
+            Transform::SwapRelatives => swap_relatives(sequence),
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transform::RotateLeft => write!(f, "rotate left"),
+            Transform::RotateRight => write!(f, "rotate right"),
+            Transform::Reverse => write!(f, "reverse"),
+            Transform::Retrograde => write!(f, "retrograde"),
+            Transform::DegreeDown => write!(f, "degree down"),
+            Transform::DegreeUp => write!(f, "degree up"),
+            Transform::DoubleTime => write!(f, "double time"),
+            Transform::HalfTime => write!(f, "half time"),
+            Transform::SwapRelatives => write!(f, "relatives"),
+        }
+    }
+}
+
+fn steps_of(sequence: &ChordSequence) -> Vec<ClipStep> {
+    let last_step = Tatum::try_from(sequence.steps() - 1).unwrap();
+    sequence
+        .clip(Tatum::try_from(0).unwrap(), last_step)
+        .steps()
+        .to_vec()
+}
+
+/// Make a lane `length` steps long holding `steps`, cut short if there are more.
+fn sequence_of(steps: Vec<ClipStep>, length: usize) -> ChordSequence {
+    let mut sequence = ChordSequence::with_steps(length).unwrap();
+    sequence.paste(Tatum::try_from(0).unwrap(), &Clip::new(steps));
+    sequence
+}
+
+const EMPTY_STEP: ClipStep = ClipStep {
+    chord: None,
+    condition: StepCondition::Always,
+    timing: StepTiming::ON_GRID,
+};
+
+/// Move every step `steps` later, or earlier when negative, wrapping round the loop.
+pub(crate) fn rotate(sequence: &ChordSequence, steps: i32) -> ChordSequence {
+    let mut rotated = steps_of(sequence);
+    let by = steps.rem_euclid(rotated.len() as i32) as usize;
+    rotated.rotate_right(by);
+    sequence_of(rotated, sequence.steps())
+}
+
+/// Play the grid backwards, so chords start where they used to end.
+pub(crate) fn reverse(sequence: &ChordSequence) -> ChordSequence {
+    let mut reversed = steps_of(sequence);
+    reversed.reverse();
+    sequence_of(reversed, sequence.steps())
+}
+
+/// Play the chords in the opposite order, each held for as long as before. Any empty steps
+/// before the first chord stay at the start.
+pub(crate) fn retrograde(sequence: &ChordSequence) -> ChordSequence {
+    let steps = steps_of(sequence);
+    let lead = steps.iter().take_while(|step| step.chord.is_none()).count();
+    let mut spans: Vec<(ClipStep, usize)> = vec![];
+    for step in &steps[lead..] {
+        match spans.last_mut() {
+            Some((_, length)) if step.chord.is_none() => *length += 1,
+            _ => spans.push((*step, 1)),
+        }
+    }
+    let mut retrograde = vec![EMPTY_STEP; lead];
+    for (step, length) in spans.into_iter().rev() {
+        retrograde.push(step);
+        retrograde.extend(std::iter::repeat_n(EMPTY_STEP, length - 1));
+    }
+    sequence_of(retrograde, sequence.steps())
+}
+
+/// Move every chord `degrees` up the scale, or down when negative.
+pub(crate) fn shift_degrees(sequence: &ChordSequence, degrees: i32) -> ChordSequence {
+    let shifted = steps_of(sequence)
+        .into_iter()
+        .map(|step| ClipStep {
+            chord: step.chord.map(|chord| chord.shifted(degrees)),
+            ..step
+        })
+        .collect();
+    sequence_of(shifted, sequence.steps())
+}
+
+/// Squeeze the lane into half as many steps, so it plays twice as fast. Each pair of steps
+/// becomes one, keeping the first chord of the pair.
+pub(crate) fn double_time(sequence: &ChordSequence) -> ChordSequence {
+    let compressed: Vec<ClipStep> = steps_of(sequence)
+        .chunks(2)
+        .map(|pair| {
+            *pair
+                .iter()
+                .find(|step| step.chord.is_some())
+                .unwrap_or(&pair[0])
+        })
+        .collect();
+    let length = compressed.len();
+    sequence_of(compressed, length)
+}
+
+/// Stretch the lane over twice as many steps, so it plays half as fast. Lanes have a maximum
+/// length, so chords that no longer fit are dropped.
+pub(crate) fn half_time(sequence: &ChordSequence) -> ChordSequence {
+    let stretched = steps_of(sequence)
+        .into_iter()
+        .flat_map(|step| [step, EMPTY_STEP])
+        .collect();
+    sequence_of(stretched, (sequence.steps() * 2).min(MAX_TATUMS_PER_LANE))
+}
+
+/// Swap every chord for its relative, which keeps its function but changes its colour.
+pub(crate) fn swap_relatives(sequence: &ChordSequence) -> ChordSequence {
+    let swapped = steps_of(sequence)
+        .into_iter()
+        .map(|step| ClipStep {
+            chord: step.chord.map(relative),
+            ..step
+        })
+        .collect();
+    sequence_of(swapped, sequence.steps())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::tatum::{Tatum, MAX_TATUMS_PER_LANE},
+        generators::transforms::{
+            double_time, half_time, retrograde, reverse, rotate, shift_degrees, swap_relatives,
+        },
+        model::{chord_sequence::ChordSequence, clip::Clip},
+    };
+
+    /// A lane as long as the text, written as it reads on the grid.
+    fn lane(text: &str) -> ChordSequence {
+        let clip: Clip = text.parse().unwrap();
+        let mut sequence = ChordSequence::with_steps(clip.steps().len()).unwrap();
+        sequence.paste(Tatum::try_from(0).unwrap(), &clip);
+        sequence
+    }
+
+    fn text(sequence: &ChordSequence) -> String {
+        let last_step = Tatum::try_from(sequence.steps() - 1).unwrap();
+        sequence
+            .clip(Tatum::try_from(0).unwrap(), last_step)
+            .to_string()
+    }
+
+    #[test]
+    fn rotate_wraps_both_ways() {
+        let sequence = lane("I . . V");
+        assert_eq!(text(&rotate(&sequence, 1)), "V I . .");
+        assert_eq!(text(&rotate(&sequence, -1)), ". . V I");
+        assert_eq!(rotate(&sequence, 4), sequence);
+    }
+
+    #[test]
+    fn reverse_flips_grid() {
+        assert_eq!(text(&reverse(&lane("I . . V?1st IV"))), "IV V?1st . . I");
+    }
+
+    #[test]
+    fn retrograde_keeps_chord_lengths() {
+        assert_eq!(
+            text(&retrograde(&lane(". I . . V . IV ."))),
+            ". IV . V . I . ."
+        );
+    }
+
+    #[test]
+    fn shift_degrees_moves_diatonically() {
+        assert_eq!(text(&shift_degrees(&lane("I . VII V"), 1)), "II . I VI");
+    }
+
+    #[test]
+    fn double_time_halves_lane() {
+        assert_eq!(text(&double_time(&lane("I . . V IV . VI"))), "I V IV VI");
+    }
+
+    #[test]
+    fn half_time_doubles_lane() {
+        assert_eq!(text(&half_time(&lane("I V"))), "I . V .");
+    }
+
+    #[test]
+    fn half_time_stops_at_maximum_length() {
+        let sequence = half_time(&ChordSequence::with_steps(MAX_TATUMS_PER_LANE).unwrap());
+        assert_eq!(sequence.steps(), MAX_TATUMS_PER_LANE);
+    }
+
+    #[test]
+    fn swap_relatives_swaps_tonic_and_relative() {
+        assert_eq!(text(&swap_relatives(&lane("I IV V VI"))), "VI II III I");
+    }
+}
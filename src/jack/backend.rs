@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use jack::Frames;
+
+use super::{jack_processor::OutputPort, timing_info::FramesPerSecond};
+
+/// Something that runs the engine, pulling a process cycle of MIDI from it at a time, with a
+/// transport the GUI can drive.
+pub(crate) trait Backend {
+    fn frames_per_second(&self) -> FramesPerSecond;
+
+    fn start_transport(&mut self) -> Result<(), &'static str>;
+
+    fn stop_transport(&mut self) -> Result<(), &'static str>;
+
+    /// Move the transport to `frame`.
+    fn locate(&mut self, frame: Frames) -> Result<(), &'static str>;
+
+    /// Run the engine until `end` frames since the backend started, handing what each output
+    /// sends to `send` with the frame it is sent at. Only backends with no clock of their own can
+    /// be rendered on demand.
+    fn render_to(
+        &mut self,
+        end: u64,
+        send: &mut dyn FnMut(u64, OutputPort, &[u8]),
+    ) -> Result<(), &'static str>;
+
+    /// Bring the engine up to `elapsed` since the backend started, for backends with no clock
+    /// of their own. A sound server runs the engine itself, so by default this does nothing.
+    fn catch_up(&mut self, _elapsed: Duration) {}
+
+    /// The JACK client behind the backend, for what only JACK does, such as timebase master.
+    fn jack_client(&self) -> Option<&jack::Client> {
+        None
+    }
+}
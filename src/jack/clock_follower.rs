@@ -0,0 +1,271 @@
+use jack::Frames;
+
+use crate::data_types::musical_position::MusicalPosition;
+
+use super::midi_clock::{PULSES_PER_TATUM, TICKS_PER_PULSE};
+
+/// How far the followed pulse length moves towards each one measured. Small enough to ride
+/// out the jitter of MIDI interfaces, large enough to follow a tempo knob being turned.
+const SMOOTHING: f64 = 0.1;
+
+/// Pulses this much longer or shorter than the followed length are a new tempo rather than
+/// jitter, so are taken straight away.
+const TEMPO_JUMP: f64 = 0.5;
+
+/// Where MIDI clock has taken the song over a process cycle.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) struct ClockCycle {
+    pub rolling: bool,
+    /// Song position at the start of the cycle
+    pub start: MusicalPosition,
+    /// Song position at the end of the cycle, which is the start while stopped
+    pub end: MusicalPosition,
+    /// Started, or continued from a new song position, since the last cycle
+    pub moved: bool,
+}
+
+/// Follows MIDI beat clock from other gear. Between pulses the position moves on at the
+/// measured tempo, smoothed so jitter in when pulses arrive does not make playback wobble, but
+/// never past the next pulse, so it holds when the clock stops or slows.
+#[derive(Debug, Default)]
+pub(crate) struct ClockFollower {
+    /// Frames since the follower was made, at the start of the current cycle
+    now: u64,
+    /// Started or continued, waiting for the first pulse to set off
+    armed: bool,
+    /// Pulses are moving the song along
+    running: bool,
+    /// Pulse the next clock marks, counted from the start of the song
+    next_pulse: u64,
+    /// Pulse and frame of the last clock while running
+    last_pulse: Option<(u64, u64)>,
+    /// Frame of the last clock, whether running or not, for measuring the tempo
+    last_clock: Option<u64>,
+    /// Smoothed frames between pulses
+    pulse_length: Option<f64>,
+    /// Song position in pulses reached by the end of the last cycle
+    position: f64,
+    moved: bool,
+}
+
+impl ClockFollower {
+    /// Take in a MIDI message arriving `time` frames into the current cycle. Anything other than
+    /// clock, transport and song position messages is ignored.
+    pub fn receive(&mut self, time: Frames, bytes: &[u8]) {
+        let frame = self.now + time as u64;
+        match bytes {
+            [0xF8, ..] => self.clock(frame),
+            [0xFA, ..] => {
+                self.next_pulse = 0;
+                self.arm();
+            }
+            [0xFB, ..] => self.arm(),
+            [0xFC, ..] => {
+                self.running = false;
+                self.armed = false;
+                self.last_pulse = None;
+            }
+            // Only followed while stopped, as the MIDI spec asks
+            [0xF2, low, high, ..] if !self.running => {
+                let sixteenths = (*low as u64 & 0x7F) | (*high as u64 & 0x7F) << 7;
+                self.next_pulse = sixteenths * PULSES_PER_TATUM;
+                self.position = self.next_pulse as f64;
+            }
+            _ => {}
+        }
+    }
+
+    fn arm(&mut self) {
+        self.armed = true;
+        self.running = false;
+        self.last_pulse = None;
+        self.position = self.next_pulse as f64;
+    }
+
+    fn clock(&mut self, frame: u64) {
+        if let Some(last_clock) = self.last_clock {
+            let measured = frame.saturating_sub(last_clock) as f64;
+            self.pulse_length = Some(match self.pulse_length {
+                Some(length) if (measured - length).abs() <= length * TEMPO_JUMP => {
+                    length + (measured - length) * SMOOTHING
+                }
+                _ => measured,
+            });
+        }
+        self.last_clock = Some(frame);
+        if !(self.armed || self.running) {
+            return;
+        }
+        if self.armed {
+            self.armed = false;
+            self.running = true;
+            self.moved = true;
+        }
+        self.last_pulse = Some((self.next_pulse, frame));
+        self.next_pulse += 1;
+    }
+
+    /// Song position in pulses at `frame`, which is after the last pulse.
+    fn position_at(&self, frame: u64) -> f64 {
+        let Some((pulse, pulse_frame)) = self.last_pulse else {
+            return self.position;
+        };
+        let fraction = match self.pulse_length {
+            Some(length) if length > 0.0 => {
+                (frame.saturating_sub(pulse_frame) as f64 / length).min(1.0)
+            }
+            _ => 0.0,
+        };
+        pulse as f64 + fraction
+    }
+
+    /// Move on to the end of a process cycle `n_frames` long, once everything arriving in it
+    /// has been received.
+    pub fn advance(&mut self, n_frames: Frames) -> ClockCycle {
+        self.now += n_frames as u64;
+        let moved = std::mem::take(&mut self.moved);
+        let start = match (moved, self.last_pulse) {
+            // Set off from the first pulse, wherever in the cycle it came
+            (true, Some((pulse, _))) => pulse as f64,
+            _ => self.position,
+        };
+        let end = if self.running {
+            self.position_at(self.now).max(start)
+        } else {
+            start
+        };
+        self.position = end;
+        ClockCycle {
+            rolling: self.running,
+            start: position_of_pulses(start),
+            end: position_of_pulses(end),
+            moved,
+        }
+    }
+}
+
+fn position_of_pulses(pulses: f64) -> MusicalPosition {
+    MusicalPosition::from_ticks((pulses * TICKS_PER_PULSE as f64).round() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use jack::Frames;
+
+    use crate::{
+        data_types::musical_position::MusicalPosition,
+        jack::clock_follower::{ClockCycle, ClockFollower},
+    };
+
+    const CLOCK: [u8; 1] = [0xF8];
+    const START: [u8; 1] = [0xFA];
+    const CONTINUE: [u8; 1] = [0xFB];
+    const STOP: [u8; 1] = [0xFC];
+
+    /// Quarter notes a minute the follower has measured, at 2400 frames a second.
+    fn bpm(follower: &ClockFollower) -> f64 {
+        2400.0 * 60.0 / (follower.pulse_length.unwrap() * 24.0)
+    }
+
+    /// Cycles `n_frames` long with a pulse every `pulse_length` frames, with `jitter` frames
+    /// added to each pulse in turn.
+    fn clocked_cycles(
+        follower: &mut ClockFollower,
+        cycles: u32,
+        n_frames: Frames,
+        pulse_length: Frames,
+        jitter: &[i32],
+    ) -> Vec<ClockCycle> {
+        let mut pulse = 0;
+        let mut result = vec![];
+        for cycle in 0..cycles {
+            let cycle_end = (cycle + 1) * n_frames;
+            loop {
+                let offset = jitter
+                    .get(pulse % jitter.len().max(1))
+                    .copied()
+                    .unwrap_or(0);
+                let frame = (pulse as u32 * pulse_length).saturating_add_signed(offset);
+                if frame >= cycle_end {
+                    break;
+                }
+                follower.receive(frame.saturating_sub(cycle * n_frames), &CLOCK);
+                pulse += 1;
+            }
+            result.push(follower.advance(n_frames));
+        }
+        result
+    }
+
+    #[test]
+    fn clock_before_start_only_sets_tempo() {
+        let mut follower = ClockFollower::default();
+        let cycles = clocked_cycles(&mut follower, 10, 256, 100, &[]);
+        assert!(cycles.iter().all(|cycle| !cycle.rolling));
+        assert_eq!(cycles[9].end, MusicalPosition::from_ticks(0));
+        assert_eq!(bpm(&follower), 60.0);
+    }
+
+    #[test]
+    fn follows_steady_clock() {
+        let mut follower = ClockFollower::default();
+        follower.receive(0, &START);
+        let cycles = clocked_cycles(&mut follower, 12, 100, 100, &[]);
+        assert!(cycles[0].rolling && cycles[0].moved);
+        assert!(!cycles[1].moved);
+        // Forty ticks a pulse, after the first cycle measures the tempo
+        for window in cycles[1..].windows(2) {
+            assert_eq!(window[0].end, window[1].start);
+            assert_eq!(window[1].end.ticks() - window[1].start.ticks(), 40);
+        }
+    }
+
+    #[test]
+    fn smooths_jittery_clock() {
+        let mut follower = ClockFollower::default();
+        follower.receive(0, &START);
+        clocked_cycles(&mut follower, 200, 128, 100, &[0, 7, -5, 3, -6, 1]);
+        let bpm = bpm(&follower);
+        assert!((bpm - 60.0).abs() < 1.0, "{bpm}");
+    }
+
+    #[test]
+    fn takes_new_tempo_straight_away() {
+        let mut follower = ClockFollower::default();
+        clocked_cycles(&mut follower, 10, 256, 100, &[]);
+        // The last pulse was at 2500, so one more on time, then one far too soon
+        follower.receive(40, &CLOCK);
+        follower.receive(80, &CLOCK);
+        assert_eq!(bpm(&follower), 150.0);
+    }
+
+    #[test]
+    fn holds_at_next_pulse_when_clock_stops_arriving() {
+        let mut follower = ClockFollower::default();
+        follower.receive(0, &START);
+        clocked_cycles(&mut follower, 3, 100, 100, &[]);
+        let held = follower.advance(1000);
+        assert!(held.rolling);
+        assert_eq!(held.end, MusicalPosition::from_ticks(3 * 40));
+        assert_eq!(follower.advance(1000).end, held.end);
+    }
+
+    #[test]
+    fn stops_and_continues_from_song_position() {
+        let mut follower = ClockFollower::default();
+        follower.receive(0, &START);
+        clocked_cycles(&mut follower, 3, 100, 100, &[]);
+        follower.receive(0, &STOP);
+        assert!(!follower.advance(100).rolling);
+        // Bar two, which is sixteen sixteenths in
+        follower.receive(0, &[0xF2, 16, 0]);
+        follower.receive(1, &CONTINUE);
+        let waiting = follower.advance(100);
+        assert!(!waiting.rolling);
+        assert_eq!(waiting.start, MusicalPosition::from_tatums(16));
+        follower.receive(50, &CLOCK);
+        let resumed = follower.advance(100);
+        assert!(resumed.rolling && resumed.moved);
+        assert_eq!(resumed.start, MusicalPosition::from_tatums(16));
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    marker::PhantomData,
+    ptr,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Arc,
+    },
+};
+
+/// The two slots passed between the threads. Each holds a box or is null, and whichever side
+/// swaps a box out owns it.
+struct Slots<T> {
+    /// Published by the GUI, waiting for the engine to pick it up
+    pending: AtomicPtr<T>,
+    /// Finished with by the engine, waiting for the GUI to drop it
+    retired: AtomicPtr<T>,
+    /// Values move between threads, so only share them if `T` can be sent
+    _values: PhantomData<Box<T>>,
+}
+
+fn take<T>(slot: &AtomicPtr<T>) -> Option<Box<T>> {
+    let pointer = slot.swap(ptr::null_mut(), Ordering::AcqRel);
+    // SAFETY: non-null pointers in a slot always come from `Box::into_raw`, and swapping
+    // the pointer out means no other thread can take it too
+    (!pointer.is_null()).then(|| unsafe { Box::from_raw(pointer) })
+}
+
+impl<T> Drop for Slots<T> {
+    fn drop(&mut self) {
+        take(&self.pending);
+        take(&self.retired);
+    }
+}
+
+/// Sends values to a [`Subscriber`] on the audio thread. Every allocation and deallocation
+/// happens on this side, so the subscriber never has to.
+pub(crate) struct Publisher<T> {
+    slots: Arc<Slots<T>>,
+}
+
+/// Receives the latest value from a [`Publisher`] without locking or allocating, so it can
+/// be used from the JACK process callback.
+pub(crate) struct Subscriber<T> {
+    slots: Arc<Slots<T>>,
+    current: Box<T>,
+    /// The value replaced last, kept until the retired slot is free to hand it back
+    previous: Option<Box<T>>,
+}
+
+/// A handoff starting with `initial`, for one thread to publish and another to read.
+pub(crate) fn handoff<T>(initial: T) -> (Publisher<T>, Subscriber<T>) {
+    let slots = Arc::new(Slots {
+        pending: AtomicPtr::new(ptr::null_mut()),
+        retired: AtomicPtr::new(ptr::null_mut()),
+        _values: PhantomData,
+    });
+    (
+        Publisher {
+            slots: slots.clone(),
+        },
+        Subscriber {
+            slots,
+            current: Box::new(initial),
+            previous: None,
+        },
+    )
+}
+
+impl<T> Publisher<T> {
+    /// Make `value` the next one the subscriber sees, replacing anything it has not picked
+    /// up yet.
+    pub fn publish(&mut self, value: T) {
+        self.reclaim();
+        let pointer = Box::into_raw(Box::new(value));
+        let unread = self.slots.pending.swap(pointer, Ordering::AcqRel);
+        if !unread.is_null() {
+            // SAFETY: swapped out of the slot, so this side owns it
+            drop(unsafe { Box::from_raw(unread) });
+        }
+    }
+
+    /// Drop a value the subscriber has finished with.
+    pub fn reclaim(&mut self) {
+        take(&self.slots.retired);
+    }
+}
+
+impl<T> Subscriber<T> {
+    pub fn current(&self) -> &T {
+        &self.current
+    }
+
+    /// Move on to the latest published value, returning whether there was one. Replaced values
+    /// go back to the publisher to drop, one at a time, so a new value waits until the
+    /// publisher has reclaimed the last.
+    pub fn receive(&mut self) -> bool {
+        if let Some(previous) = self.previous.take() {
+            let pointer = Box::into_raw(previous);
+            let handed_back = self.slots.retired.compare_exchange(
+                ptr::null_mut(),
+                pointer,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            if handed_back.is_err() {
+                // SAFETY: the exchange failed, so the pointer was never shared
+                self.previous = Some(unsafe { Box::from_raw(pointer) });
+                return false;
+            }
+        }
+        let Some(latest) = take(&self.slots.pending) else {
+            return false;
+        };
+        self.previous = Some(std::mem::replace(&mut self.current, latest));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jack::handoff::handoff;
+
+    #[test]
+    fn subscriber_sees_latest_value() {
+        let (mut publisher, mut subscriber) = handoff(1);
+        assert!(!subscriber.receive());
+        publisher.publish(2);
+        publisher.publish(3);
+        assert!(subscriber.receive());
+        assert_eq!(*subscriber.current(), 3);
+        assert!(!subscriber.receive());
+        assert_eq!(*subscriber.current(), 3);
+    }
+
+    #[test]
+    fn new_value_waits_until_old_one_is_reclaimed() {
+        let (mut publisher, mut subscriber) = handoff(1);
+        publisher.publish(2);
+        assert!(subscriber.receive());
+        // Hands back 1, then takes 3
+        publisher.publish(3);
+        assert!(subscriber.receive());
+        assert_eq!(*subscriber.current(), 3);
+        // Publish without reclaiming, so 2 has to wait behind 1 in the retired slot
+        publisher.slots.pending.store(
+            Box::into_raw(Box::new(4)),
+            std::sync::atomic::Ordering::Release,
+        );
+        assert!(!subscriber.receive());
+        assert_eq!(*subscriber.current(), 3);
+        publisher.reclaim();
+        assert!(subscriber.receive());
+        assert_eq!(*subscriber.current(), 4);
+    }
+}
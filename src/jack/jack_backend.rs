@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use jack::{AsyncClient, MidiIn, MidiOut, Port, ProcessHandler};
+
+use crate::model::playhead::Playhead;
+
+use super::{
+    backend::Backend,
+    handoff::Subscriber,
+    jack_processor::{Engine, InputPort, OutputPort},
+    key_queue::KeySender,
+    schedule::Schedule,
+    timing_info::{FramesPerSecond, TimingInfo},
+    transport::TransportCycle,
+};
+
+struct JackProcessor {
+    engine: Engine,
+    chord_port: Port<MidiOut>,
+    melody_port: Port<MidiOut>,
+    clock_port: Port<MidiOut>,
+    clock_in_port: Port<MidiIn>,
+    trigger_port: Port<MidiIn>,
+    keyboard_port: Port<MidiIn>,
+}
+
+impl ProcessHandler for JackProcessor {
+    fn process(
+        &mut self,
+        client: &jack::Client,
+        process_scope: &jack::ProcessScope,
+    ) -> jack::Control {
+        let transport = TransportCycle::query(client);
+        for (port, input) in [
+            (&self.clock_in_port, InputPort::ClockIn),
+            (&self.trigger_port, InputPort::Triggers),
+            (&self.keyboard_port, InputPort::Keyboard),
+        ] {
+            for event in port.iter(process_scope) {
+                self.engine.receive(input, event.time, event.bytes);
+            }
+        }
+
+        let mut chord_writer = self.chord_port.writer(process_scope);
+        let mut melody_writer = self.melody_port.writer(process_scope);
+        let mut clock_writer = self.clock_port.writer(process_scope);
+        self.engine
+            .process(&transport, process_scope.n_frames(), |port, time, bytes| {
+                assert!(time < process_scope.n_frames());
+                let writer = match port {
+                    OutputPort::Chords => &mut chord_writer,
+                    OutputPort::Melody => &mut melody_writer,
+                    OutputPort::Clock => &mut clock_writer,
+                };
+                writer.write(&jack::RawMidi { time, bytes }).unwrap();
+            });
+
+        jack::Control::Continue
+    }
+}
+
+/// Runs the engine in a JACK client, on its ports and following its transport.
+pub(crate) struct JackBackend {
+    client: AsyncClient<(), JackProcessor>,
+}
+
+impl JackBackend {
+    /// Start a client on the running JACK server, which fails if there isn't one.
+    pub fn activate(
+        schedule: Subscriber<Schedule>,
+        playhead: Arc<Playhead>,
+        keys: KeySender,
+    ) -> Result<JackBackend, &'static str> {
+        let (client, _status) = jack::Client::new("tubular", jack::ClientOptions::NO_START_SERVER)
+            .map_err(|_| "Could not connect to a JACK server")?;
+        let port_error = |_| "Could not register JACK ports";
+        let chord_port = client
+            .register_port("chords", jack::MidiOut)
+            .map_err(port_error)?;
+        let melody_port = client
+            .register_port("melody", jack::MidiOut)
+            .map_err(port_error)?;
+        let clock_port = client
+            .register_port("clock", jack::MidiOut)
+            .map_err(port_error)?;
+        let clock_in_port = client
+            .register_port("clock in", jack::MidiIn)
+            .map_err(port_error)?;
+        let trigger_port = client
+            .register_port("triggers", jack::MidiIn)
+            .map_err(port_error)?;
+        let keyboard_port = client
+            .register_port("keyboard", jack::MidiIn)
+            .map_err(port_error)?;
+
+        let jack_timing_info = TimingInfo {
+            frames_per_second: FramesPerSecond::from(client.sample_rate()),
+        };
+
+        let client_handler = JackProcessor {
+            engine: Engine::new(schedule, playhead, keys, jack_timing_info),
+            chord_port,
+            melody_port,
+            clock_port,
+            clock_in_port,
+            trigger_port,
+            keyboard_port,
+        };
+
+        let client = client
+            .activate_async((), client_handler)
+            .map_err(|_| "Could not start the JACK client")?;
+        Ok(JackBackend { client })
+    }
+}
+
+impl Backend for JackBackend {
+    fn frames_per_second(&self) -> FramesPerSecond {
+        FramesPerSecond::from(self.client.as_client().sample_rate())
+    }
+
+    fn start_transport(&mut self) -> Result<(), &'static str> {
+        self.client
+            .as_client()
+            .transport()
+            .start()
+            .map_err(|_| "Could not start the JACK transport")
+    }
+
+    fn stop_transport(&mut self) -> Result<(), &'static str> {
+        self.client
+            .as_client()
+            .transport()
+            .stop()
+            .map_err(|_| "Could not stop the JACK transport")
+    }
+
+    fn locate(&mut self, frame: jack::Frames) -> Result<(), &'static str> {
+        self.client
+            .as_client()
+            .transport()
+            .locate(frame)
+            .map_err(|_| "Could not move the JACK transport")
+    }
+
+    fn render_to(
+        &mut self,
+        _end: u64,
+        _send: &mut dyn FnMut(u64, OutputPort, &[u8]),
+    ) -> Result<(), &'static str> {
+        Err("JACK runs the engine in its own process cycle, and sends to its own ports")
+    }
+
+    fn jack_client(&self) -> Option<&jack::Client> {
+        Some(self.client.as_client())
+    }
+}
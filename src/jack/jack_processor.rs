@@ -1,657 +1,2010 @@
-use std::{
-    collections::HashSet,
-    sync::{Arc, RwLock},
-};
+use std::{ops::Range, sync::Arc};
 
-use jack::{AsyncClient, Frames, MidiOut, Port, ProcessHandler};
-use midi_msg::MidiMsg;
+use jack::Frames;
 
 use crate::{
-    data_types::note::Note,
-    model::{project_state::ProjectState, project_time_info::ProjectTimeInfo},
+    data_types::{
+        chord_degree::ChordDegree, musical_position::MusicalPosition, note::Note,
+        velocity::Velocity,
+    },
+    model::{
+        output_settings::{OutputSettings, ProgramChange},
+        playhead::Playhead,
+        recording::RecordedKey,
+        sync_source::SyncSource,
+    },
+    music_theory::chords::chord_degreee_to_notes,
 };
 
 use super::{
-    sequence_translation::{self, chord_sequence_to_frame_offset, Event, FrameOffset, MidiEvent},
-    timing_info::{FramesPerSecond, TimingInfo},
+    clock_follower::ClockFollower,
+    handoff::Subscriber,
+    key_queue::KeySender,
+    loop_counter::LoopCounter,
+    midi_clock::MidiClock,
+    schedule::Schedule,
+    sequence_translation::{melody_events_in_pass, CompiledLane, MidiEvent},
+    timing_info::TimingInfo,
+    transport::{position_of_bbt, TransportCycle},
 };
 
-pub(crate) struct JackProcessor {
-    project_state: Arc<RwLock<ProjectState>>,
-    chord_port: Port<MidiOut>,
-    jack_timing_info: TimingInfo,
-    current_events: Vec<Event>,
+/// Most events sent on one port in a process cycle. Room for them is made up front, so the
+/// process callback never allocates, and any more are dropped.
+const MAX_EVENTS_PER_CYCLE: usize = 1024;
+
+/// Which input a message arrives on.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) enum InputPort {
+    /// MIDI clock from other gear, followed when it is the sync source
+    ClockIn,
+    /// Keys that play chords live
+    Triggers,
+    /// Keys that are recorded into the project
+    Keyboard,
 }
 
-impl JackProcessor {
-    pub(crate) fn activate_async(
-        project_state: Arc<RwLock<ProjectState>>,
-    ) -> AsyncClient<(), JackProcessor> {
-        let (client, _status) =
-            jack::Client::new("tubular", jack::ClientOptions::NO_START_SERVER).unwrap();
-        let chord_port = client.register_port("chords", jack::MidiOut).unwrap();
+/// Which output a message is sent on.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) enum OutputPort {
+    Chords,
+    Melody,
+    Clock,
+}
 
-        let jack_timing_info = TimingInfo {
-            frames_per_second: FramesPerSecond::from(client.sample_rate()),
-        };
+/// The engine as a backend drives it. Messages from the inputs go in, a process cycle is run,
+/// and messages for the outputs come out, all without allocating or locking, so it can run
+/// on a realtime thread or against a virtual clock.
+pub(crate) struct Engine {
+    sequencer: Sequencer,
+    playhead: Arc<Playhead>,
+    keys: KeySender,
+    /// Keys from the keyboard input, kept until the cycle they arrived in places them
+    keyboard_events: EventBuffer,
+}
 
-        let starting_events = chord_sequence_to_frame_offset(
-            &project_state.read().unwrap().chord_sequence,
-            &jack_timing_info,
-            &project_state.read().unwrap().time,
-        );
+impl Engine {
+    pub fn new(
+        schedule: Subscriber<Schedule>,
+        playhead: Arc<Playhead>,
+        keys: KeySender,
+        jack_timing_info: TimingInfo,
+    ) -> Engine {
+        Engine {
+            sequencer: Sequencer::new(schedule, jack_timing_info),
+            playhead,
+            keys,
+            keyboard_events: EventBuffer::default(),
+        }
+    }
 
-        let client_handler = JackProcessor {
-            project_state,
-            chord_port,
-            jack_timing_info,
-            current_events: starting_events,
+    /// Take in a message arriving on `port` `time` frames into the next process cycle.
+    pub fn receive(&mut self, port: InputPort, time: Frames, bytes: &[u8]) {
+        match port {
+            InputPort::ClockIn => self.sequencer.clock_follower.receive(time, bytes),
+            InputPort::Triggers => self.sequencer.trigger(time, bytes),
+            InputPort::Keyboard => {
+                if let Some((note, velocity)) = parse_key(bytes) {
+                    let event = match velocity {
+                        Some(velocity) => MidiEvent::NoteOn(note, velocity),
+                        None => MidiEvent::NoteOff(note),
+                    };
+                    self.keyboard_events.push(time, event);
+                }
+            }
+        }
+    }
+
+    /// Run a process cycle `n_frames` long, handing what each output sends to `send`, in
+    /// order of time for each output.
+    pub fn process(
+        &mut self,
+        transport: &TransportCycle,
+        n_frames: Frames,
+        mut send: impl FnMut(OutputPort, Frames, &[u8]),
+    ) {
+        let song_frame = self.sequencer.cycle(transport, n_frames);
+        // Clock messages have no channel, so any settings do
+        let clock_settings = OutputSettings::default();
+        for (port, events, settings) in [
+            (
+                OutputPort::Chords,
+                &self.sequencer.chords.events,
+                &self.sequencer.chords.settings,
+            ),
+            (
+                OutputPort::Melody,
+                &self.sequencer.melody.events,
+                &self.sequencer.melody.settings,
+            ),
+            (
+                OutputPort::Clock,
+                &self.sequencer.clock_events,
+                &clock_settings,
+            ),
+        ] {
+            for (time, event) in events.events() {
+                send(
+                    port,
+                    *time,
+                    translate_to_midi_message(settings, event).as_slice(),
+                );
+            }
+        }
+
+        for (time, event) in self.keyboard_events.events() {
+            if let Some(key) = self.sequencer.recorded_key(*time, event) {
+                self.keys.send(key);
+            }
+        }
+        self.keyboard_events.clear();
+
+        self.playhead
+            .set(self.sequencer.position_of_frame(song_frame));
+    }
+}
+
+/// The events for one port in a process cycle, timed from the start of the cycle. They are
+/// kept in order as they are added, with note-offs before note-ons at the same time so
+/// repeated notes retrigger.
+#[derive(Debug)]
+struct EventBuffer {
+    events: Vec<(Frames, MidiEvent)>,
+}
+
+impl Default for EventBuffer {
+    fn default() -> Self {
+        Self {
+            events: Vec::with_capacity(MAX_EVENTS_PER_CYCLE),
+        }
+    }
+}
+
+/// Where an event goes in a buffer: by time, then note-offs first.
+fn order((time, event): &(Frames, MidiEvent)) -> (Frames, bool) {
+    (*time, matches!(event, MidiEvent::NoteOn(..)))
+}
+
+impl EventBuffer {
+    fn push(&mut self, time: Frames, event: MidiEvent) {
+        if self.events.len() == self.events.capacity() {
+            return;
+        }
+        let new_event = (time, event);
+        let index = self
+            .events
+            .partition_point(|event| order(event) <= order(&new_event));
+        self.events.insert(index, new_event);
+    }
+
+    fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    fn retain(&mut self, keep: impl FnMut(&(Frames, MidiEvent)) -> bool) {
+        self.events.retain(keep);
+    }
+
+    fn events(&self) -> &[(Frames, MidiEvent)] {
+        &self.events
+    }
+
+    /// Rescale event times from a span `from` frames long to one `to` frames long.
+    fn stretch(&mut self, from: Frames, to: Frames) {
+        if from == 0 || from == to {
+            return;
+        }
+        for (time, _) in &mut self.events {
+            *time = (*time as u64 * to as u64 / from as u64) as Frames;
+        }
+    }
+}
+
+/// Notes being held, one bit for each MIDI note, so they can be tracked without allocating.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+struct NoteSet(u128);
+
+impl NoteSet {
+    fn bit(note: Note) -> u128 {
+        1u128.checked_shl(u8::from(note) as u32).unwrap_or(0)
+    }
+
+    fn insert(&mut self, note: Note) {
+        self.0 |= NoteSet::bit(note);
+    }
+
+    fn remove(&mut self, note: Note) {
+        self.0 &= !NoteSet::bit(note);
+    }
+
+    fn contains(self, note: Note) -> bool {
+        self.0 & NoteSet::bit(note) != 0
+    }
+
+    fn union(self, other: NoteSet) -> NoteSet {
+        NoteSet(self.0 | other.0)
+    }
+
+    fn difference(self, other: NoteSet) -> NoteSet {
+        NoteSet(self.0 & !other.0)
+    }
+
+    fn notes(self) -> impl Iterator<Item = Note> {
+        (0..128u8)
+            .filter(move |&note| self.0 & (1 << note) != 0)
+            .map(Note::from)
+    }
+
+    fn play(&mut self, event: &MidiEvent) {
+        match event {
+            MidiEvent::NoteOn(note, _) => self.insert(*note),
+            MidiEvent::NoteOff(note) => self.remove(*note),
+            _ => {}
+        }
+    }
+}
+
+/// Notes sounding on a port, one set for each MIDI channel. It is kept from the events
+/// actually sent, rather than the schedule they came from, so whatever the schedule changes to
+/// every note can be released.
+#[derive(Debug, Default)]
+struct NoteLedger {
+    held: [NoteSet; 16],
+}
+
+impl NoteLedger {
+    /// Record `event` being sent on `channel`, unless it releases a note that is not sounding,
+    /// returning whether it should be sent.
+    fn send(&mut self, channel: u8, event: &MidiEvent) -> bool {
+        let held = &mut self.held[channel as usize];
+        if let MidiEvent::NoteOff(note) = event {
+            if !held.contains(*note) {
+                return false;
+            }
+        }
+        held.play(event);
+        true
+    }
+
+    #[cfg(test)]
+    fn held(&self, channel: u8) -> NoteSet {
+        self.held[channel as usize]
+    }
+}
+
+/// One flag for each event in a buffer, so which to send can be worked out without allocating.
+#[derive(Default)]
+struct EventFlags([u64; MAX_EVENTS_PER_CYCLE / 64]);
+
+impl EventFlags {
+    fn set(&mut self, index: usize) {
+        self.0[index / 64] |= 1 << (index % 64);
+    }
+
+    fn get(&self, index: usize) -> bool {
+        self.0[index / 64] & 1 << (index % 64) != 0
+    }
+}
+
+/// Chords played from keys on the trigger input. Each key remembers the chord it started, so
+/// it releases that chord even if the layout changes while it is held.
+#[derive(Debug)]
+struct LiveChords {
+    keys: [Option<ChordDegree>; 128],
+}
+
+impl Default for LiveChords {
+    fn default() -> Self {
+        Self { keys: [None; 128] }
+    }
+}
+
+impl LiveChords {
+    fn held(&self) -> NoteSet {
+        let mut notes = NoteSet::default();
+        for chord in self.keys.iter().flatten() {
+            for note in chord_degreee_to_notes(chord) {
+                notes.insert(note);
+            }
+        }
+        notes
+    }
+
+    /// Hold `chord` down with `key`, returning its notes.
+    fn press(&mut self, key: Note, chord: ChordDegree) -> [Note; 3] {
+        self.keys[u8::from(key) as usize] = Some(chord);
+        chord_degreee_to_notes(&chord)
+    }
+
+    /// Let go of `key`, returning the notes no other key is holding.
+    fn release(&mut self, key: Note) -> NoteSet {
+        let Some(chord) = self.keys[u8::from(key) as usize].take() else {
+            return NoteSet::default();
         };
+        let mut released = NoteSet::default();
+        for note in chord_degreee_to_notes(&chord) {
+            released.insert(note);
+        }
+        released.difference(self.held())
+    }
+}
+
+/// What a port sends in the current process cycle, and what it has left sounding. Notes can be
+/// held by the schedule, by keys on the trigger input, or both, and only go off once neither
+/// is holding them.
+#[derive(Debug, Default)]
+struct Output {
+    /// Events from the schedule, then everything to send once settled
+    events: EventBuffer,
+    /// Events from keys on the trigger input, timed as they arrived
+    live_events: EventBuffer,
+    ledger: NoteLedger,
+    /// Notes the schedule is holding
+    sequenced: NoteSet,
+    /// Notes keys are holding
+    live: NoteSet,
+    /// Settings the events are sent with
+    settings: OutputSettings,
+    /// Last program asked for on the current channel
+    program_sent: Option<ProgramChange>,
+}
+
+impl Output {
+    /// Take up `settings` for the cycle, and ask for their program if it has changed or
+    /// playback is `starting`. Notes left sounding on the old channel would never be released,
+    /// so everything is let go there first, and the move waits for the next cycle.
+    fn configure(&mut self, settings: OutputSettings, starting: bool) {
+        if settings.channel != self.settings.channel {
+            let sounding = self.sequenced.union(self.live);
+            if sounding != NoteSet::default() {
+                self.events.clear();
+                self.live_events.clear();
+                for note in sounding.notes() {
+                    self.events.push(0, MidiEvent::NoteOff(note));
+                }
+                self.sequenced = NoteSet::default();
+                self.live = NoteSet::default();
+                return;
+            }
+            self.program_sent = None;
+        }
+        self.settings = settings;
+        if let Some(program) = settings.program {
+            if starting || self.program_sent != settings.program {
+                if let Some(bank) = program.bank() {
+                    for (controller, value) in [(0, bank >> 7), (32, bank & 0x7F)] {
+                        self.events.push(
+                            0,
+                            MidiEvent::ControlChange {
+                                controller,
+                                value: value as u8,
+                            },
+                        );
+                    }
+                }
+                self.events
+                    .push(0, MidiEvent::ProgramChange(program.program()));
+            }
+        }
+        self.program_sent = settings.program;
+    }
+
+    /// Release, at the start of the cycle, every note the schedule holds that is not in `keep`.
+    fn release_all_except(&mut self, keep: NoteSet) {
+        for note in self.sequenced.difference(keep).notes() {
+            self.events.push(0, MidiEvent::NoteOff(note));
+        }
+    }
+
+    /// Merge in the live events, dropping releases of notes the other side still holds and of
+    /// notes that are not sounding, and record the rest as sent.
+    fn settle(&mut self) {
+        let sequenced_events = self.events.events();
+        let live_events = self.live_events.events();
+        let mut send_sequenced = EventFlags::default();
+        let mut send_live = EventFlags::default();
+        let (mut next_sequenced, mut next_live) = (0, 0);
+        // Go through both in time order, so each release is judged by who holds the note then
+        loop {
+            let live_first = match (
+                sequenced_events.get(next_sequenced),
+                live_events.get(next_live),
+            ) {
+                (None, None) => break,
+                (Some(sequenced), Some(live)) => order(live) < order(sequenced),
+                (sequenced, _) => sequenced.is_none(),
+            };
+            if live_first {
+                let (_, event) = &live_events[next_live];
+                self.live.play(event);
+                if !matches!(event, MidiEvent::NoteOff(note) if self.sequenced.contains(*note)) {
+                    send_live.set(next_live);
+                }
+                next_live += 1;
+            } else {
+                let (_, event) = &sequenced_events[next_sequenced];
+                self.sequenced.play(event);
+                if !matches!(event, MidiEvent::NoteOff(note) if self.live.contains(*note)) {
+                    send_sequenced.set(next_sequenced);
+                }
+                next_sequenced += 1;
+            }
+        }
 
-        client.activate_async((), client_handler).unwrap()
+        let mut index = 0;
+        self.events.retain(|_| {
+            index += 1;
+            send_sequenced.get(index - 1)
+        });
+        for (index, (time, event)) in self.live_events.events.drain(..).enumerate() {
+            if send_live.get(index) {
+                self.events.push(time, event);
+            }
+        }
+        let ledger = &mut self.ledger;
+        let channel = self.settings.channel.into();
+        self.events.retain(|(_, event)| ledger.send(channel, event));
     }
 }
 
-fn frames_of_next_offset(
-    last_frame_time: Frames,
-    frames_through_bar: FrameOffset,
+/// MIDI bytes for an event, held inline so sending it needs no allocation.
+#[derive(PartialEq, Eq, Debug)]
+struct MidiBytes {
+    bytes: [u8; 3],
+    length: usize,
+}
+
+impl MidiBytes {
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+}
+
+fn translate_to_midi_message(settings: &OutputSettings, event: &MidiEvent) -> MidiBytes {
+    let channel = u8::from(settings.channel);
+    let (bytes, length) = match event {
+        MidiEvent::NoteOn(note, velocity) => {
+            ([0x90 | channel, (*note).into(), (*velocity).into()], 3)
+        }
+        MidiEvent::NoteOff(note) => (
+            [
+                0x80 | channel,
+                (*note).into(),
+                settings.note_off_velocity.into(),
+            ],
+            3,
+        ),
+        MidiEvent::ControlChange { controller, value } => {
+            ([0xB0 | channel, *controller, *value], 3)
+        }
+        MidiEvent::PitchBend(bend) => (
+            [
+                0xE0 | channel,
+                (bend & 0x7F) as u8,
+                (bend >> 7 & 0x7F) as u8,
+            ],
+            3,
+        ),
+        MidiEvent::ChannelPressure(pressure) => ([0xD0 | channel, *pressure, 0], 2),
+        MidiEvent::ProgramChange(program) => ([0xC0 | channel, *program, 0], 2),
+        MidiEvent::Clock => ([0xF8, 0, 0], 1),
+        MidiEvent::Start => ([0xFA, 0, 0], 1),
+        MidiEvent::Continue => ([0xFB, 0, 0], 1),
+        MidiEvent::Stop => ([0xFC, 0, 0], 1),
+        MidiEvent::SongPosition(sixteenths) => (
+            [
+                0xF2,
+                (sixteenths & 0x7F) as u8,
+                (sixteenths >> 7 & 0x7F) as u8,
+            ],
+            3,
+        ),
+    };
+    MidiBytes { bytes, length }
+}
+
+/// Every pass through a loop `steps` long that plays during `frames`. Loops last longer or
+/// shorter as the tempo changes, so each is looked up separately.
+fn passes_during(
+    frames: &Range<Frames>,
+    steps: usize,
+    schedule: &Schedule,
     jack_timing_info: &TimingInfo,
-    project_timing_info: &ProjectTimeInfo,
-) -> Frames {
-    let frames_per_bar = jack_timing_info.frames_per_bar(project_timing_info);
-    let frames_since_start_of_last_bar = frames_per_bar.frames_through_bar(&last_frame_time);
+    mut for_pass: impl FnMut(u64),
+) {
+    let mut span = jack_timing_info.loop_at_frame(&schedule.timeline, frames.start, steps);
+    while span.start_frame < frames.end {
+        for_pass(span.number);
+        span = jack_timing_info.loop_span(&schedule.timeline, steps, span.number + 1);
+    }
+}
 
-    let frames_til_next_bar = frames_per_bar - frames_since_start_of_last_bar;
-    let start_frame_of_next_bar = last_frame_time + frames_til_next_bar;
-    let start_frame_of_current_bar = last_frame_time - frames_since_start_of_last_bar;
-    let time_in_current_bar = start_frame_of_current_bar + frames_through_bar;
-    let time_in_next_bar = start_frame_of_next_bar + frames_through_bar;
-    if time_in_current_bar >= last_frame_time {
-        return time_in_current_bar;
+/// Add the events of `lanes` that fall in `frames`, each lane looping on its own length.
+fn schedule_lanes(
+    frames: Range<Frames>,
+    cycle_start: Frames,
+    lanes: &[CompiledLane],
+    schedule: &Schedule,
+    loop_counter: &LoopCounter,
+    jack_timing_info: &TimingInfo,
+    events: &mut EventBuffer,
+) {
+    for lane in lanes {
+        passes_during(
+            &frames,
+            lane.steps(),
+            schedule,
+            jack_timing_info,
+            |number| {
+                lane.events_in_pass(
+                    number,
+                    loop_counter,
+                    schedule.fill,
+                    jack_timing_info,
+                    &schedule.timeline,
+                    |frame, event| {
+                        if frames.contains(&frame) {
+                            events.push(frame - cycle_start, event);
+                        }
+                    },
+                )
+            },
+        );
     }
-    time_in_next_bar
 }
 
-fn is_upcoming_event(
-    event_time_through_bar: FrameOffset,
-    last_frame_time: Frames,
-    n_frames: Frames,
+/// Notes the lanes are holding at `frame`, which need releasing if the lanes stop there.
+fn notes_sounding_at(
+    frame: Frames,
+    lanes: &[CompiledLane],
+    schedule: &Schedule,
+    loop_counter: &LoopCounter,
     jack_timing_info: &TimingInfo,
-    project_timing_info: &ProjectTimeInfo,
-) -> bool {
-    let event_frame_time = frames_of_next_offset(
-        last_frame_time,
-        event_time_through_bar,
-        jack_timing_info,
-        project_timing_info,
-    );
-    assert!(event_frame_time >= last_frame_time);
-    event_frame_time - last_frame_time < n_frames
-}
-
-fn notes_on_at_point(sequence: &Vec<Event>, frames_through_bar: FrameOffset) -> HashSet<Note> {
-    let mut live_notes = HashSet::new();
-    for event in sequence
-        .iter()
-        .filter(|e| e.bar_offset_frames < frames_through_bar)
-    {
-        match &event.event {
-            sequence_translation::MidiEvent::NoteOn(note) => live_notes.insert(*note),
-            sequence_translation::MidiEvent::NoteOff(note) => live_notes.remove(note),
-        };
+) -> NoteSet {
+    let mut notes = NoteSet::default();
+    for lane in lanes {
+        let span = jack_timing_info.loop_at_frame(&schedule.timeline, frame, lane.steps());
+        let mut lane_notes = NoteSet::default();
+        // A step nudged late can still be sounding from the pass before
+        for number in span.number.saturating_sub(1)..=span.number {
+            lane.events_in_pass(
+                number,
+                loop_counter,
+                schedule.fill,
+                jack_timing_info,
+                &schedule.timeline,
+                |event_frame, event| {
+                    if event_frame < frame {
+                        lane_notes.play(&event);
+                    }
+                },
+            );
+        }
+        notes = notes.union(lane_notes);
     }
-    live_notes
+    notes
 }
 
-fn lingering_notes(
-    old_events: &Vec<Event>,
-    new_events: &Vec<Event>,
-    frames_through_bar: FrameOffset,
-) -> HashSet<Note> {
-    let old_notes_on = notes_on_at_point(old_events, frames_through_bar);
-    let new_notes_on = notes_on_at_point(new_events, frames_through_bar);
-    return old_notes_on.difference(&new_notes_on).cloned().collect();
+/// The lanes playing at `frame`, which are the old scene's until a queued switch.
+fn lanes_at<'a>(
+    frame: Frames,
+    schedule: &'a Schedule,
+    jack_timing_info: &TimingInfo,
+) -> &'a [CompiledLane] {
+    match &schedule.scene_change {
+        Some(change) if frame < jack_timing_info.nearest_frame(&schedule.timeline, change.at) => {
+            &change.previous_lanes
+        }
+        _ => &schedule.lanes,
+    }
 }
 
-fn ghost_notes(
-    old_events: &Vec<Event>,
-    new_events: &Vec<Event>,
-    frames_through_bar: FrameOffset,
-) -> HashSet<Note> {
-    let old_notes_on = notes_on_at_point(old_events, frames_through_bar);
-    let new_notes_on = notes_on_at_point(new_events, frames_through_bar);
-    return new_notes_on.difference(&old_notes_on).cloned().collect();
+/// Schedule the lanes for a process cycle, moving from the old scene to the new one at the
+/// boundary of a queued scene switch when it falls in the cycle.
+fn schedule_chords(
+    cycle_start: Frames,
+    n_frames: Frames,
+    schedule: &Schedule,
+    loop_counter: &LoopCounter,
+    jack_timing_info: &TimingInfo,
+    events: &mut EventBuffer,
+) {
+    let cycle = cycle_start..cycle_start + n_frames;
+    let lanes = |frames, lanes, events: &mut EventBuffer| {
+        schedule_lanes(
+            frames,
+            cycle_start,
+            lanes,
+            schedule,
+            loop_counter,
+            jack_timing_info,
+            events,
+        )
+    };
+    let Some(change) = &schedule.scene_change else {
+        return lanes(cycle, &schedule.lanes, events);
+    };
+    let switch_frame = jack_timing_info.nearest_frame(&schedule.timeline, change.at);
+    if !cycle.contains(&switch_frame) || switch_frame == cycle_start {
+        return lanes(
+            cycle,
+            lanes_at(cycle_start, schedule, jack_timing_info),
+            events,
+        );
+    }
+    lanes(cycle_start..switch_frame, &change.previous_lanes, events);
+    // The old scene's note-offs come in its later steps, which never play
+    let released = notes_sounding_at(
+        switch_frame,
+        &change.previous_lanes,
+        schedule,
+        loop_counter,
+        jack_timing_info,
+    );
+    for note in released.notes() {
+        events.push(switch_frame - cycle_start, MidiEvent::NoteOff(note));
+    }
+    lanes(switch_frame..cycle.end, &schedule.lanes, events);
 }
 
-fn translate_to_midi_message(event: &MidiEvent) -> MidiMsg {
-    match event {
-        sequence_translation::MidiEvent::NoteOn(note) => MidiMsg::ChannelVoice {
-            channel: midi_msg::Channel::Ch1,
-            msg: midi_msg::ChannelVoiceMsg::NoteOn {
-                note: (*note).into(),
-                velocity: 120,
-            },
-        },
-        sequence_translation::MidiEvent::NoteOff(note) => MidiMsg::ChannelVoice {
-            channel: midi_msg::Channel::Ch1,
-            msg: midi_msg::ChannelVoiceMsg::NoteOff {
-                note: (*note).into(),
-                velocity: 64,
-            },
+/// Notes the melody is holding at `frame`.
+fn melody_sounding_at(
+    frame: Frames,
+    schedule: &Schedule,
+    jack_timing_info: &TimingInfo,
+) -> NoteSet {
+    let span = jack_timing_info.loop_at_frame(&schedule.timeline, frame, schedule.melody.steps());
+    let mut notes = NoteSet::default();
+    // Notes are cut off at the end of a pass, so only this one can be holding any
+    melody_events_in_pass(
+        &schedule.melody,
+        span.number,
+        jack_timing_info,
+        &schedule.timeline,
+        |event_frame, event| {
+            if event_frame < frame {
+                notes.play(&event);
+            }
         },
-    }
+    );
+    notes
 }
 
-fn get_midi_events_for_next_n_frames(
-    last_frame_time: Frames,
+fn schedule_melody(
+    cycle_start: Frames,
     n_frames: Frames,
-    sequence: &Vec<Event>,
-    old_sequence: &Vec<Event>,
+    schedule: &Schedule,
     jack_timing_info: &TimingInfo,
-    project_timing_info: &ProjectTimeInfo,
-) -> Vec<(u32, MidiEvent)> {
-    let mut upcoming_events: Vec<(u32, MidiEvent)> = vec![];
-    let frames_through_bar = jack_timing_info
-        .frames_per_bar(project_timing_info)
-        .frames_through_bar(&last_frame_time);
-    let lingering_notes = lingering_notes(old_sequence, sequence, frames_through_bar);
-    let ghost_notes = ghost_notes(old_sequence, sequence, frames_through_bar);
-
-    let old_note_off_messages = lingering_notes
-        .iter()
-        .map(|note| MidiEvent::NoteOff(*note))
-        .map(|midi_msg| (0, midi_msg));
-    upcoming_events.extend(old_note_off_messages);
-
-    let upcoming_notes = sequence
-        .iter()
-        .filter(|&event| {
-            is_upcoming_event(
-                event.bar_offset_frames,
-                last_frame_time,
-                n_frames,
+    events: &mut EventBuffer,
+) {
+    let cycle = cycle_start..cycle_start + n_frames;
+    passes_during(
+        &cycle,
+        schedule.melody.steps(),
+        schedule,
+        jack_timing_info,
+        |number| {
+            melody_events_in_pass(
+                &schedule.melody,
+                number,
                 jack_timing_info,
-                project_timing_info,
+                &schedule.timeline,
+                |frame, event| {
+                    if cycle.contains(&frame) {
+                        events.push(frame - cycle_start, event);
+                    }
+                },
             )
+        },
+    );
+}
+
+/// The key a note-on or note-off message is for, with how hard it was pressed, or nothing when
+/// it was let go.
+fn parse_key(bytes: &[u8]) -> Option<(Note, Option<Velocity>)> {
+    match bytes {
+        [status, key, velocity, ..] if status & 0xF0 == 0x90 && *velocity > 0 => Some((
+            Note::from(key & 0x7F),
+            Velocity::try_from(velocity & 0x7F).ok(),
+        )),
+        // Note-on with no velocity is a release too
+        [status, key, _, ..] if status & 0xF0 == 0x80 || status & 0xF0 == 0x90 => {
+            Some((Note::from(key & 0x7F), None))
+        }
+        _ => None,
+    }
+}
+
+/// The stretch of the song the last process cycle covered, for placing input that arrived
+/// during it.
+#[derive(Clone, Copy, Debug, Default)]
+struct CycleSpan {
+    rolling: bool,
+    song_frame: Frames,
+    song_frames: Frames,
+    n_frames: Frames,
+}
+
+impl CycleSpan {
+    fn song_frame_at(&self, time: Frames) -> Frames {
+        if self.n_frames == 0 {
+            return self.song_frame;
+        }
+        self.song_frame + (time as u64 * self.song_frames as u64 / self.n_frames as u64) as Frames
+    }
+}
+
+/// Where playback has got to while the transport rolls.
+#[derive(Clone, Copy, Debug)]
+struct Playback {
+    /// Transport frame the next cycle starts at, unless the transport is moved
+    transport_frame: Frames,
+    /// Song frame the next cycle starts at
+    song_frame: Frames,
+    loop_counter: LoopCounter,
+}
+
+/// Works out what each port sends in a process cycle, apart from JACK itself so it can be
+/// driven a cycle at a time.
+struct Sequencer {
+    schedule: Subscriber<Schedule>,
+    jack_timing_info: TimingInfo,
+    /// Only while the transport rolls
+    playback: Option<Playback>,
+    chords: Output,
+    melody: Output,
+    clock: MidiClock,
+    /// Beat clock and transport messages, which hold no notes
+    clock_events: EventBuffer,
+    /// Incoming MIDI clock, for when it is followed instead of the JACK transport
+    clock_follower: ClockFollower,
+    live_chords: LiveChords,
+    span: CycleSpan,
+}
+
+impl Sequencer {
+    fn new(schedule: Subscriber<Schedule>, jack_timing_info: TimingInfo) -> Sequencer {
+        Sequencer {
+            schedule,
+            jack_timing_info,
+            playback: None,
+            chords: Output::default(),
+            melody: Output::default(),
+            clock: MidiClock::default(),
+            clock_events: EventBuffer::default(),
+            clock_follower: ClockFollower::default(),
+            live_chords: LiveChords::default(),
+            span: CycleSpan::default(),
+        }
+    }
+
+    /// A key on the keyboard input, arriving `time` frames into the last cycle, placed in the
+    /// song for recording.
+    fn recorded_key(&self, time: Frames, event: &MidiEvent) -> Option<RecordedKey> {
+        let (note, pressed) = match event {
+            MidiEvent::NoteOn(note, _) => (*note, true),
+            MidiEvent::NoteOff(note) => (*note, false),
+            _ => return None,
+        };
+        Some(RecordedKey {
+            note,
+            pressed,
+            rolling: self.span.rolling,
+            at: self.position_of_frame(self.span.song_frame_at(time)),
         })
-        .filter(|event| {
-            if let MidiEvent::NoteOff(note_off) = event.event {
-                return !ghost_notes.contains(&note_off);
+    }
+
+    /// Play or release the chord for a key on the trigger input, arriving `time` frames into
+    /// the cycle. Keys play whether or not the transport is rolling.
+    fn trigger(&mut self, time: Frames, bytes: &[u8]) {
+        let Some((key, velocity)) = parse_key(bytes) else {
+            return;
+        };
+        // A key pressed again without being let go retriggers its chord
+        for note in self.live_chords.release(key).notes() {
+            self.chords.live_events.push(time, MidiEvent::NoteOff(note));
+        }
+        let Some(velocity) = velocity else {
+            return;
+        };
+        let Some(chord) = self.schedule.current().trigger_layout.chord_degree(key) else {
+            return;
+        };
+        for note in self.live_chords.press(key, chord) {
+            self.chords
+                .live_events
+                .push(time, MidiEvent::NoteOn(note, velocity));
+        }
+    }
+
+    /// Song frame the transport is at, going by the timebase master's bar and beat when there
+    /// is one, as its tempo may not match the project's.
+    fn song_frame(&self, transport: &TransportCycle) -> Frames {
+        let schedule = self.schedule.current();
+        match &transport.bbt {
+            Some(bbt) => self.jack_timing_info.nearest_frame(
+                &schedule.timeline,
+                position_of_bbt(bbt, &schedule.time_signatures),
+            ),
+            None => transport.frame,
+        }
+    }
+
+    /// Playback carrying on from the last cycle, or starting afresh where the transport is
+    /// if it has just started rolling or been moved.
+    fn playback(&mut self, transport: &TransportCycle) -> Playback {
+        match self.playback {
+            Some(playback) if playback.transport_frame == transport.frame => playback,
+            _ => {
+                let song_frame = self.song_frame(transport);
+                Playback {
+                    transport_frame: transport.frame,
+                    song_frame,
+                    loop_counter: LoopCounter::starting_at(song_frame),
+                }
             }
-            true
-        })
-        .map(|event| {
-            let time = frames_of_next_offset(
-                last_frame_time,
-                event.bar_offset_frames,
+        }
+    }
+
+    /// Fill the ports' events for the process cycle `n_frames` long, returning the song frame
+    /// playback has reached by its end.
+    fn cycle(&mut self, transport: &TransportCycle, n_frames: Frames) -> Frames {
+        // Stopped until playback says otherwise
+        self.span = CycleSpan {
+            n_frames,
+            ..CycleSpan::default()
+        };
+        let was_playing = self.playback.is_some();
+        let song_frame = self.follow(transport, n_frames);
+        if !self.span.rolling {
+            self.span.song_frame = song_frame;
+        }
+        let starting = !was_playing && self.playback.is_some();
+        let schedule = self.schedule.current();
+        self.chords.configure(schedule.chord_output, starting);
+        self.melody.configure(schedule.melody_output, starting);
+        // Keys are played in real time, so go in once the schedule's events are fitted to it
+        self.chords.settle();
+        self.melody.settle();
+        song_frame
+    }
+
+    /// Fill the ports' events from the schedule, following the chosen sync source.
+    fn follow(&mut self, transport: &TransportCycle, n_frames: Frames) -> Frames {
+        self.chords.events.clear();
+        self.melody.events.clear();
+        self.clock_events.clear();
+        let received = self.schedule.receive();
+        // Kept up with whichever source is followed, so it is ready to switch to
+        let clock = self.clock_follower.advance(n_frames);
+
+        match self.schedule.current().sync_source {
+            SyncSource::JackTransport => {
+                if !transport.rolling {
+                    self.stop();
+                    return self.song_frame(transport);
+                }
+                let playback = self.playback(transport);
+                self.play(playback, n_frames, n_frames, received)
+            }
+            SyncSource::MidiClock => {
+                let timeline = &self.schedule.current().timeline;
+                let start_frame = self.jack_timing_info.nearest_frame(timeline, clock.start);
+                if !clock.rolling {
+                    self.stop();
+                    return start_frame;
+                }
+                let playback = match self.playback {
+                    Some(playback) if !clock.moved => playback,
+                    _ => Playback {
+                        transport_frame: transport.frame,
+                        song_frame: start_frame,
+                        loop_counter: LoopCounter::starting_at(start_frame),
+                    },
+                };
+                // The clock sets the tempo, so the cycle can cover more or less of the song
+                let end_frame = self
+                    .jack_timing_info
+                    .nearest_frame(timeline, clock.end)
+                    .max(playback.song_frame);
+                self.play(
+                    playback,
+                    end_frame - playback.song_frame,
+                    n_frames,
+                    received,
+                )
+            }
+        }
+    }
+
+    /// Release everything if playback has just stopped.
+    fn stop(&mut self) {
+        if self.playback.take().is_some() {
+            self.chords.release_all_except(NoteSet::default());
+            self.melody.release_all_except(NoteSet::default());
+            self.clock
+                .stop(|time, event| self.clock_events.push(time, event));
+        }
+    }
+
+    /// Play `song_frames` of the song from `playback` in a cycle `n_frames` long, squeezing or
+    /// stretching them to fit, and return the song frame reached.
+    fn play(
+        &mut self,
+        playback: Playback,
+        song_frames: Frames,
+        n_frames: Frames,
+        received: bool,
+    ) -> Frames {
+        let cycle_start = playback.song_frame;
+        let loop_counter = playback.loop_counter;
+        let jack_timing_info = &self.jack_timing_info;
+        let schedule = self.schedule.current();
+
+        let starting = self.playback.is_none();
+        let moved = self
+            .playback
+            .is_some_and(|last| last.song_frame != cycle_start);
+        if starting || moved {
+            self.clock.start(
+                cycle_start,
+                moved,
+                &schedule.timeline,
                 jack_timing_info,
-                project_timing_info,
+                |time, event| self.clock_events.push(time, event),
             );
-            assert!(time >= last_frame_time);
-            let frames_to_go = time - last_frame_time;
-            assert!(frames_to_go < n_frames);
-            (frames_to_go, event.event.clone())
-        });
-    upcoming_events.extend(upcoming_notes);
-    upcoming_events.sort_by_key(|(time, _midi_message)| *time);
-    upcoming_events
-}
-
-impl ProcessHandler for JackProcessor {
-    fn process(&mut self, _: &jack::Client, _process_scope: &jack::ProcessScope) -> jack::Control {
-        let current_project_state = self.project_state.read().unwrap();
-        let sequence = sequence_translation::chord_sequence_to_frame_offset(
-            &current_project_state.chord_sequence,
-            &self.jack_timing_info,
-            &current_project_state.time,
+        }
+        self.clock.pulses(
+            cycle_start..cycle_start + song_frames,
+            cycle_start,
+            &schedule.timeline,
+            jack_timing_info,
+            |time, event| self.clock_events.push(time, event),
         );
+        if moved {
+            // Notes from before a move are stale, even where they would be playing here
+            self.chords.release_all_except(NoteSet::default());
+            self.melody.release_all_except(NoteSet::default());
+        } else if received {
+            // Anything the new schedule would not still be holding is never released by it
+            self.chords.release_all_except(notes_sounding_at(
+                cycle_start,
+                lanes_at(cycle_start, schedule, jack_timing_info),
+                schedule,
+                &loop_counter,
+                jack_timing_info,
+            ));
+            self.melody.release_all_except(melody_sounding_at(
+                cycle_start,
+                schedule,
+                jack_timing_info,
+            ));
+        }
 
-        let upcoming_events = get_midi_events_for_next_n_frames(
-            _process_scope.last_frame_time(),
-            _process_scope.n_frames(),
-            &sequence,
-            &self.current_events,
-            &self.jack_timing_info,
-            &current_project_state.time,
+        schedule_chords(
+            cycle_start,
+            song_frames,
+            schedule,
+            &loop_counter,
+            jack_timing_info,
+            &mut self.chords.events,
         );
-
-        let mut chord_port_writer = self.chord_port.writer(_process_scope);
-        for (time, upcoming_event) in upcoming_events {
-            assert!(time < _process_scope.n_frames());
-            let midi_msg = translate_to_midi_message(&upcoming_event);
-            chord_port_writer
-                .write(&jack::RawMidi {
-                    time,
-                    bytes: &midi_msg.to_midi(),
-                })
-                .unwrap();
+        schedule_melody(
+            cycle_start,
+            song_frames,
+            schedule,
+            jack_timing_info,
+            &mut self.melody.events,
+        );
+        for events in [
+            &mut self.chords.events,
+            &mut self.melody.events,
+            &mut self.clock_events,
+        ] {
+            events.stretch(song_frames, n_frames);
         }
 
-        jack::Control::Continue
+        self.span = CycleSpan {
+            rolling: true,
+            song_frame: cycle_start,
+            song_frames,
+            n_frames,
+        };
+        self.playback = Some(Playback {
+            transport_frame: playback.transport_frame + n_frames,
+            song_frame: cycle_start + song_frames,
+            loop_counter,
+        });
+        cycle_start + song_frames
+    }
+
+    fn position_of_frame(&self, frame: Frames) -> MusicalPosition {
+        self.jack_timing_info
+            .position_of_frame(&self.schedule.current().timeline, frame)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{collections::HashSet, sync::Arc};
 
-    use std::{collections::HashSet, vec};
+    use jack::{Frames, TransportBBT};
 
     use crate::{
-        data_types::{beats_per_minute::BeatsPerMinute, note::Note},
+        data_types::{
+            beats_per_minute::BeatsPerMinute, chord_degree::ChordDegree, midi_channel::MidiChannel,
+            musical_position::MusicalPosition, note::Note, tatum::Tatum, velocity::Velocity,
+        },
         jack::{
             jack_processor::{
-                frames_of_next_offset, ghost_notes, is_upcoming_event, lingering_notes,
-                notes_on_at_point,
+                lanes_at, melody_sounding_at, notes_sounding_at, schedule_chords,
+                translate_to_midi_message, Engine, EventBuffer, InputPort, NoteLedger, NoteSet,
+                Sequencer,
             },
-            sequence_translation::{Event, FrameOffset, MidiEvent},
+            key_queue::key_queue,
+            loop_counter::LoopCounter,
+            schedule::{schedule_channel, Schedule, ScheduleSender},
+            sequence_translation::MidiEvent,
             timing_info::{FramesPerSecond, TimingInfo},
+            transport::TransportCycle,
+        },
+        model::{
+            chord_sequence::ChordSequence,
+            melody_lane::MelodyNote,
+            output_settings::{OutputSettings, ProgramChange},
+            project_state::ProjectState,
+            project_time_info::ProjectTimeInfo,
+            recording::RecordedKey,
+            scene::SceneChange,
+            step_condition::StepCondition,
+            step_timing::StepTiming,
+            sync_source::SyncSource,
+            tempo_map::{TempoChange, TempoCurve, TempoMap},
         },
-        model::project_time_info::ProjectTimeInfo,
     };
 
-    use super::get_midi_events_for_next_n_frames;
+    /// Where the outputs send by default
+    const CHANNEL: u8 = 0;
 
-    #[test]
-    fn test_frame_offset() {
-        // timing is 80 frames a bar
-
-        let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
-        };
-        let jack_timing_info = TimingInfo {
+    /// 80 frames a bar, 20 frames a beat, 5 frames a tatum at the default 120bpm
+    fn jack_timing_info() -> TimingInfo {
+        TimingInfo {
             frames_per_second: FramesPerSecond::from(40),
-        };
+        }
+    }
 
-        assert_eq!(
-            frames_of_next_offset(
-                90,
-                FrameOffset::from(5),
-                &jack_timing_info,
-                &project_time_info
-            ),
-            165
-        );
+    /// The transport rolling at `frame`, with no timebase master.
+    fn rolling(frame: Frames) -> TransportCycle {
+        TransportCycle {
+            rolling: true,
+            frame,
+            bbt: None,
+        }
+    }
 
-        assert_eq!(
-            frames_of_next_offset(
-                90,
-                FrameOffset::from(15),
-                &jack_timing_info,
-                &project_time_info
-            ),
-            95
+    fn stopped(frame: Frames) -> TransportCycle {
+        TransportCycle {
+            rolling: false,
+            ..rolling(frame)
+        }
+    }
+
+    fn project_with_lane(lane: ChordSequence) -> ProjectState {
+        ProjectState {
+            lanes: vec![lane],
+            ..ProjectState::default()
+        }
+    }
+
+    /// The chord port's events for the process cycle `n_frames` long from `cycle_start`.
+    fn cycle_events(
+        schedule: &Schedule,
+        jack_timing_info: &TimingInfo,
+        cycle_start: Frames,
+        n_frames: Frames,
+    ) -> Vec<(Frames, MidiEvent)> {
+        let mut events = EventBuffer::default();
+        schedule_chords(
+            cycle_start,
+            n_frames,
+            schedule,
+            &LoopCounter::starting_at(0),
+            jack_timing_info,
+            &mut events,
         );
+        events.events().to_vec()
+    }
 
+    fn chord_events(
+        project: &ProjectState,
+        cycle_start: Frames,
+        n_frames: Frames,
+    ) -> Vec<(Frames, MidiEvent)> {
+        cycle_events(
+            &Schedule::compile(project),
+            &jack_timing_info(),
+            cycle_start,
+            n_frames,
+        )
+    }
+
+    fn note_ons(notes: [u8; 3]) -> HashSet<MidiEvent> {
+        HashSet::from(notes.map(|note| MidiEvent::NoteOn(Note::from(note), Velocity::default())))
+    }
+
+    fn note_set(notes: impl IntoIterator<Item = u8>) -> NoteSet {
+        let mut set = NoteSet::default();
+        for note in notes {
+            set.insert(Note::from(note));
+        }
+        set
+    }
+
+    fn note_offs(notes: [u8; 3]) -> HashSet<MidiEvent> {
+        HashSet::from(notes.map(|note| MidiEvent::NoteOff(Note::from(note))))
+    }
+
+    #[test]
+    fn test_event_buffer_puts_note_offs_first() {
+        let mut events = EventBuffer::default();
+        events.push(5, MidiEvent::NoteOn(Note::from(60), Velocity::default()));
+        events.push(5, MidiEvent::NoteOff(Note::from(60)));
+        events.push(0, MidiEvent::ChannelPressure(1));
         assert_eq!(
-            frames_of_next_offset(
-                79,
-                FrameOffset::from(0),
-                &jack_timing_info,
-                &project_time_info
-            ),
-            80
+            events.events(),
+            [
+                (0, MidiEvent::ChannelPressure(1)),
+                (5, MidiEvent::NoteOff(Note::from(60))),
+                (5, MidiEvent::NoteOn(Note::from(60), Velocity::default())),
+            ]
         );
+    }
 
-        assert_eq!(
-            frames_of_next_offset(
-                80,
-                FrameOffset::from(0),
-                &jack_timing_info,
-                &project_time_info
-            ),
-            80
+    #[test]
+    fn test_note_set_follows_notes() {
+        let mut notes = NoteSet::default();
+        notes.play(&MidiEvent::NoteOn(Note::from(60), Velocity::default()));
+        notes.play(&MidiEvent::NoteOn(Note::from(127), Velocity::default()));
+        notes.play(&MidiEvent::NoteOff(Note::from(60)));
+        assert_eq!(notes.notes().collect::<Vec<_>>(), vec![Note::from(127)]);
+    }
+
+    #[test]
+    fn test_applies_loop_conditions() {
+        let mut lane = ChordSequence::new(vec![Some(ChordDegree::I)]).unwrap();
+        lane.set_condition(
+            Tatum::try_from(0).unwrap(),
+            StepCondition::LoopOf {
+                loop_number: 2,
+                loop_count: 2,
+            },
         );
+        let project = project_with_lane(lane);
+
+        let note_ons = |cycle_start| {
+            chord_events(&project, cycle_start, 10)
+                .into_iter()
+                .filter(|(_, event)| matches!(event, MidiEvent::NoteOn(..)))
+                .count()
+        };
+
+        assert_eq!(note_ons(0), 0);
+        assert_eq!(note_ons(80), 3);
+        assert_eq!(note_ons(160), 0);
+        assert_eq!(note_ons(240), 3);
+    }
+
+    #[test]
+    fn test_splits_cycle_at_loop_boundary() {
+        let mut lane = ChordSequence::new(vec![Some(ChordDegree::I)]).unwrap();
+        lane.set_condition(Tatum::try_from(0).unwrap(), StepCondition::NotFirstLoop);
+
         assert_eq!(
-            frames_of_next_offset(
-                80,
-                FrameOffset::from(1),
-                &jack_timing_info,
-                &project_time_info
-            ),
-            81
+            chord_events(&project_with_lane(lane), 70, 20),
+            vec![
+                (10, MidiEvent::NoteOn(Note::from(60), Velocity::default())),
+                (10, MidiEvent::NoteOn(Note::from(64), Velocity::default())),
+                (10, MidiEvent::NoteOn(Note::from(67), Velocity::default())),
+                (15, MidiEvent::NoteOff(Note::from(60))),
+                (15, MidiEvent::NoteOff(Note::from(64))),
+                (15, MidiEvent::NoteOff(Note::from(67))),
+            ]
         );
     }
 
     #[test]
-    fn test_is_upcoming_event() {
-        // timing is 80 frames a bar
+    fn test_short_loop_drifts_against_bar() {
+        // A 7 step lane loops every 35 frames
+        let mut lane = ChordSequence::with_steps(7).unwrap();
+        lane[Tatum::try_from(0).unwrap()] = Some(ChordDegree::I);
 
-        let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
-        };
-        let jack_timing_info = TimingInfo {
-            frames_per_second: FramesPerSecond::from(40),
-        };
+        let note_on_times: Vec<Frames> = chord_events(&project_with_lane(lane), 0, 80)
+            .into_iter()
+            .filter(|(_, event)| event == &MidiEvent::NoteOn(Note::from(60), Velocity::default()))
+            .map(|(time, _)| time)
+            .collect();
 
-        assert!(is_upcoming_event(
-            FrameOffset::from(0),
-            80,
-            10,
-            &jack_timing_info,
-            &project_time_info
-        ));
-        assert!(is_upcoming_event(
-            FrameOffset::from(0),
-            71,
-            10,
-            &jack_timing_info,
-            &project_time_info
-        ));
-        assert!(!is_upcoming_event(
-            FrameOffset::from(10),
-            80,
-            10,
-            &jack_timing_info,
-            &project_time_info
-        ));
+        assert_eq!(note_on_times, vec![0, 35, 70]);
     }
 
     #[test]
-    fn test_get_midi_events_for_next_n_frames() {
-        // timing is 80 frames a bar, 20 frames a beat, 5 frames a Tatum
-        let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
+    fn test_scene_switch_waits_for_boundary_and_releases_old_notes() {
+        let mut previous_lane = ChordSequence::default();
+        previous_lane[Tatum::try_from(3).unwrap()] = Some(ChordDegree::I);
+        let mut lane = ChordSequence::default();
+        lane[Tatum::try_from(4).unwrap()] = Some(ChordDegree::V);
+        let project = ProjectState {
+            lanes: vec![lane],
+            scene_change: Some(SceneChange {
+                at: MusicalPosition::from_tatums(4),
+                previous_lanes: vec![previous_lane],
+            }),
+            ..ProjectState::default()
         };
-        let jack_timing_info = TimingInfo {
-            frames_per_second: FramesPerSecond::from(40),
+
+        let events = chord_events(&project, 10, 20);
+
+        let at = |time: Frames| -> HashSet<MidiEvent> {
+            events
+                .iter()
+                .filter(|(t, _)| *t == time)
+                .map(|(_, event)| event.clone())
+                .collect()
         };
+        // The old scene's chord plays, and is released at the switch however it was meant to end
+        assert_eq!(at(5), note_ons([60, 64, 67]));
+        let mut at_switch = note_offs([60, 64, 67]);
+        at_switch.extend(note_ons([67, 71, 74]));
+        assert_eq!(at(10), at_switch);
+        assert_eq!(at(15), note_offs([67, 71, 74]));
+        assert_eq!(events.len(), 12);
+    }
 
-        let event_for_bar = vec![
-            Event {
-                event: MidiEvent::NoteOn(Note::from(60)),
-                bar_offset_frames: FrameOffset::from(0),
-            },
-            Event {
-                event: MidiEvent::NoteOff(Note::from(60)),
-                bar_offset_frames: FrameOffset::from(5),
-            },
-        ];
+    #[test]
+    fn test_scene_switch_releases_chord_nudged_past_it() {
+        let mut previous_lane = ChordSequence::default();
+        let step = Tatum::try_from(3).unwrap();
+        previous_lane[step] = Some(ChordDegree::I);
+        previous_lane.set_timing(step, StepTiming::new(1, 48).unwrap());
+        let project = ProjectState {
+            scene_change: Some(SceneChange {
+                at: MusicalPosition::from_tatums(4),
+                previous_lanes: vec![previous_lane],
+            }),
+            ..ProjectState::default()
+        };
 
-        let events = get_midi_events_for_next_n_frames(
-            80,
-            10, // processing two tatums
-            &event_for_bar,
-            &vec![],
-            &jack_timing_info,
-            &project_time_info,
-        );
+        let events = chord_events(&project, 10, 20);
 
+        assert_eq!(events.len(), 6);
         assert_eq!(
-            events,
-            vec![
-                (0, MidiEvent::NoteOn(Note::from(60))),
-                (5, MidiEvent::NoteOff(Note::from(60))),
-            ]
+            events[..3].iter().cloned().collect::<HashSet<_>>(),
+            [60, 64, 67]
+                .map(|note| (6, MidiEvent::NoteOn(Note::from(note), Velocity::default())))
+                .into()
         );
+        assert_eq!(
+            events[3..].iter().cloned().collect::<HashSet<_>>(),
+            [60, 64, 67]
+                .map(|note| (10, MidiEvent::NoteOff(Note::from(note))))
+                .into()
+        );
+    }
+
+    #[test]
+    fn test_follows_tempo_change() {
+        // 80 frames a bar at 120bpm, then 160 frames a bar at 60bpm from bar 1
+        let mut project = ProjectState::default();
+        project.time.tempo.set_change(TempoChange {
+            bar: 1,
+            bpm: BeatsPerMinute::from(60),
+            curve: TempoCurve::Step,
+        });
+        project.lanes[0][Tatum::try_from(1).unwrap()] = Some(ChordDegree::I);
+
+        let events = chord_events(&project, 0, 240);
+
+        let note_on_times: Vec<Frames> = events
+            .iter()
+            .filter(|(_, event)| event == &MidiEvent::NoteOn(Note::from(60), Velocity::default()))
+            .map(|(time, _)| *time)
+            .collect();
+        let note_off_times: Vec<Frames> = events
+            .iter()
+            .filter(|(_, event)| event == &MidiEvent::NoteOff(Note::from(60)))
+            .map(|(time, _)| *time)
+            .collect();
+        assert_eq!(note_on_times, vec![5, 90]);
+        assert_eq!(note_off_times, vec![10, 100]);
     }
 
     #[test]
-    fn test_get_midi_events_for_next_n_frames_event_from_start_of_next_bar() {
-        // timing is 80 frames a bar, 20 frames a beat, 5 frames a Tatum
-        let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
+    fn test_no_drift_over_hours_of_process_cycles() {
+        let bpm = BeatsPerMinute::try_from(97.3).unwrap();
+        let mut project = ProjectState {
+            time: ProjectTimeInfo {
+                tempo: TempoMap::new(bpm),
+                ..ProjectTimeInfo::default()
+            },
+            ..ProjectState::default()
         };
+        project.lanes[0][Tatum::try_from(0).unwrap()] = Some(ChordDegree::I);
+        let schedule = Schedule::compile(&project);
+        let frames_per_second = 44100;
         let jack_timing_info = TimingInfo {
-            frames_per_second: FramesPerSecond::from(40),
+            frames_per_second: FramesPerSecond::from(frames_per_second),
         };
 
-        let event_for_bar = vec![
-            Event {
-                event: MidiEvent::NoteOn(Note::from(60)),
-                bar_offset_frames: FrameOffset::from(0),
-            },
-            Event {
-                event: MidiEvent::NoteOff(Note::from(60)),
-                bar_offset_frames: FrameOffset::from(5),
-            },
-            Event {
-                event: MidiEvent::NoteOn(Note::from(62)),
-                bar_offset_frames: FrameOffset::from(10),
-            },
-            Event {
-                event: MidiEvent::NoteOff(Note::from(62)),
-                bar_offset_frames: FrameOffset::from(15),
-            },
-        ];
+        // Three hours of the largest process cycles JACK uses
+        let cycle_frames = 8192;
+        let cycles = 3 * 60 * 60 * frames_per_second as u32 / cycle_frames;
+        let mut bar_starts = vec![];
+        for cycle in 0..cycles {
+            let cycle_start = cycle * cycle_frames;
+            let events = cycle_events(&schedule, &jack_timing_info, cycle_start, cycle_frames);
+            bar_starts.extend(
+                events
+                    .iter()
+                    .filter(|(_, event)| {
+                        event == &MidiEvent::NoteOn(Note::from(60), Velocity::default())
+                    })
+                    .map(|(time, _)| cycle_start + time),
+            );
+        }
 
-        let events = get_midi_events_for_next_n_frames(
-            86,
-            79, // just shy of a whole bar
-            &event_for_bar,
-            &vec![],
-            &jack_timing_info,
-            &project_time_info,
+        let exact_frames_per_bar = 4.0 * 60.0 / f32::from(bpm) as f64 * frames_per_second as f64;
+        let played_frames = (cycles * cycle_frames) as f64;
+        assert_eq!(
+            bar_starts.len(),
+            (played_frames / exact_frames_per_bar).ceil() as usize
         );
+        for (bar, frame) in bar_starts.iter().enumerate() {
+            assert_eq!(*frame, (bar as f64 * exact_frames_per_bar).round() as u32);
+        }
+    }
 
-        let start_of_next_frame = 160 - 86;
+    #[test]
+    fn test_translate_midi_messages() {
+        let bytes = |event| {
+            translate_to_midi_message(&OutputSettings::default(), &event)
+                .as_slice()
+                .to_vec()
+        };
         assert_eq!(
-            events,
-            vec![
-                (4, MidiEvent::NoteOn(Note::from(62))),  // Turn on 62
-                (9, MidiEvent::NoteOff(Note::from(62))), // Turn off 62
-                (start_of_next_frame, MidiEvent::NoteOn(Note::from(60))), // Turn on 60 at start of next bar
-            ]
+            bytes(MidiEvent::NoteOn(Note::from(60), Velocity::default())),
+            vec![0x90, 60, u8::from(Velocity::default())]
+        );
+        assert_eq!(
+            bytes(MidiEvent::NoteOff(Note::from(60))),
+            vec![0x80, 60, 64]
+        );
+        assert_eq!(
+            bytes(MidiEvent::ControlChange {
+                controller: 74,
+                value: 100
+            }),
+            vec![0xB0, 74, 100]
         );
+        assert_eq!(bytes(MidiEvent::PitchBend(8192)), vec![0xE0, 0x00, 0x40]);
+        assert_eq!(bytes(MidiEvent::ChannelPressure(5)), vec![0xD0, 5]);
+        assert_eq!(bytes(MidiEvent::ProgramChange(12)), vec![0xC0, 12]);
+        assert_eq!(bytes(MidiEvent::Clock), vec![0xF8]);
+        let settings = OutputSettings {
+            channel: MidiChannel::try_from(9).unwrap(),
+            note_off_velocity: Velocity::try_from(0).unwrap(),
+            ..OutputSettings::default()
+        };
+        assert_eq!(
+            translate_to_midi_message(&settings, &MidiEvent::SongPosition(200)).as_slice(),
+            [0xF2, 72, 1]
+        );
+        assert_eq!(
+            translate_to_midi_message(&settings, &MidiEvent::NoteOff(Note::from(36))).as_slice(),
+            [0x89, 36, 0]
+        );
+    }
+
+    #[test]
+    fn test_ledger_drops_note_offs_for_silent_notes() {
+        let mut ledger = NoteLedger::default();
+        let note_on = MidiEvent::NoteOn(Note::from(60), Velocity::default());
+        let note_off = MidiEvent::NoteOff(Note::from(60));
+        assert!(!ledger.send(CHANNEL, &note_off));
+        assert!(ledger.send(CHANNEL, &note_on));
+        // Channels are kept apart
+        assert!(!ledger.send(1, &note_off));
+        assert!(ledger.send(CHANNEL, &note_off));
+        assert!(!ledger.send(CHANNEL, &note_off));
+    }
+
+    /// Plays a project through the sequencer a cycle at a time, sending it edits between cycles
+    /// as the GUI does, and listening to the ports as a synth would.
+    struct Harness {
+        project: ProjectState,
+        sender: ScheduleSender,
+        sequencer: Sequencer,
+        cycle_start: Frames,
+        chords: NoteSet,
+        melody: NoteSet,
+    }
+
+    impl Harness {
+        fn new(project: ProjectState) -> Harness {
+            let (sender, schedule) = schedule_channel(&project);
+            Harness {
+                project,
+                sender,
+                sequencer: Sequencer::new(schedule, jack_timing_info()),
+                cycle_start: 0,
+                chords: NoteSet::default(),
+                melody: NoteSet::default(),
+            }
+        }
+
+        /// Send the project as it is now, then play `cycles` process cycles `n_frames` long.
+        fn play(&mut self, cycles: u32, n_frames: Frames) {
+            self.sender.update(&self.project);
+            for _ in 0..cycles {
+                self.sequencer.cycle(&rolling(self.cycle_start), n_frames);
+                for (sounding, events) in [
+                    (&mut self.chords, &self.sequencer.chords.events),
+                    (&mut self.melody, &self.sequencer.melody.events),
+                ] {
+                    for (_, event) in events.events() {
+                        if let MidiEvent::NoteOff(note) = event {
+                            assert!(sounding.contains(*note), "{note:?} was not playing");
+                        }
+                        sounding.play(event);
+                    }
+                }
+                self.cycle_start += n_frames;
+                self.assert_nothing_stuck();
+            }
+        }
+
+        /// Every note sounding is one the project is playing, so will be released.
+        fn assert_nothing_stuck(&self) {
+            let schedule = Schedule::compile(&self.project);
+            let jack_timing_info = jack_timing_info();
+            let frame = self.cycle_start;
+            let playing = notes_sounding_at(
+                frame,
+                lanes_at(frame, &schedule, &jack_timing_info),
+                &schedule,
+                &LoopCounter::starting_at(0),
+                &jack_timing_info,
+            );
+            let held = self.sequencer.live_chords.held();
+            assert_eq!(
+                self.chords.difference(playing.union(held)),
+                NoteSet::default()
+            );
+            let playing = melody_sounding_at(frame, &schedule, &jack_timing_info);
+            assert_eq!(self.melody.difference(playing), NoteSet::default());
+        }
+    }
+
+    #[test]
+    fn test_chord_changed_while_held_releases_only_dropped_notes() {
+        let mut project = ProjectState::default();
+        project.lanes[0][Tatum::try_from(0).unwrap()] = Some(ChordDegree::I);
+        let mut harness = Harness::new(project);
+        harness.play(1, 2);
+        assert_eq!(harness.chords, note_set([60, 64, 67]));
+
+        // III shares two notes with I, which carry on and are released when III would be
+        harness.project.lanes[0][Tatum::try_from(0).unwrap()] = Some(ChordDegree::III);
+        harness.play(1, 2);
+        assert_eq!(harness.chords, note_set([64, 67]));
+        harness.play(1, 2);
+        assert_eq!(harness.chords, NoteSet::default());
+    }
+
+    #[test]
+    fn test_chord_removed_while_held_is_released() {
+        let mut project = ProjectState::default();
+        project.lanes[0][Tatum::try_from(0).unwrap()] = Some(ChordDegree::I);
+        let mut harness = Harness::new(project);
+        harness.play(1, 2);
+
+        // Removed and put back, and neither leaves anything behind
+        for chord in [None, Some(ChordDegree::I), None] {
+            harness.project.lanes[0][Tatum::try_from(0).unwrap()] = chord;
+            harness.play(1, 1);
+        }
+        assert_eq!(harness.chords, NoteSet::default());
+        harness.play(100, 7);
+        assert_eq!(harness.chords, NoteSet::default());
     }
 
     #[test]
-    fn test_get_midi_events_for_next_n_frames_turns_off_old_note() {
-        // timing is 80 frames a bar, 20 frames a beat, 5 frames a Tatum
-        let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
+    fn test_no_stuck_notes_after_many_edits() {
+        let mut harness = Harness::new(ProjectState::default());
+        // Any fixed sequence of edits will do, so a simple generator picks them
+        let mut seed: u32 = 0x2545_f491;
+        let mut random = move |range: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % range
         };
-        let jack_timing_info = TimingInfo {
-            frames_per_second: FramesPerSecond::from(40),
+
+        for _ in 0..2000 {
+            let steps = harness.project.lanes[0].steps() as u32;
+            let step = Tatum::try_from(random(steps) as usize).unwrap();
+            match random(6) {
+                0 => harness.project.lanes[0][step] = None,
+                1 => {
+                    let ratchet = random(4) as u8 + 1;
+                    let nudge = random(241) as i16 - 120;
+                    harness
+                        .project
+                        .set_timing(0, step, StepTiming::new(ratchet, nudge).unwrap());
+                }
+                2 => {
+                    let steps = random(16) as usize + 1;
+                    harness.project.set_lane_steps(0, steps).unwrap();
+                }
+                3 => {
+                    let note = MelodyNote {
+                        note: Note::from(60 + random(3) as u8),
+                        start: MusicalPosition::from_ticks(random(16 * 240) as u64),
+                        length: MusicalPosition::from_ticks(random(4 * 240) as u64 + 1),
+                        velocity: Velocity::default(),
+                    };
+                    let _ = harness.project.add_melody_note(note);
+                }
+                4 => {
+                    let note = harness
+                        .project
+                        .melody
+                        .notes()
+                        .get(random(4) as usize)
+                        .cloned();
+                    if let Some(note) = note {
+                        harness.project.remove_melody_note(note.note, note.start);
+                    }
+                }
+                _ => {
+                    let chord = ChordDegree::ALL[random(7) as usize];
+                    harness.project.lanes[0][step] = Some(chord);
+                }
+            }
+            let n_frames = random(12) + 1;
+            harness.play(random(3) + 1, n_frames);
+        }
+
+        // With everything cleared, nothing is left sounding once a loop has gone by
+        harness.project = ProjectState::default();
+        harness.play(1, 80);
+        assert_eq!(harness.chords, NoteSet::default());
+        assert_eq!(harness.melody, NoteSet::default());
+    }
+
+    #[test]
+    fn test_no_stuck_notes_with_keys_and_edits() {
+        let mut harness = Harness::new(ProjectState::default());
+        let mut seed: u32 = 0x9e37_79b9;
+        let mut random = move |range: u32| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed % range
         };
 
-        let old_events = vec![
-            Event {
-                event: MidiEvent::NoteOn(Note::from(70)),
-                bar_offset_frames: FrameOffset::from(0),
-            },
-            Event {
-                event: MidiEvent::NoteOff(Note::from(70)),
-                bar_offset_frames: FrameOffset::from(5),
-            },
-        ];
+        for _ in 0..2000 {
+            let step = Tatum::try_from(random(16) as usize).unwrap();
+            let key = 60 + random(12) as u8;
+            let n_frames = random(12) + 1;
+            match random(4) {
+                0 => harness.project.lanes[0][step] = None,
+                1 => {
+                    let chord = ChordDegree::ALL[random(7) as usize];
+                    harness.project.lanes[0][step] = Some(chord);
+                }
+                2 => harness
+                    .sequencer
+                    .trigger(random(n_frames), &[KEY_DOWN, key, 100]),
+                _ => harness
+                    .sequencer
+                    .trigger(random(n_frames), &[KEY_UP, key, 0]),
+            }
+            harness.play(random(3) + 1, n_frames);
+        }
 
-        let event_for_bar = vec![
-            Event {
-                event: MidiEvent::NoteOn(Note::from(60)),
-                bar_offset_frames: FrameOffset::from(0),
-            },
-            Event {
-                event: MidiEvent::NoteOff(Note::from(60)),
-                bar_offset_frames: FrameOffset::from(5),
-            },
-        ];
+        // Once every key is let go and the lane cleared, nothing is left sounding
+        for key in 60..72 {
+            harness.sequencer.trigger(0, &[KEY_UP, key, 0]);
+        }
+        harness.project = ProjectState::default();
+        harness.play(1, 80);
+        assert_eq!(harness.chords, NoteSet::default());
+    }
 
-        let events = get_midi_events_for_next_n_frames(
-            83,
-            5,
-            &event_for_bar,
-            &old_events,
-            &jack_timing_info,
-            &project_time_info,
-        );
+    /// A sequencer for a project with chord I on the first step of a lane.
+    fn sequencer_playing_chord() -> Sequencer {
+        let mut project = ProjectState::default();
+        project.lanes[0][Tatum::try_from(0).unwrap()] = Some(ChordDegree::I);
+        let (_, schedule) = schedule_channel(&project);
+        Sequencer::new(schedule, jack_timing_info())
+    }
+
+    fn chord_port_events(sequencer: &Sequencer) -> HashSet<(Frames, MidiEvent)> {
+        sequencer.chords.events.events().iter().cloned().collect()
+    }
 
-        assert_eq!(events, vec![(0, MidiEvent::NoteOff(Note::from(70)))]);
+    /// Chord I played for a step from `time`.
+    fn chord_played_at(time: Frames) -> HashSet<(Frames, MidiEvent)> {
+        let ons = note_ons([60, 64, 67])
+            .into_iter()
+            .map(|event| (time, event));
+        let offs = note_offs([60, 64, 67])
+            .into_iter()
+            .map(|event| (time + 5, event));
+        ons.chain(offs).collect()
     }
 
     #[test]
-    fn test_notes_on_at_point_before_first_event() {
-        let notes_on = notes_on_at_point(
-            &vec![Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(60)),
-            }],
-            FrameOffset::from(0),
+    fn test_stopping_transport_releases_notes() {
+        let mut sequencer = sequencer_playing_chord();
+        assert_eq!(sequencer.cycle(&rolling(0), 2), 2);
+        assert_eq!(
+            sequencer.chords.ledger.held(CHANNEL),
+            note_set([60, 64, 67])
+        );
+
+        assert_eq!(sequencer.cycle(&stopped(2), 2), 2);
+        assert_eq!(
+            chord_port_events(&sequencer),
+            note_offs([60, 64, 67])
+                .into_iter()
+                .map(|event| (0, event))
+                .collect()
         );
-        assert_eq!(notes_on, HashSet::new());
+        assert_eq!(sequencer.chords.ledger.held(CHANNEL), NoteSet::default());
+
+        // Nothing plays while stopped, even where a chord starts
+        sequencer.cycle(&stopped(80), 10);
+        assert!(sequencer.chords.events.events().is_empty());
+    }
+
+    #[test]
+    fn test_playback_starts_where_transport_rolls() {
+        let mut sequencer = sequencer_playing_chord();
+        sequencer.cycle(&stopped(0), 10);
+        // Starting in the middle of the second bar plays nothing until the third
+        assert_eq!(sequencer.cycle(&rolling(120), 50), 170);
+        assert_eq!(chord_port_events(&sequencer), chord_played_at(40));
     }
 
     #[test]
-    fn test_notes_on_at_point_after_first_event() {
-        let notes_on = notes_on_at_point(
-            &vec![Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(60)),
-            }],
-            FrameOffset::from(1),
+    fn test_relocating_releases_notes_and_plays_from_new_position() {
+        let mut sequencer = sequencer_playing_chord();
+        sequencer.cycle(&rolling(0), 2);
+        assert_eq!(sequencer.cycle(&rolling(2), 2), 4);
+
+        // Moved back to the start of the bar while the chord is held
+        assert_eq!(sequencer.cycle(&rolling(0), 2), 2);
+        let mut expected: HashSet<_> = note_offs([60, 64, 67])
+            .into_iter()
+            .map(|event| (0, event))
+            .collect();
+        expected.extend(note_ons([60, 64, 67]).into_iter().map(|event| (0, event)));
+        assert_eq!(chord_port_events(&sequencer), expected);
+        assert_eq!(
+            sequencer.chords.ledger.held(CHANNEL),
+            note_set([60, 64, 67])
         );
-        assert_eq!(notes_on, HashSet::from([Note::from(60)]));
     }
 
     #[test]
-    fn test_notes_on_after_point_after_first_on_and_off() {
-        let sequence = vec![
-            Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(60)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(1),
-                event: MidiEvent::NoteOff(Note::from(60)),
-            },
-        ];
-        let notes_on = notes_on_at_point(&sequence, FrameOffset::from(2));
-        assert_eq!(notes_on, HashSet::from([]));
+    fn test_timebase_master_position_wins_over_frame() {
+        let mut sequencer = sequencer_playing_chord();
+        let bar_three = TransportCycle {
+            bbt: Some(TransportBBT {
+                bar: 3,
+                ..TransportBBT::default()
+            }),
+            ..rolling(1000)
+        };
+        assert_eq!(sequencer.cycle(&bar_three, 10), 170);
+        assert_eq!(chord_port_events(&sequencer), chord_played_at(0));
+        // Once rolling, playback carries on from there as the frames go by
+        assert_eq!(
+            sequencer.cycle(
+                &TransportCycle {
+                    bbt: None,
+                    ..rolling(1010)
+                },
+                10
+            ),
+            180
+        );
+        assert!(chord_port_events(&sequencer).is_empty());
+    }
+
+    /// Transport messages on the clock port, leaving out the pulses.
+    fn clock_messages(sequencer: &Sequencer) -> Vec<MidiEvent> {
+        sequencer
+            .clock_events
+            .events()
+            .iter()
+            .map(|(_, event)| event.clone())
+            .filter(|event| *event != MidiEvent::Clock)
+            .collect()
     }
 
     #[test]
-    fn test_notes_on_at_point_after_first_on_and_off() {
-        let sequence = vec![
-            Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(60)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(1),
-                event: MidiEvent::NoteOff(Note::from(60)),
-            },
-        ];
-        let notes_on = notes_on_at_point(&sequence, FrameOffset::from(1));
-        assert_eq!(notes_on, HashSet::from([Note::from(60)]));
+    fn test_clock_follows_transport() {
+        let mut sequencer = sequencer_playing_chord();
+        sequencer.cycle(&stopped(0), 10);
+        assert!(sequencer.clock_events.events().is_empty());
+
+        sequencer.cycle(&rolling(0), 10);
+        assert_eq!(clock_messages(&sequencer), vec![MidiEvent::Start]);
+        assert_eq!(
+            sequencer.clock_events.events()[1],
+            (0, MidiEvent::Clock),
+            "the first pulse comes with the start"
+        );
+        sequencer.cycle(&rolling(10), 10);
+        assert!(clock_messages(&sequencer).is_empty());
+
+        // Half way through the first bar
+        sequencer.cycle(&rolling(40), 10);
+        assert_eq!(
+            clock_messages(&sequencer),
+            vec![
+                MidiEvent::Stop,
+                MidiEvent::SongPosition(8),
+                MidiEvent::Continue
+            ]
+        );
+
+        sequencer.cycle(&stopped(50), 10);
+        assert_eq!(clock_messages(&sequencer), vec![MidiEvent::Stop]);
+        sequencer.cycle(&stopped(50), 10);
+        assert!(sequencer.clock_events.events().is_empty());
     }
 
+    const KEY_DOWN: u8 = 0x90;
+    const KEY_UP: u8 = 0x80;
+
     #[test]
-    fn test_notes_on_at_point_turn_two_notes_on_one_note_off() {
-        let sequence = vec![
-            Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(60)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(62)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(1),
-                event: MidiEvent::NoteOff(Note::from(60)),
-            },
-        ];
-        let notes_on = notes_on_at_point(&sequence, FrameOffset::from(2));
-        assert_eq!(notes_on, HashSet::from([Note::from(62)]));
+    fn test_keys_play_chords_while_stopped() {
+        let mut sequencer = sequencer_playing_chord();
+        // C sharp plays II with the default layout, and a note-on with no velocity lets go
+        sequencer.trigger(3, &[KEY_DOWN, 49, 100]);
+        sequencer.cycle(&stopped(0), 10);
+        let velocity = Velocity::try_from(100).unwrap();
+        assert_eq!(
+            sequencer.chords.events.events(),
+            [62, 65, 69].map(|note| (3, MidiEvent::NoteOn(Note::from(note), velocity)))
+        );
+        sequencer.trigger(0, &[KEY_DOWN, 49, 0]);
+        sequencer.cycle(&stopped(0), 10);
+        assert_eq!(
+            chord_port_events(&sequencer),
+            note_offs([62, 65, 69])
+                .into_iter()
+                .map(|event| (0, event))
+                .collect()
+        );
+        // Other keys and messages are ignored
+        sequencer.trigger(0, &[KEY_DOWN, 55, 100]);
+        sequencer.trigger(0, &[0xB0, 1, 100]);
+        sequencer.cycle(&stopped(0), 10);
+        assert!(sequencer.chords.events.events().is_empty());
     }
 
     #[test]
-    fn test_lingering_notes_turns_old_notes_off() {
-        let old_sequence = vec![
-            Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(60)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(1),
-                event: MidiEvent::NoteOff(Note::from(60)),
-            },
-        ];
+    fn test_keyboard_keys_are_placed_in_song() {
+        let mut project = ProjectState::default();
+        project.lanes[0][Tatum::try_from(0).unwrap()] = Some(ChordDegree::I);
+        let (_, schedule) = schedule_channel(&project);
+        let (key_sender, mut keys) = key_queue(4);
+        let mut engine = Engine::new(schedule, Arc::default(), key_sender, jack_timing_info());
+        engine.receive(InputPort::Keyboard, 10, &[KEY_DOWN, 60, 100]);
+        engine.receive(InputPort::Keyboard, 0, &[0xB0, 1, 100]);
+        engine.process(&rolling(80), 20, |_, _, _| {});
+        assert_eq!(
+            keys.receive(),
+            Some(RecordedKey {
+                note: Note::from(60),
+                pressed: true,
+                rolling: true,
+                at: MusicalPosition::from_tatums(18),
+            })
+        );
+        assert_eq!(keys.receive(), None);
+        engine.receive(InputPort::Keyboard, 10, &[KEY_UP, 60, 0]);
+        engine.process(&stopped(40), 20, |_, _, _| {});
+        assert_eq!(
+            keys.receive(),
+            Some(RecordedKey {
+                note: Note::from(60),
+                pressed: false,
+                rolling: false,
+                at: MusicalPosition::from_tatums(8),
+            })
+        );
+    }
 
-        let new_sequence = vec![
-            Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(61)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(1),
-                event: MidiEvent::NoteOn(Note::from(61)),
-            },
+    #[test]
+    fn test_program_sent_on_load_start_and_change() {
+        let mut project = ProjectState::default();
+        project.chord_output.program = Some(ProgramChange::new(Some(130), 5).unwrap());
+        let (mut sender, schedule) = schedule_channel(&project);
+        let mut sequencer = Sequencer::new(schedule, jack_timing_info());
+        let program = vec![
+            (
+                0,
+                MidiEvent::ControlChange {
+                    controller: 0,
+                    value: 1,
+                },
+            ),
+            (
+                0,
+                MidiEvent::ControlChange {
+                    controller: 32,
+                    value: 2,
+                },
+            ),
+            (0, MidiEvent::ProgramChange(5)),
         ];
+        sequencer.cycle(&stopped(0), 10);
+        assert_eq!(sequencer.chords.events.events(), program);
+        assert!(sequencer.melody.events.events().is_empty());
+        sequencer.cycle(&stopped(0), 10);
+        assert!(sequencer.chords.events.events().is_empty());
+        sequencer.cycle(&rolling(0), 10);
+        assert_eq!(sequencer.chords.events.events()[..3], program);
 
+        project.chord_output.program = Some(ProgramChange::new(None, 7).unwrap());
+        sender.update(&project);
+        sequencer.cycle(&rolling(10), 10);
         assert_eq!(
-            lingering_notes(&old_sequence, &new_sequence, FrameOffset::from(1)),
-            HashSet::from([Note::from(60)])
+            sequencer.chords.events.events(),
+            [(0, MidiEvent::ProgramChange(7))]
         );
     }
 
     #[test]
-    fn test_lingering_notes_halfway_through_note() {
-        let old_sequence = vec![
-            Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(60)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(2),
-                event: MidiEvent::NoteOff(Note::from(60)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(4),
-                event: MidiEvent::NoteOn(Note::from(60)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(6),
-                event: MidiEvent::NoteOff(Note::from(60)),
-            },
-        ];
+    fn test_changing_channel_lets_go_on_old_channel_first() {
+        let mut project = ProjectState::default();
+        project.lanes[0][Tatum::try_from(0).unwrap()] = Some(ChordDegree::I);
+        let (mut sender, schedule) = schedule_channel(&project);
+        let mut sequencer = Sequencer::new(schedule, jack_timing_info());
+        sequencer.cycle(&rolling(0), 2);
+        assert_eq!(
+            sequencer.chords.ledger.held(CHANNEL),
+            note_set([60, 64, 67])
+        );
 
-        let new_sequence = vec![
-            Event {
-                bar_offset_frames: FrameOffset::from(4),
-                event: MidiEvent::NoteOn(Note::from(60)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(6),
-                event: MidiEvent::NoteOff(Note::from(60)),
-            },
-        ];
+        project.chord_output.channel = MidiChannel::try_from(1).unwrap();
+        sender.update(&project);
+        sequencer.cycle(&rolling(2), 2);
         assert_eq!(
-            lingering_notes(&old_sequence, &new_sequence, FrameOffset::from(1)),
-            HashSet::from([Note::from(60)])
+            chord_port_events(&sequencer),
+            note_offs([60, 64, 67])
+                .into_iter()
+                .map(|event| (0, event))
+                .collect()
         );
+        assert_eq!(u8::from(sequencer.chords.settings.channel), CHANNEL);
+        assert_eq!(sequencer.chords.ledger.held(CHANNEL), NoteSet::default());
+
+        // The next chord plays on the new channel
+        sequencer.cycle(&rolling(4), 80);
+        assert_eq!(u8::from(sequencer.chords.settings.channel), 1);
+        assert_eq!(sequencer.chords.ledger.held(1), note_set([60, 64, 67]));
+        assert_eq!(sequencer.chords.ledger.held(CHANNEL), NoteSet::default());
     }
 
     #[test]
-    fn test_ghost_note_includes_note_playing() {
-        let old_sequence = vec![
-            Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(70)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(5),
-                event: MidiEvent::NoteOff(Note::from(70)),
-            },
-        ];
+    fn test_held_key_outlasts_sequenced_chord() {
+        let mut sequencer = sequencer_playing_chord();
+        sequencer.trigger(0, &[KEY_DOWN, 60, 100]);
+        // The sequenced I chord lasts a step, five frames, but the key is still down
+        sequencer.cycle(&rolling(0), 10);
+        assert_eq!(
+            sequencer.chords.ledger.held(CHANNEL),
+            note_set([60, 64, 67])
+        );
+        assert!(chord_port_events(&sequencer)
+            .iter()
+            .all(|(_, event)| matches!(event, MidiEvent::NoteOn(..))));
+        sequencer.trigger(2, &[KEY_UP, 60, 0]);
+        sequencer.cycle(&rolling(10), 10);
+        assert_eq!(
+            chord_port_events(&sequencer),
+            note_offs([60, 64, 67])
+                .into_iter()
+                .map(|event| (2, event))
+                .collect()
+        );
+    }
 
-        let new_sequence = vec![
-            Event {
-                bar_offset_frames: FrameOffset::from(0),
-                event: MidiEvent::NoteOn(Note::from(60)),
-            },
-            Event {
-                bar_offset_frames: FrameOffset::from(6),
-                event: MidiEvent::NoteOff(Note::from(60)),
+    #[test]
+    fn test_sequenced_chord_outlasts_released_key() {
+        let mut sequencer = sequencer_playing_chord();
+        sequencer.trigger(0, &[KEY_DOWN, 60, 100]);
+        sequencer.trigger(2, &[KEY_UP, 60, 0]);
+        sequencer.cycle(&rolling(0), 10);
+        // Let go at two, but the sequence holds the chord until five
+        assert_eq!(
+            chord_port_events(&sequencer)
+                .into_iter()
+                .filter(|(_, event)| matches!(event, MidiEvent::NoteOff(..)))
+                .collect::<HashSet<_>>(),
+            note_offs([60, 64, 67])
+                .into_iter()
+                .map(|event| (5, event))
+                .collect()
+        );
+    }
+
+    /// Frames a chord on the second step starts at, following MIDI clock with a pulse every
+    /// `pulse_length` frames, in cycles `n_frames` long.
+    fn chord_start_following_clock(pulse_length: Frames, n_frames: Frames) -> Vec<Frames> {
+        let mut project = ProjectState::default();
+        project.lanes[0][Tatum::try_from(1).unwrap()] = Some(ChordDegree::I);
+        project.sync_source = SyncSource::MidiClock;
+        let (_, schedule) = schedule_channel(&project);
+        // 600 frames a tatum and 100 a pulse at the project's 120bpm
+        let mut sequencer = Sequencer::new(
+            schedule,
+            TimingInfo {
+                frames_per_second: FramesPerSecond::from(4800),
             },
-        ];
+        );
+        sequencer.clock_follower.receive(0, &[0xFA]);
+        let mut starts = vec![];
+        for cycle in 0..20 {
+            let cycle_start = cycle * n_frames;
+            let first_pulse = cycle_start.div_ceil(pulse_length) * pulse_length;
+            for frame in (first_pulse..cycle_start + n_frames).step_by(pulse_length as usize) {
+                sequencer
+                    .clock_follower
+                    .receive(frame - cycle_start, &[0xF8]);
+            }
+            // The JACK transport is left alone
+            sequencer.cycle(&stopped(0), n_frames);
+            starts.extend(
+                sequencer
+                    .chords
+                    .events
+                    .events()
+                    .iter()
+                    .filter(|(_, event)| {
+                        *event == MidiEvent::NoteOn(Note::from(60), Velocity::default())
+                    })
+                    .map(|(time, _)| cycle_start + time),
+            );
+        }
+        starts
+    }
+
+    #[test]
+    fn test_follows_midi_clock_tempo() {
+        // Six pulses to a tatum, so twice the project's tempo halves the wait
+        for n_frames in [64, 100, 256] {
+            let starts = chord_start_following_clock(50, n_frames);
+            assert!(starts[0].abs_diff(300) <= 1, "{starts:?}");
+            let starts = chord_start_following_clock(200, n_frames * 2);
+            assert!(starts[0].abs_diff(1200) <= 2, "{starts:?}");
+        }
+    }
+
+    #[test]
+    fn test_stretches_events_to_fit_cycle() {
+        let mut events = EventBuffer::default();
+        events.push(0, MidiEvent::Clock);
+        events.push(99, MidiEvent::Clock);
+        events.stretch(100, 50);
         assert_eq!(
-            ghost_notes(&old_sequence, &new_sequence, FrameOffset::from(1)),
-            HashSet::from([Note::from(60)])
+            events.events(),
+            [(0, MidiEvent::Clock), (49, MidiEvent::Clock)]
         );
     }
 }
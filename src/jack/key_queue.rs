@@ -0,0 +1,135 @@
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+use crate::{
+    data_types::{musical_position::MusicalPosition, note::Note},
+    model::recording::RecordedKey,
+};
+
+/// Keys waiting for the GUI, each packed into one word so they can be passed over without
+/// locking or allocating. Only the sender moves `write` and only the receiver moves `read`,
+/// and both only ever count up, wrapping round the slots.
+struct KeyQueue {
+    slots: Box<[AtomicU64]>,
+    write: AtomicUsize,
+    read: AtomicUsize,
+}
+
+const PRESSED: u64 = 1 << 7;
+const ROLLING: u64 = 1 << 8;
+const TICKS_SHIFT: u32 = 9;
+
+fn pack(key: RecordedKey) -> u64 {
+    let mut packed = u8::from(key.note) as u64 & 0x7F | key.at.ticks() << TICKS_SHIFT;
+    if key.pressed {
+        packed |= PRESSED;
+    }
+    if key.rolling {
+        packed |= ROLLING;
+    }
+    packed
+}
+
+fn unpack(packed: u64) -> RecordedKey {
+    RecordedKey {
+        note: Note::from((packed & 0x7F) as u8),
+        pressed: packed & PRESSED != 0,
+        rolling: packed & ROLLING != 0,
+        at: MusicalPosition::from_ticks(packed >> TICKS_SHIFT),
+    }
+}
+
+/// The engine's end, which drops keys rather than wait when the GUI falls behind.
+pub(crate) struct KeySender {
+    queue: Arc<KeyQueue>,
+}
+
+/// The GUI's end.
+pub(crate) struct KeyReceiver {
+    queue: Arc<KeyQueue>,
+}
+
+/// A queue with room for `capacity` keys the GUI has not picked up yet.
+pub(crate) fn key_queue(capacity: usize) -> (KeySender, KeyReceiver) {
+    let queue = Arc::new(KeyQueue {
+        slots: (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+        write: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+    });
+    (
+        KeySender {
+            queue: queue.clone(),
+        },
+        KeyReceiver { queue },
+    )
+}
+
+impl KeySender {
+    /// Queue `key`, returning whether there was room for it.
+    pub fn send(&mut self, key: RecordedKey) -> bool {
+        let slots = &self.queue.slots;
+        let write = self.queue.write.load(Ordering::Relaxed);
+        let read = self.queue.read.load(Ordering::Acquire);
+        if write.wrapping_sub(read) >= slots.len() {
+            return false;
+        }
+        slots[write % slots.len()].store(pack(key), Ordering::Relaxed);
+        self.queue
+            .write
+            .store(write.wrapping_add(1), Ordering::Release);
+        true
+    }
+}
+
+impl KeyReceiver {
+    pub fn receive(&mut self) -> Option<RecordedKey> {
+        let slots = &self.queue.slots;
+        let read = self.queue.read.load(Ordering::Relaxed);
+        let write = self.queue.write.load(Ordering::Acquire);
+        if read == write {
+            return None;
+        }
+        let packed = slots[read % slots.len()].load(Ordering::Relaxed);
+        self.queue
+            .read
+            .store(read.wrapping_add(1), Ordering::Release);
+        Some(unpack(packed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{musical_position::MusicalPosition, note::Note},
+        jack::key_queue::key_queue,
+        model::recording::RecordedKey,
+    };
+
+    fn key(note: u8, ticks: u64) -> RecordedKey {
+        RecordedKey {
+            note: Note::from(note),
+            pressed: note.is_multiple_of(2),
+            rolling: note.is_multiple_of(3),
+            at: MusicalPosition::from_ticks(ticks),
+        }
+    }
+
+    #[test]
+    fn keys_arrive_whole_and_in_order() {
+        let (mut sender, mut receiver) = key_queue(4);
+        assert_eq!(receiver.receive(), None);
+        for round in 0..5 {
+            let keys = [key(0, round), key(127, 1 << 40), key(60, 7), key(61, 0)];
+            for key in keys {
+                assert!(sender.send(key));
+            }
+            assert!(!sender.send(key(1, 1)), "full, so dropped");
+            for key in keys {
+                assert_eq!(receiver.receive(), Some(key));
+            }
+            assert_eq!(receiver.receive(), None);
+        }
+    }
+}
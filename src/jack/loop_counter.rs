@@ -0,0 +1,108 @@
+use jack::Frames;
+
+use crate::model::project_time_info::SongTime;
+
+use super::timing_info::TimingInfo;
+
+/// Counts how many times each lane has looped since playback started. Loops stay aligned to
+/// frame zero, so the partial loop playback starts in counts as the first.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct LoopCounter {
+    start_frame: Frames,
+}
+
+impl LoopCounter {
+    pub(crate) fn starting_at(start_frame: Frames) -> LoopCounter {
+        LoopCounter { start_frame }
+    }
+
+    pub(crate) fn loop_index(
+        &self,
+        loop_number: u64,
+        steps: usize,
+        timing_info: &TimingInfo,
+        project_time_info: &impl SongTime,
+    ) -> u64 {
+        let loops_at_start = timing_info
+            .loop_at_frame(project_time_info, self.start_frame, steps)
+            .number;
+        loop_number.saturating_sub(loops_at_start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::beats_per_minute::BeatsPerMinute,
+        jack::{
+            loop_counter::LoopCounter,
+            timing_info::{FramesPerSecond, TimingInfo},
+        },
+        model::{project_time_info::ProjectTimeInfo, tempo_map::TempoMap},
+    };
+
+    fn loop_index(
+        loop_counter: &LoopCounter,
+        frame: u32,
+        steps: usize,
+        jack_timing_info: &TimingInfo,
+        project_time_info: &ProjectTimeInfo,
+    ) -> u64 {
+        let loop_number = jack_timing_info
+            .loop_at_frame(project_time_info, frame, steps)
+            .number;
+        loop_counter.loop_index(loop_number, steps, jack_timing_info, project_time_info)
+    }
+
+    #[test]
+    fn first_loop_is_the_one_playback_starts_in() {
+        // timing is 80 frames a bar
+        let project_time_info = ProjectTimeInfo {
+            tempo: TempoMap::new(BeatsPerMinute::from(120)),
+            ..ProjectTimeInfo::default()
+        };
+        let jack_timing_info = TimingInfo {
+            frames_per_second: FramesPerSecond::from(40),
+        };
+        let loop_counter = LoopCounter::starting_at(100);
+        let index = |frame| {
+            loop_index(
+                &loop_counter,
+                frame,
+                16,
+                &jack_timing_info,
+                &project_time_info,
+            )
+        };
+
+        assert_eq!(index(100), 0);
+        assert_eq!(index(159), 0);
+        assert_eq!(index(160), 1);
+        assert_eq!(index(400), 4);
+    }
+
+    #[test]
+    fn short_lanes_count_loops_faster() {
+        // timing is 80 frames a bar, so a 4 step lane loops every 20 frames
+        let project_time_info = ProjectTimeInfo {
+            tempo: TempoMap::new(BeatsPerMinute::from(120)),
+            ..ProjectTimeInfo::default()
+        };
+        let jack_timing_info = TimingInfo {
+            frames_per_second: FramesPerSecond::from(40),
+        };
+        let loop_counter = LoopCounter::starting_at(0);
+        let index = |frame| {
+            loop_index(
+                &loop_counter,
+                frame,
+                4,
+                &jack_timing_info,
+                &project_time_info,
+            )
+        };
+
+        assert_eq!(index(79), 3);
+        assert_eq!(index(80), 4);
+    }
+}
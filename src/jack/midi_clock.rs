@@ -0,0 +1,243 @@
+use std::ops::Range;
+
+use jack::Frames;
+
+use crate::{
+    data_types::{
+        musical_position::{MusicalPosition, TICKS_PER_TATUM},
+        tatum::TATUMS_PER_QUARTER_NOTE,
+    },
+    model::project_time_info::SongTime,
+};
+
+use super::{sequence_translation::MidiEvent, timing_info::TimingInfo};
+
+/// MIDI beat clock runs at 24 pulses a quarter note
+pub(crate) const PULSES_PER_QUARTER_NOTE: u64 = 24;
+
+pub(crate) const TICKS_PER_PULSE: u64 =
+    TICKS_PER_TATUM * TATUMS_PER_QUARTER_NOTE as u64 / PULSES_PER_QUARTER_NOTE;
+
+/// Song position pointers count sixteenth notes, which are tatums
+pub(crate) const PULSES_PER_TATUM: u64 = PULSES_PER_QUARTER_NOTE / TATUMS_PER_QUARTER_NOTE as u64;
+
+/// Most sixteenth notes a song position pointer can hold
+const MAX_SONG_POSITION: u64 = 0x3FFF;
+
+/// Frame clock pulse `pulse` lands on. Each is found from the start of the song, like every
+/// other event, so pulses follow the tempo map without building up rounding error.
+fn pulse_frame(pulse: u64, time_info: &impl SongTime, jack_timing_info: &TimingInfo) -> Frames {
+    jack_timing_info.nearest_frame(
+        time_info,
+        MusicalPosition::from_ticks(pulse * TICKS_PER_PULSE),
+    )
+}
+
+/// The first clock pulse landing on or after `frame`.
+fn first_pulse_from(
+    frame: Frames,
+    time_info: &impl SongTime,
+    jack_timing_info: &TimingInfo,
+) -> u64 {
+    let mut pulse = jack_timing_info.position_of_frame(time_info, frame).ticks() / TICKS_PER_PULSE;
+    // Pulses are rounded to the nearest frame, so the one found can be either side of it
+    while pulse > 0 && pulse_frame(pulse - 1, time_info, jack_timing_info) >= frame {
+        pulse -= 1;
+    }
+    while pulse_frame(pulse, time_info, jack_timing_info) < frame {
+        pulse += 1;
+    }
+    pulse
+}
+
+/// Sends MIDI beat clock, and the start, stop and song position messages that go with it, so
+/// other gear follows tubular's transport.
+#[derive(Debug, Default)]
+pub(crate) struct MidiClock {
+    /// First pulse to send after starting, which has to match the song position sent
+    resume_pulse: u64,
+}
+
+impl MidiClock {
+    /// Start the clock from `song_frame`, at the start of the cycle. Away from the start of the
+    /// song, gear is told to carry on from the next sixteenth note, and the clock waits for it.
+    /// `moved` is for when the clock was already running, which has to stop first.
+    pub fn start(
+        &mut self,
+        song_frame: Frames,
+        moved: bool,
+        time_info: &impl SongTime,
+        jack_timing_info: &TimingInfo,
+        mut send: impl FnMut(Frames, MidiEvent),
+    ) {
+        if moved {
+            send(0, MidiEvent::Stop);
+        }
+        let pulse = first_pulse_from(song_frame, time_info, jack_timing_info);
+        let sixteenths = pulse.div_ceil(PULSES_PER_TATUM).min(MAX_SONG_POSITION);
+        self.resume_pulse = sixteenths * PULSES_PER_TATUM;
+        if sixteenths == 0 {
+            send(0, MidiEvent::Start);
+        } else {
+            send(0, MidiEvent::SongPosition(sixteenths as u16));
+            send(0, MidiEvent::Continue);
+        }
+    }
+
+    /// Send the pulses landing in `frames` of the song, timed from `cycle_start`.
+    pub fn pulses(
+        &mut self,
+        frames: Range<Frames>,
+        cycle_start: Frames,
+        time_info: &impl SongTime,
+        jack_timing_info: &TimingInfo,
+        mut send: impl FnMut(Frames, MidiEvent),
+    ) {
+        let mut pulse =
+            first_pulse_from(frames.start, time_info, jack_timing_info).max(self.resume_pulse);
+        loop {
+            let frame = pulse_frame(pulse, time_info, jack_timing_info);
+            if frame >= frames.end {
+                break;
+            }
+            send(frame - cycle_start, MidiEvent::Clock);
+            pulse += 1;
+        }
+    }
+
+    pub fn stop(&mut self, mut send: impl FnMut(Frames, MidiEvent)) {
+        send(0, MidiEvent::Stop);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jack::Frames;
+
+    use crate::{
+        data_types::beats_per_minute::BeatsPerMinute,
+        jack::{
+            midi_clock::MidiClock,
+            sequence_translation::MidiEvent,
+            timing_info::{FramesPerSecond, TimingInfo},
+        },
+        model::{
+            project_state::ProjectState,
+            project_time_info::Timeline,
+            tempo_map::{TempoChange, TempoCurve, TempoMap},
+        },
+    };
+
+    /// 1000 frames a pulse at the default 120bpm
+    fn jack_timing_info() -> TimingInfo {
+        TimingInfo {
+            frames_per_second: FramesPerSecond::from(48000),
+        }
+    }
+
+    fn timeline(tempo: TempoMap) -> Timeline {
+        let mut project = ProjectState::default();
+        project.time.tempo = tempo;
+        project.time.timeline()
+    }
+
+    /// Song frames of the clock pulses sent over `cycles` cycles `n_frames` long from
+    /// `song_frame`, and the other messages.
+    fn run(
+        clock: &mut MidiClock,
+        timeline: &Timeline,
+        song_frame: Frames,
+        cycles: u32,
+        n_frames: Frames,
+    ) -> (Vec<Frames>, Vec<MidiEvent>) {
+        let mut pulses = vec![];
+        let mut messages = vec![];
+        for cycle in 0..cycles {
+            let start = song_frame + cycle * n_frames;
+            clock.pulses(
+                start..start + n_frames,
+                start,
+                timeline,
+                &jack_timing_info(),
+                |time, event| match event {
+                    MidiEvent::Clock => pulses.push(start + time),
+                    event => messages.push(event),
+                },
+            );
+        }
+        (pulses, messages)
+    }
+
+    #[test]
+    fn pulses_are_evenly_spaced_whatever_the_cycle_size() {
+        let timeline = timeline(TempoMap::new(BeatsPerMinute::from(120)));
+        for n_frames in [64, 1000, 1024, 4096] {
+            let mut clock = MidiClock::default();
+            let (pulses, messages) = run(&mut clock, &timeline, 0, 480_000 / n_frames, n_frames);
+            assert!(messages.is_empty());
+            for (number, frame) in pulses.iter().enumerate() {
+                assert_eq!(*frame, number as Frames * 1000);
+            }
+        }
+    }
+
+    #[test]
+    fn pulses_follow_tempo_changes() {
+        let mut tempo = TempoMap::new(BeatsPerMinute::from(120));
+        tempo.set_change(TempoChange {
+            bar: 1,
+            bpm: BeatsPerMinute::from(120),
+            curve: TempoCurve::Linear,
+        });
+        tempo.set_change(TempoChange {
+            bar: 2,
+            bpm: BeatsPerMinute::from(60),
+            curve: TempoCurve::Step,
+        });
+        let timeline = timeline(tempo);
+        let (pulses, _) = run(&mut MidiClock::default(), &timeline, 0, 600, 1000);
+        let gaps: Vec<_> = pulses.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        // A bar of 96 pulses at 120bpm, a bar slowing down, then steady at 60bpm
+        assert!(gaps[..95].iter().all(|&gap| gap == 1000));
+        assert!(gaps[96..191].windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(gaps[192..].iter().all(|&gap| gap == 2000));
+    }
+
+    #[test]
+    fn starts_from_the_top() {
+        let timeline = timeline(TempoMap::new(BeatsPerMinute::from(120)));
+        let mut clock = MidiClock::default();
+        let mut messages = vec![];
+        clock.start(0, false, &timeline, &jack_timing_info(), |time, event| {
+            messages.push((time, event))
+        });
+        assert_eq!(messages, vec![(0, MidiEvent::Start)]);
+        let (pulses, _) = run(&mut clock, &timeline, 0, 1, 1024);
+        assert_eq!(pulses, vec![0, 1000]);
+    }
+
+    #[test]
+    fn continues_from_the_next_sixteenth() {
+        let timeline = timeline(TempoMap::new(BeatsPerMinute::from(120)));
+        let mut clock = MidiClock::default();
+        let mut messages = vec![];
+        // Pulse 14 is past the second sixteenth, so carry on from the third
+        clock.start(
+            13_500,
+            true,
+            &timeline,
+            &jack_timing_info(),
+            |time, event| messages.push((time, event)),
+        );
+        assert_eq!(
+            messages,
+            vec![
+                (0, MidiEvent::Stop),
+                (0, MidiEvent::SongPosition(3)),
+                (0, MidiEvent::Continue)
+            ]
+        );
+        let (pulses, _) = run(&mut clock, &timeline, 13_500, 5, 1024);
+        assert_eq!(pulses, vec![18_000]);
+    }
+}
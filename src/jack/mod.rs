@@ -1,3 +1,14 @@
+pub mod backend;
+pub mod clock_follower;
+pub mod handoff;
+pub mod jack_backend;
 pub mod jack_processor;
+pub mod key_queue;
+pub mod loop_counter;
+pub mod midi_clock;
+pub mod offline_backend;
+pub mod schedule;
 pub mod sequence_translation;
+pub mod timebase;
 pub mod timing_info;
+pub mod transport;
@@ -0,0 +1,288 @@
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use jack::Frames;
+
+use crate::model::playhead::Playhead;
+
+use super::{
+    backend::Backend,
+    handoff::Subscriber,
+    jack_processor::{Engine, InputPort, OutputPort},
+    key_queue::KeySender,
+    schedule::Schedule,
+    timing_info::{FramesPerSecond, TimingInfo},
+    transport::TransportCycle,
+};
+
+/// The most wall-clock time one catch up plays through. After longer, as when the GUI has been
+/// held up, the backend skips ahead rather than render the whole gap on the GUI thread.
+const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+/// Runs the engine against a virtual clock instead of a sound server, as far and as fast as it
+/// is asked to, so it can run headless in tests or render to a file. Its transport is its own,
+/// and rolls on a frame at a time as JACK's does.
+pub(crate) struct OfflineBackend {
+    engine: Engine,
+    frames_per_second: FramesPerSecond,
+    /// Frames in each process cycle, as a JACK period would be
+    cycle_frames: Frames,
+    transport: TransportCycle,
+    /// Frames rendered so far
+    now: u64,
+    /// Messages still to arrive on the inputs, in order of frame
+    inputs: VecDeque<(u64, InputPort, Vec<u8>)>,
+}
+
+impl OfflineBackend {
+    pub fn new(
+        schedule: Subscriber<Schedule>,
+        playhead: Arc<Playhead>,
+        keys: KeySender,
+        frames_per_second: FramesPerSecond,
+        cycle_frames: Frames,
+    ) -> OfflineBackend {
+        let jack_timing_info = TimingInfo { frames_per_second };
+        OfflineBackend {
+            engine: Engine::new(schedule, playhead, keys, jack_timing_info),
+            frames_per_second,
+            cycle_frames,
+            transport: TransportCycle {
+                rolling: false,
+                frame: 0,
+                bbt: None,
+            },
+            now: 0,
+            inputs: VecDeque::new(),
+        }
+    }
+
+    /// Have `bytes` arrive on `port` `frame` frames after rendering started. Anything due
+    /// before the cycle being rendered arrives at its start.
+    #[cfg(test)]
+    pub fn queue_input(&mut self, frame: u64, port: InputPort, bytes: &[u8]) {
+        let index = self.inputs.partition_point(|(queued, ..)| *queued <= frame);
+        self.inputs.insert(index, (frame, port, bytes.to_vec()));
+    }
+
+    fn frames_in(&self, duration: Duration) -> u64 {
+        let frames_per_second = usize::from(self.frames_per_second) as f64;
+        (duration.as_secs_f64() * frames_per_second) as u64
+    }
+
+    /// Render up to `end` as `render_to` does, but skip all but the last `MAX_CATCH_UP` of it,
+    /// moving a rolling transport on as far as it would have played.
+    fn keep_up_to(&mut self, end: u64, send: &mut dyn FnMut(u64, OutputPort, &[u8])) {
+        let behind = end.saturating_sub(self.now);
+        let most = self.frames_in(MAX_CATCH_UP);
+        if behind > most {
+            let cycle_frames = self.cycle_frames as u64;
+            let skipped = (behind - most) / cycle_frames * cycle_frames;
+            self.now += skipped;
+            if self.transport.rolling {
+                self.transport.frame += skipped as Frames;
+            }
+        }
+        // Rendering offline can't fail
+        let _ = self.render_to(end, send);
+    }
+}
+
+impl Backend for OfflineBackend {
+    fn frames_per_second(&self) -> FramesPerSecond {
+        self.frames_per_second
+    }
+
+    fn start_transport(&mut self) -> Result<(), &'static str> {
+        self.transport.rolling = true;
+        Ok(())
+    }
+
+    fn stop_transport(&mut self) -> Result<(), &'static str> {
+        self.transport.rolling = false;
+        Ok(())
+    }
+
+    fn locate(&mut self, frame: Frames) -> Result<(), &'static str> {
+        self.transport.frame = frame;
+        Ok(())
+    }
+
+    /// Renders a whole cycle at a time, so may run a little past `end`.
+    fn render_to(
+        &mut self,
+        end: u64,
+        send: &mut dyn FnMut(u64, OutputPort, &[u8]),
+    ) -> Result<(), &'static str> {
+        while self.now < end {
+            let cycle_start = self.now;
+            let cycle_end = cycle_start + self.cycle_frames as u64;
+            while self
+                .inputs
+                .front()
+                .is_some_and(|(frame, ..)| *frame < cycle_end)
+            {
+                let (frame, port, bytes) = self.inputs.pop_front().unwrap();
+                let time = frame.saturating_sub(cycle_start) as Frames;
+                self.engine.receive(port, time, &bytes);
+            }
+            self.engine
+                .process(&self.transport, self.cycle_frames, |port, time, bytes| {
+                    send(cycle_start + time as u64, port, bytes)
+                });
+            if self.transport.rolling {
+                self.transport.frame += self.cycle_frames;
+            }
+            self.now = cycle_end;
+        }
+        Ok(())
+    }
+
+    /// Play in step with the wall clock, with nowhere for the outputs to go.
+    fn catch_up(&mut self, elapsed: Duration) {
+        let end = self.frames_in(elapsed);
+        self.keep_up_to(end, &mut |_, _, _| {});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{
+        data_types::{chord_degree::ChordDegree, musical_position::MusicalPosition, tatum::Tatum},
+        jack::{
+            backend::Backend,
+            jack_processor::{InputPort, OutputPort},
+            key_queue::{key_queue, KeyReceiver},
+            offline_backend::{OfflineBackend, MAX_CATCH_UP},
+            schedule::schedule_channel,
+            timing_info::FramesPerSecond,
+        },
+        model::{playhead::Playhead, project_state::ProjectState, recording::RecordedKey},
+    };
+
+    /// A backend playing chord I on the first step of each bar, which is 80 frames at the
+    /// default 120bpm, in cycles of 16 frames.
+    fn backend_playing_chord() -> (OfflineBackend, Arc<Playhead>, KeyReceiver) {
+        let mut project = ProjectState::default();
+        project.lanes[0][Tatum::try_from(0).unwrap()] = Some(ChordDegree::I);
+        let (_, schedule) = schedule_channel(&project);
+        let playhead = Arc::new(Playhead::default());
+        let (key_sender, keys) = key_queue(16);
+        let backend = OfflineBackend::new(
+            schedule,
+            playhead.clone(),
+            key_sender,
+            FramesPerSecond::from(40),
+            16,
+        );
+        (backend, playhead, keys)
+    }
+
+    /// Everything sent up to `end`, with the frame and port it was sent on.
+    fn render_to(backend: &mut dyn Backend, end: u64) -> Vec<(u64, OutputPort, Vec<u8>)> {
+        let mut rendered = vec![];
+        backend
+            .render_to(end, &mut |frame, port, bytes| {
+                rendered.push((frame, port, bytes.to_vec()))
+            })
+            .unwrap();
+        rendered
+    }
+
+    fn note_ons(rendered: &[(u64, OutputPort, Vec<u8>)]) -> Vec<(u64, u8)> {
+        rendered
+            .iter()
+            .filter(|(_, port, bytes)| *port == OutputPort::Chords && bytes[0] & 0xF0 == 0x90)
+            .map(|(frame, _, bytes)| (*frame, bytes[1]))
+            .collect()
+    }
+
+    #[test]
+    fn renders_chords_and_clock_while_rolling() {
+        let (mut backend, playhead, _) = backend_playing_chord();
+        assert!(note_ons(&render_to(&mut backend, 160)).is_empty());
+        backend.start_transport().unwrap();
+        let rendered = render_to(&mut backend, 320);
+        assert_eq!(
+            note_ons(&rendered),
+            [
+                (160, 60),
+                (160, 64),
+                (160, 67),
+                (240, 60),
+                (240, 64),
+                (240, 67)
+            ]
+        );
+        let clock: Vec<_> = rendered
+            .iter()
+            .filter(|(_, port, _)| *port == OutputPort::Clock)
+            .map(|(_, _, bytes)| bytes.as_slice())
+            .collect();
+        assert_eq!(clock[0], [0xFA]);
+        // 24 pulses a quarter note, which is 20 frames
+        assert_eq!(
+            clock.iter().filter(|bytes| **bytes == [0xF8]).count(),
+            8 * 24
+        );
+        assert_eq!(playhead.position(), MusicalPosition::from_tatums(32));
+    }
+
+    #[test]
+    fn locates_before_starting() {
+        let (mut backend, playhead, _) = backend_playing_chord();
+        backend.locate(60).unwrap();
+        backend.start_transport().unwrap();
+        assert_eq!(
+            note_ons(&render_to(&mut backend, 32)),
+            [(20, 60), (20, 64), (20, 67)]
+        );
+        // Frame 92, five frames a tatum
+        let reached = MusicalPosition::from_ticks(92 * 240 / 5);
+        assert_eq!(playhead.position(), reached);
+        backend.stop_transport().unwrap();
+        render_to(&mut backend, 48);
+        assert_eq!(playhead.position(), reached);
+    }
+
+    #[test]
+    fn inputs_arrive_when_queued() {
+        let (mut backend, _, mut keys) = backend_playing_chord();
+        backend.queue_input(30, InputPort::Keyboard, &[0x90, 62, 100]);
+        backend.queue_input(10, InputPort::Triggers, &[0x90, 60, 100]);
+        let rendered = render_to(&mut backend, 32);
+        assert_eq!(note_ons(&rendered), [(10, 60), (10, 64), (10, 67)]);
+        assert!(rendered
+            .iter()
+            .all(|(_, port, bytes)| *port != OutputPort::Chords || bytes[2] == 100));
+        assert_eq!(
+            keys.receive(),
+            Some(RecordedKey {
+                note: 62.into(),
+                pressed: true,
+                rolling: false,
+                at: MusicalPosition::from_ticks(0),
+            })
+        );
+        assert_eq!(keys.receive(), None);
+    }
+
+    #[test]
+    fn skips_ahead_when_far_behind() {
+        let (mut backend, playhead, _) = backend_playing_chord();
+        backend.start_transport().unwrap();
+        let hour = 40 * 60 * 60;
+        let mut rendered = vec![];
+        backend.keep_up_to(hour, &mut |frame, _, _| rendered.push(frame));
+        let most = backend.frames_in(MAX_CATCH_UP);
+        assert!(rendered.iter().all(|frame| *frame + most + 16 >= hour));
+        assert!(!rendered.is_empty());
+        assert_eq!(playhead.position(), MusicalPosition::from_tatums(hour / 5));
+        backend.catch_up(Duration::from_secs(60 * 60 + 2));
+        assert_eq!(
+            playhead.position(),
+            MusicalPosition::from_tatums(hour / 5 + 16)
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{
+    data_types::{musical_position::MusicalPosition, velocity::Velocity},
+    model::{
+        automation_lane::AutomationLane, chord_sequence::ChordSequence, melody_lane::MelodyLane,
+        output_settings::OutputSettings, project_state::ProjectState, project_time_info::Timeline,
+        sync_source::SyncSource, time_signature_map::TimeSignatureMap,
+        trigger_layout::TriggerLayout,
+    },
+};
+
+use super::{
+    handoff::{handoff, Publisher, Subscriber},
+    sequence_translation::CompiledLane,
+};
+
+/// A queued scene switch, as the engine sees it.
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct ScheduledSceneChange {
+    pub at: MusicalPosition,
+    pub previous_lanes: Vec<CompiledLane>,
+}
+
+/// Everything the engine plays, compiled from the project on the GUI thread. The engine only
+/// ever reads a schedule, and gets a whole new one when the project changes, so it never has
+/// to lock the project or allocate.
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct Schedule {
+    pub timeline: Timeline,
+    /// For finding bars given by the transport
+    pub time_signatures: TimeSignatureMap,
+    pub lanes: Vec<CompiledLane>,
+    pub scene_change: Option<ScheduledSceneChange>,
+    pub melody: MelodyLane,
+    pub chord_output: OutputSettings,
+    pub melody_output: OutputSettings,
+    pub fill: bool,
+    pub sync_source: SyncSource,
+    pub trigger_layout: TriggerLayout,
+}
+
+fn compile_lanes(
+    lanes: &[ChordSequence],
+    velocity: Velocity,
+    automation: &[AutomationLane],
+) -> Vec<CompiledLane> {
+    lanes
+        .iter()
+        .enumerate()
+        .map(|(track, lane)| {
+            CompiledLane::new(
+                lane,
+                velocity,
+                automation
+                    .iter()
+                    .filter(move |automation| automation.track == track),
+            )
+        })
+        .collect()
+}
+
+impl Schedule {
+    pub fn compile(project: &ProjectState) -> Schedule {
+        let velocity = project.chord_output.velocity;
+        Schedule {
+            timeline: project.time.timeline(),
+            time_signatures: project.time.time_signatures.clone(),
+            lanes: compile_lanes(&project.lanes, velocity, &project.automation),
+            scene_change: project
+                .scene_change
+                .as_ref()
+                .map(|change| ScheduledSceneChange {
+                    at: change.at,
+                    previous_lanes: compile_lanes(
+                        &change.previous_lanes,
+                        velocity,
+                        &project.automation,
+                    ),
+                }),
+            melody: project.melody.clone(),
+            chord_output: project.chord_output,
+            melody_output: project.melody_output,
+            fill: project.fill,
+            sync_source: project.sync_source,
+            trigger_layout: project.trigger_layout,
+        }
+    }
+}
+
+/// Keeps the engine's schedule up to date with the project, from the GUI thread.
+pub(crate) struct ScheduleSender {
+    publisher: Publisher<Schedule>,
+    sent: Schedule,
+}
+
+/// A sender for changes to `project`, and the engine's end, which starts with the project as
+/// it is now.
+pub(crate) fn schedule_channel(project: &ProjectState) -> (ScheduleSender, Subscriber<Schedule>) {
+    let schedule = Schedule::compile(project);
+    let (publisher, subscriber) = handoff(schedule.clone());
+    (
+        ScheduleSender {
+            publisher,
+            sent: schedule,
+        },
+        subscriber,
+    )
+}
+
+impl ScheduleSender {
+    /// Send the project to the engine if it has changed since it was last sent, and drop
+    /// schedules the engine has finished with.
+    pub fn update(&mut self, project: &ProjectState) {
+        let schedule = Schedule::compile(project);
+        if schedule == self.sent {
+            self.publisher.reclaim();
+            return;
+        }
+        self.publisher.publish(schedule.clone());
+        self.sent = schedule;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{chord_degree::ChordDegree, tatum::Tatum},
+        jack::schedule::{schedule_channel, Schedule},
+        model::{
+            automation_lane::{AutomationLane, AutomationTarget},
+            project_state::ProjectState,
+        },
+    };
+
+    #[test]
+    fn automation_is_compiled_into_its_track() {
+        let mut project = ProjectState::default();
+        project.add_lane();
+        let mut automation = AutomationLane::new(1, AutomationTarget::PitchBend);
+        automation.set_point(0, Some(8192)).unwrap();
+        project.automation.push(automation);
+
+        let schedule = Schedule::compile(&project);
+        assert_ne!(schedule.lanes[0], schedule.lanes[1]);
+        project.automation.clear();
+        assert_eq!(Schedule::compile(&project).lanes[0], schedule.lanes[0]);
+    }
+
+    #[test]
+    fn only_changes_are_sent() {
+        let mut project = ProjectState::default();
+        let (mut sender, mut engine) = schedule_channel(&project);
+        sender.update(&project);
+        assert!(!engine.receive());
+
+        project.update_chord_sequence(0, Tatum::try_from(0).unwrap(), Some(ChordDegree::V));
+        sender.update(&project);
+        assert!(engine.receive());
+        assert_eq!(*engine.current(), Schedule::compile(&project));
+    }
+}
@@ -1,276 +1,590 @@
-use std::ops::Mul;
-
 use jack::Frames;
 
 use crate::{
-    data_types::{chord_degree::ChordDegree, note::Note, tatum::Tatum},
-    model::{chord_sequence::ChordSequence, project_time_info::ProjectTimeInfo},
+    data_types::{
+        musical_position::{MusicalPosition, TICKS_PER_TATUM},
+        note::Note,
+        velocity::Velocity,
+    },
+    model::{
+        automation_lane::{AutomationLane, AutomationTarget},
+        chord_sequence::ChordSequence,
+        melody_lane::MelodyLane,
+        project_time_info::SongTime,
+        step_condition::{steps_playing, StepCondition},
+        step_timing::StepTiming,
+    },
     music_theory::chords::chord_degreee_to_notes,
 };
 
-use super::timing_info::{FramesPerTatum, TimingInfo};
-
-#[derive(PartialEq, Eq, Debug, Copy, Clone, PartialOrd)]
-pub(crate) struct FrameOffset(u32);
+use super::{loop_counter::LoopCounter, timing_info::TimingInfo};
 
-impl From<u32> for FrameOffset {
-    fn from(value: u32) -> Self {
-        FrameOffset(value)
-    }
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub(crate) enum MidiEvent {
+    NoteOn(Note, Velocity),
+    NoteOff(Note),
+    ControlChange {
+        controller: u8,
+        value: u8,
+    },
+    /// 14 bit, centred on 8192
+    PitchBend(u16),
+    ChannelPressure(u8),
+    ProgramChange(u8),
+    /// MIDI beat clock pulse. This and the transport messages below have no channel.
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    /// Sixteenth notes since the start of the song
+    SongPosition(u16),
 }
 
-impl From<FrameOffset> for Frames {
-    fn from(value: FrameOffset) -> Self {
-        value.0
+impl MidiEvent {
+    fn automation(target: AutomationTarget, value: u16) -> MidiEvent {
+        match target {
+            AutomationTarget::ControlChange(controller) => MidiEvent::ControlChange {
+                controller,
+                value: value as u8,
+            },
+            AutomationTarget::PitchBend => MidiEvent::PitchBend(value),
+            AutomationTarget::ChannelPressure => MidiEvent::ChannelPressure(value as u8),
+        }
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
-pub(crate) enum MidiEvent {
-    NoteOn(Note),
-    NoteOff(Note),
+/// A step holding a chord, with the notes of the chord worked out ahead of playback.
+#[derive(PartialEq, Clone, Debug)]
+struct CompiledStep {
+    step: usize,
+    notes: [Note; 3],
+    timing: StepTiming,
 }
 
-#[derive(PartialEq, Eq, Debug)]
-pub(crate) struct Event {
-    pub(crate) bar_offset_frames: FrameOffset,
-    pub(crate) event: MidiEvent,
+/// A lane worked out into what the engine needs to play it. This is done on the GUI thread,
+/// so playing the lane takes no allocation. Conditions are kept as they are, since whether a
+/// step plays depends on the pass.
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct CompiledLane {
+    steps: usize,
+    conditions: Vec<StepCondition>,
+    chords: Vec<CompiledStep>,
+    /// Chords carry no velocity of their own, so are all played at the output's
+    velocity: Velocity,
+    /// Automation for the track the lane plays, through one pass of the lane
+    automation: Vec<(MusicalPosition, MidiEvent)>,
 }
 
-impl Mul<FramesPerTatum> for Tatum {
-    type Output = FrameOffset;
-
-    fn mul(self, rhs: FramesPerTatum) -> Self::Output {
-        let tatum_index: usize = self.into();
-        let frames_per_tatum: u32 = rhs.into();
-        FrameOffset(tatum_index as u32 * frames_per_tatum)
+impl CompiledLane {
+    pub fn new<'a>(
+        sequence: &ChordSequence,
+        velocity: Velocity,
+        automation: impl Iterator<Item = &'a AutomationLane>,
+    ) -> CompiledLane {
+        let chords = sequence
+            .iter()
+            .enumerate()
+            .filter_map(|(step, chord)| {
+                chord.map(|chord| CompiledStep {
+                    step,
+                    notes: chord_degreee_to_notes(&chord),
+                    timing: sequence.timing(step.try_into().unwrap()),
+                })
+            })
+            .collect();
+        let mut automation: Vec<(MusicalPosition, MidiEvent)> = automation
+            .flat_map(|lane| {
+                lane.values_for_loop(sequence.steps())
+                    .into_iter()
+                    .map(|(position, value)| {
+                        (position, MidiEvent::automation(lane.target(), value))
+                    })
+            })
+            .collect();
+        automation.sort_by_key(|(position, _)| *position);
+        CompiledLane {
+            steps: sequence.steps(),
+            conditions: sequence.conditions().to_vec(),
+            chords,
+            velocity,
+            automation,
+        }
     }
-}
 
-fn get_time_of_event_relative_to_bar(
-    tatums_into_bar: Tatum,
-    project_time_info: &ProjectTimeInfo,
-    timing_info: &TimingInfo,
-) -> FrameOffset {
-    tatums_into_bar * timing_info.frames_per_tatum(project_time_info)
-}
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
 
-type EventTypeCreator = fn(Note) -> MidiEvent;
-
-fn event_for_chord(
-    chord: &ChordDegree,
-    time: FrameOffset,
-    event_type: EventTypeCreator,
-) -> [Event; 3] {
-    let notes = chord_degreee_to_notes(chord);
-    notes.map(event_type).map(|midi_event| Event {
-        bar_offset_frames: time,
-        event: midi_event,
-    })
-}
+    /// Every event of pass `number` through the lane, as frames from the start of the song.
+    /// Each chord lasts until the next one starts, or to the end of its step if that is
+    /// sooner. A step nudged off the grid can sound in the pass before or after its own, so
+    /// those passes are looked at too, and notes still held at the end of the pass are
+    /// released on its last frame. Notes come in order of time; automation follows them.
+    #[allow(clippy::too_many_arguments)]
+    pub fn events_in_pass(
+        &self,
+        number: u64,
+        loop_counter: &LoopCounter,
+        fill: bool,
+        timing_info: &TimingInfo,
+        song_time: &impl SongTime,
+        mut emit: impl FnMut(Frames, MidiEvent),
+    ) {
+        let span = timing_info.loop_span(song_time, self.steps, number);
+        let next_pass_start = span.start_frame + Frames::from(span.length);
+        let last_frame = span.start_frame + span.length.end_of_loop();
+        let pass_ticks = self.steps as u64 * TICKS_PER_TATUM;
+        let frame_of = |ticks: i64| {
+            timing_info.nearest_frame(song_time, MusicalPosition::from_ticks(ticks.max(0) as u64))
+        };
 
-pub(crate) fn chord_sequence_to_frame_offset(
-    sequence: &ChordSequence,
-    timing_info: &TimingInfo,
-    project_time_info: &ProjectTimeInfo,
-) -> Vec<Event> {
-    let mut last_chord = None;
-    let mut events = vec![];
-    for (index, chord) in sequence.iter().enumerate() {
-        let tatum = Tatum::try_from(index).unwrap();
-        let event_time = get_time_of_event_relative_to_bar(tatum, project_time_info, timing_info);
-        if let Some(last_chord_played) = last_chord {
-            let midi_events = event_for_chord(last_chord_played, event_time, MidiEvent::NoteOff);
-            events.extend(midi_events);
-            last_chord = None;
+        let mut chords = (number.saturating_sub(1)..=number + 1)
+            .flat_map(|pass| {
+                let loop_index = loop_counter.loop_index(pass, self.steps, timing_info, song_time);
+                let playing = steps_playing(&self.conditions, loop_index, fill);
+                self.chords
+                    .iter()
+                    .filter(move |chord| playing & (1 << chord.step) != 0)
+                    .map(move |chord| {
+                        let step_start = pass * pass_ticks + chord.step as u64 * TICKS_PER_TATUM;
+                        (step_start as i64, chord)
+                    })
+            })
+            .peekable();
+        while let Some((step_start, chord)) = chords.next() {
+            let next_chord_start = chords
+                .peek()
+                .map(|(next_start, next)| next_start + next.timing.nudge() as i64);
+            for (hit_start, hit_end) in chord.timing.hits() {
+                let start = step_start + hit_start;
+                let end = next_chord_start.map_or(step_start + hit_end, |next_chord_start| {
+                    next_chord_start.min(step_start + hit_end)
+                });
+                if start >= end {
+                    break;
+                }
+                let on = frame_of(start);
+                let off = match frame_of(end) {
+                    off if off == next_pass_start => last_frame,
+                    off => off,
+                };
+                if on >= off {
+                    continue;
+                }
+                if (span.start_frame..next_pass_start).contains(&on) {
+                    for note in chord.notes {
+                        emit(on, MidiEvent::NoteOn(note, self.velocity));
+                    }
+                }
+                if span.start_frame < off && off < next_pass_start {
+                    for note in chord.notes {
+                        emit(off, MidiEvent::NoteOff(note));
+                    }
+                }
+            }
         }
 
-        if let Some(chord_played) = chord {
-            last_chord = Some(chord_played);
-            let midi_events = event_for_chord(chord_played, event_time, MidiEvent::NoteOn);
-            events.extend(midi_events);
+        let pass_start = MusicalPosition::from_ticks(number * pass_ticks);
+        for (position, event) in &self.automation {
+            emit(
+                timing_info.nearest_frame(song_time, pass_start + *position),
+                event.clone(),
+            );
         }
     }
-    if let Some(last_chord_played) = last_chord {
-        let midi_events = event_for_chord(
-            last_chord_played,
-            timing_info.frames_end_of_bar(project_time_info),
-            MidiEvent::NoteOff,
-        );
-        events.extend(midi_events);
+}
+
+/// Every event of pass `number` through a melody lane, as frames from the start of the song.
+/// Notes running to the end of the pass are released on its last frame, like chords.
+pub(crate) fn melody_events_in_pass(
+    melody: &MelodyLane,
+    number: u64,
+    timing_info: &TimingInfo,
+    song_time: &impl SongTime,
+    mut emit: impl FnMut(Frames, MidiEvent),
+) {
+    let span = timing_info.loop_span(song_time, melody.steps(), number);
+    let last_frame = span.start_frame + span.length.end_of_loop();
+    let pass_start = MusicalPosition::from_tatums(number * melody.steps() as u64);
+    for note in melody.notes() {
+        let on = timing_info.nearest_frame(song_time, pass_start + note.start);
+        // Notes never run past the end of the loop, but can round to the next pass's first frame
+        let off = timing_info
+            .nearest_frame(song_time, pass_start + note.end())
+            .min(last_frame);
+        // Too short to hear
+        if on >= off {
+            continue;
+        }
+        emit(on, MidiEvent::NoteOn(note.note, note.velocity));
+        emit(off, MidiEvent::NoteOff(note.note));
     }
-    events
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
         data_types::{
-            beats_per_minute::BeatsPerMinute, chord_degree::ChordDegree, note::Note, tatum::Tatum,
+            chord_degree::ChordDegree, musical_position::MusicalPosition, note::Note, tatum::Tatum,
+            velocity::Velocity,
         },
         jack::{
-            sequence_translation::{chord_sequence_to_frame_offset, Event, FrameOffset, MidiEvent},
+            loop_counter::LoopCounter,
+            sequence_translation::{melody_events_in_pass, CompiledLane, MidiEvent},
             timing_info::{FramesPerSecond, TimingInfo},
         },
-        model::{chord_sequence::ChordSequence, project_time_info::ProjectTimeInfo},
+        model::{
+            automation_lane::{AutomationLane, AutomationTarget},
+            chord_sequence::ChordSequence,
+            melody_lane::{MelodyLane, MelodyNote},
+            project_time_info::ProjectTimeInfo,
+            step_timing::StepTiming,
+        },
     };
 
+    /// 5 frames a tatum at the default 120bpm, so 48 ticks a frame
+    fn jack_timing_info() -> TimingInfo {
+        TimingInfo {
+            frames_per_second: FramesPerSecond::from(40),
+        }
+    }
+
+    /// Events in order, timed from the start of pass `number`.
+    fn in_order(
+        steps: usize,
+        number: u64,
+        emit_events: impl FnOnce(&mut dyn FnMut(u32, MidiEvent)),
+    ) -> Vec<(u32, MidiEvent)> {
+        let project_time_info = ProjectTimeInfo::default();
+        let pass_start = jack_timing_info()
+            .loop_span(&project_time_info, steps, number)
+            .start_frame;
+        let mut events = vec![];
+        emit_events(&mut |frame, event| events.push((frame - pass_start, event)));
+        events.sort_by_key(|(time, event)| (*time, matches!(event, MidiEvent::NoteOn(..))));
+        events
+    }
+
+    fn lane_events(sequence: &ChordSequence, number: u64) -> Vec<(u32, MidiEvent)> {
+        lane_events_with_automation(sequence, &[], number)
+    }
+
+    fn lane_events_with_automation(
+        sequence: &ChordSequence,
+        automation: &[AutomationLane],
+        number: u64,
+    ) -> Vec<(u32, MidiEvent)> {
+        let lane = CompiledLane::new(sequence, Velocity::default(), automation.iter());
+        in_order(sequence.steps(), number, |emit| {
+            lane.events_in_pass(
+                number,
+                &LoopCounter::starting_at(0),
+                false,
+                &jack_timing_info(),
+                &ProjectTimeInfo::default(),
+                emit,
+            )
+        })
+    }
+
+    fn chord(time: u32, notes: [u8; 3], on: bool) -> Vec<(u32, MidiEvent)> {
+        notes
+            .map(|note| match on {
+                true => (
+                    time,
+                    MidiEvent::NoteOn(Note::from(note), Velocity::default()),
+                ),
+                false => (time, MidiEvent::NoteOff(Note::from(note))),
+            })
+            .to_vec()
+    }
+
     #[test]
-    fn test_chord_sequence_to_frame_offset() {
+    fn test_chord_lasts_one_step() {
         let sequence = ChordSequence::new(vec![Some(ChordDegree::I)]).unwrap();
-        let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
-        };
-        let jack_timing_info = TimingInfo {
-            frames_per_second: FramesPerSecond::from(40),
-        };
-        let events =
-            chord_sequence_to_frame_offset(&sequence, &jack_timing_info, &project_time_info);
+        assert_eq!(
+            lane_events(&sequence, 0),
+            [chord(0, [60, 64, 67], true), chord(5, [60, 64, 67], false)].concat()
+        );
+    }
+
+    #[test]
+    fn test_chords_in_adjacent_tatums_turn_off_old_chord() {
+        let sequence =
+            ChordSequence::new(vec![Some(ChordDegree::I), Some(ChordDegree::II)]).unwrap();
+        assert_eq!(
+            lane_events(&sequence, 0),
+            [
+                chord(0, [60, 64, 67], true),
+                chord(5, [60, 64, 67], false),
+                chord(5, [62, 65, 69], true),
+                chord(10, [62, 65, 69], false),
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_chord_at_end_is_released_on_last_frame() {
+        let mut sequence = ChordSequence::default();
+        sequence[Tatum::try_from(15).unwrap()] = Some(ChordDegree::II);
+        assert_eq!(
+            lane_events(&sequence, 0),
+            [
+                chord(75, [62, 65, 69], true),
+                chord(79, [62, 65, 69], false)
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_chord_at_end_of_short_lane() {
+        let mut sequence = ChordSequence::with_steps(7).unwrap();
+        sequence[Tatum::try_from(6).unwrap()] = Some(ChordDegree::I);
+        let note_off_times: Vec<u32> = lane_events(&sequence, 0)
+            .into_iter()
+            .filter(|(_, event)| matches!(event, MidiEvent::NoteOff(_)))
+            .map(|(time, _)| time)
+            .collect();
+        assert_eq!(note_off_times, vec![34; 3]);
+    }
+
+    #[test]
+    fn test_melody_off_grid() {
+        let velocity = Velocity::try_from(90).unwrap();
+        let mut melody = MelodyLane::default();
+        melody
+            .add_note(MelodyNote {
+                note: Note::from(72),
+                start: MusicalPosition::from_ticks(96),
+                length: MusicalPosition::from_tatums(1),
+                velocity,
+            })
+            .unwrap();
+        melody
+            .add_note(MelodyNote {
+                note: Note::from(74),
+                start: MusicalPosition::from_tatums(14),
+                length: MusicalPosition::from_tatums(4),
+                velocity,
+            })
+            .unwrap();
+
+        let events = in_order(melody.steps(), 0, |emit| {
+            melody_events_in_pass(
+                &melody,
+                0,
+                &jack_timing_info(),
+                &ProjectTimeInfo::default(),
+                emit,
+            )
+        });
 
         assert_eq!(
             events,
             vec![
-                Event {
-                    bar_offset_frames: FrameOffset::from(0),
-                    event: MidiEvent::NoteOn(Note::from(60))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(0),
-                    event: MidiEvent::NoteOn(Note::from(64))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(0),
-                    event: MidiEvent::NoteOn(Note::from(67))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(5),
-                    event: MidiEvent::NoteOff(Note::from(60))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(5),
-                    event: MidiEvent::NoteOff(Note::from(64))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(5),
-                    event: MidiEvent::NoteOff(Note::from(67))
-                },
+                (2, MidiEvent::NoteOn(Note::from(72), velocity)),
+                (7, MidiEvent::NoteOff(Note::from(72))),
+                (70, MidiEvent::NoteOn(Note::from(74), velocity)),
+                (79, MidiEvent::NoteOff(Note::from(74))),
             ]
-        )
+        );
     }
 
     #[test]
-    fn test_chord_sequence_chords_in_adjacent_tatums_turn_off_old_chord() {
-        let sequence =
-            ChordSequence::new(vec![Some(ChordDegree::I), Some(ChordDegree::II)]).unwrap();
-        let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
-        };
-        let jack_timing_info = TimingInfo {
-            frames_per_second: FramesPerSecond::from(40),
-        };
+    fn test_melody_note_ending_just_before_loop_end_is_released_in_its_pass() {
+        let mut melody = MelodyLane::default();
+        melody
+            .add_note(MelodyNote {
+                note: Note::from(60),
+                start: MusicalPosition::from_tatums(15),
+                length: MusicalPosition::from_ticks(220),
+                velocity: Velocity::default(),
+            })
+            .unwrap();
+
+        let events = in_order(melody.steps(), 0, |emit| {
+            melody_events_in_pass(
+                &melody,
+                0,
+                &jack_timing_info(),
+                &ProjectTimeInfo::default(),
+                emit,
+            )
+        });
+
+        assert_eq!(
+            events.last(),
+            Some(&(79, MidiEvent::NoteOff(Note::from(60))))
+        );
+    }
+
+    #[test]
+    fn test_melody_releases_before_retriggering() {
+        let mut melody = MelodyLane::default();
+        for start in [2, 0] {
+            melody
+                .add_note(MelodyNote {
+                    note: Note::from(60),
+                    start: MusicalPosition::from_tatums(start),
+                    length: MusicalPosition::from_tatums(2),
+                    velocity: Velocity::default(),
+                })
+                .unwrap();
+        }
+
+        let events = in_order(melody.steps(), 0, |emit| {
+            melody_events_in_pass(
+                &melody,
+                0,
+                &jack_timing_info(),
+                &ProjectTimeInfo::default(),
+                emit,
+            )
+        });
+
+        let at_ten: Vec<&MidiEvent> = events
+            .iter()
+            .filter(|(time, _)| *time == 10)
+            .map(|(_, event)| event)
+            .collect();
+        assert_eq!(
+            at_ten,
+            vec![
+                &MidiEvent::NoteOff(Note::from(60)),
+                &MidiEvent::NoteOn(Note::from(60), Velocity::default())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_automation_plays_with_its_lane() {
+        let mut automation = AutomationLane::new(0, AutomationTarget::ControlChange(74));
+        automation.interpolate = false;
+        automation.set_point(0, Some(20)).unwrap();
+        automation.set_point(2, Some(90)).unwrap();
+
         let events =
-            chord_sequence_to_frame_offset(&sequence, &jack_timing_info, &project_time_info);
+            lane_events_with_automation(&ChordSequence::with_steps(4).unwrap(), &[automation], 1);
 
         assert_eq!(
             events,
             vec![
-                Event {
-                    bar_offset_frames: FrameOffset::from(0),
-                    event: MidiEvent::NoteOn(Note::from(60))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(0),
-                    event: MidiEvent::NoteOn(Note::from(64))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(0),
-                    event: MidiEvent::NoteOn(Note::from(67))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(5),
-                    event: MidiEvent::NoteOff(Note::from(60))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(5),
-                    event: MidiEvent::NoteOff(Note::from(64))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(5),
-                    event: MidiEvent::NoteOff(Note::from(67))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(5),
-                    event: MidiEvent::NoteOn(Note::from(62))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(5),
-                    event: MidiEvent::NoteOn(Note::from(65))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(5),
-                    event: MidiEvent::NoteOn(Note::from(69))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(10),
-                    event: MidiEvent::NoteOff(Note::from(62))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(10),
-                    event: MidiEvent::NoteOff(Note::from(65))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(10),
-                    event: MidiEvent::NoteOff(Note::from(69))
-                },
+                (
+                    0,
+                    MidiEvent::ControlChange {
+                        controller: 74,
+                        value: 20
+                    }
+                ),
+                (
+                    10,
+                    MidiEvent::ControlChange {
+                        controller: 74,
+                        value: 90
+                    }
+                ),
             ]
-        )
+        );
+    }
+
+    /// When note 60 of the I chord goes on and off in one pass.
+    fn root_note_times(sequence: &ChordSequence, number: u64) -> Vec<(u32, bool)> {
+        lane_events(sequence, number)
+            .into_iter()
+            .filter_map(|(time, event)| match event {
+                MidiEvent::NoteOn(note, _) if note == Note::from(60) => Some((time, true)),
+                MidiEvent::NoteOff(note) if note == Note::from(60) => Some((time, false)),
+                _ => None,
+            })
+            .collect()
     }
 
     #[test]
-    fn test_chord_sequence_with_chord_at_end_on() {
+    fn test_ratchet_repeats_chord_within_step() {
         let mut sequence = ChordSequence::default();
-        sequence[Tatum::try_from(15).unwrap()] = Some(ChordDegree::II);
+        let step = Tatum::try_from(2).unwrap();
+        sequence[step] = Some(ChordDegree::I);
+        sequence.set_timing(step, StepTiming::new(5, 0).unwrap());
 
-        let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
-        };
-        let jack_timing_info = TimingInfo {
-            frames_per_second: FramesPerSecond::from(40),
-        };
-        let events =
-            chord_sequence_to_frame_offset(&sequence, &jack_timing_info, &project_time_info);
+        assert_eq!(
+            root_note_times(&sequence, 0),
+            vec![
+                (10, true),
+                (11, false),
+                (11, true),
+                (12, false),
+                (12, true),
+                (13, false),
+                (13, true),
+                (14, false),
+                (14, true),
+                (15, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ratchet_hits_shorter_than_a_frame_are_never_left_on() {
+        let mut sequence = ChordSequence::default();
+        let step = Tatum::try_from(15).unwrap();
+        sequence[step] = Some(ChordDegree::I);
+        sequence.set_timing(step, StepTiming::new(8, 0).unwrap());
+
+        let times = root_note_times(&sequence, 0);
+        for (index, (_, on)) in times.iter().enumerate() {
+            assert_eq!(*on, index % 2 == 0);
+        }
+        assert_eq!(times.last(), Some(&(79, false)));
+    }
+
+    #[test]
+    fn test_ratchet_stops_when_next_chord_starts() {
+        let mut sequence = ChordSequence::default();
+        let step = Tatum::try_from(2).unwrap();
+        sequence[step] = Some(ChordDegree::I);
+        sequence.set_timing(step, StepTiming::new(5, 48).unwrap());
+        let next_step = Tatum::try_from(3).unwrap();
+        sequence[next_step] = Some(ChordDegree::IV);
+        sequence.set_timing(next_step, StepTiming::new(1, -48).unwrap());
 
         assert_eq!(
-            events,
+            root_note_times(&sequence, 0),
             vec![
-                Event {
-                    bar_offset_frames: FrameOffset::from(75),
-                    event: MidiEvent::NoteOn(Note::from(62))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(75),
-                    event: MidiEvent::NoteOn(Note::from(65))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(75),
-                    event: MidiEvent::NoteOn(Note::from(69))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(79),
-                    event: MidiEvent::NoteOff(Note::from(62))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(79),
-                    event: MidiEvent::NoteOff(Note::from(65))
-                },
-                Event {
-                    bar_offset_frames: FrameOffset::from(79),
-                    event: MidiEvent::NoteOff(Note::from(69))
-                },
+                (11, true),
+                (12, false),
+                (12, true),
+                (13, false),
+                (13, true),
+                (14, false),
             ]
-        )
+        );
+    }
+
+    #[test]
+    fn test_early_nudge_plays_at_end_of_previous_loop() {
+        let mut sequence = ChordSequence::default();
+        let step = Tatum::try_from(0).unwrap();
+        sequence[step] = Some(ChordDegree::I);
+        sequence.set_timing(step, StepTiming::new(1, -48).unwrap());
+
+        // Nothing comes before the first loop, so it starts on time
+        assert_eq!(
+            root_note_times(&sequence, 0),
+            vec![(0, true), (4, false), (79, true)]
+        );
+        assert_eq!(root_note_times(&sequence, 1), vec![(4, false), (79, true)]);
+    }
+
+    #[test]
+    fn test_late_nudge_releases_in_next_loop() {
+        let mut sequence = ChordSequence::default();
+        let step = Tatum::try_from(15).unwrap();
+        sequence[step] = Some(ChordDegree::I);
+        sequence.set_timing(step, StepTiming::new(1, 48).unwrap());
+
+        assert_eq!(root_note_times(&sequence, 0), vec![(76, true)]);
+        assert_eq!(root_note_times(&sequence, 1), vec![(1, false), (76, true)]);
     }
 }
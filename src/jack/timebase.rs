@@ -0,0 +1,225 @@
+use std::ffi::{c_int, c_void};
+
+use jack::{jack_sys, Frames, TransportBBT, TransportPosition};
+
+use crate::{
+    data_types::{
+        musical_position::TICKS_PER_TATUM, tatum::TATUM_SUBDIVDISONS_PER_BAR,
+        time_signature::TimeSignature,
+    },
+    model::{
+        project_state::ProjectState, project_time_info::Timeline,
+        time_signature_map::TimeSignatureMap,
+    },
+};
+
+use super::{
+    handoff::{handoff, Publisher, Subscriber},
+    timing_info::TimingInfo,
+};
+
+/// The project's tempo map and time signatures, which is all the timebase master needs.
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct Meter {
+    pub timeline: Timeline,
+    pub time_signatures: TimeSignatureMap,
+}
+
+impl Meter {
+    pub fn of(project: &ProjectState) -> Meter {
+        Meter {
+            timeline: project.time.timeline(),
+            time_signatures: project.time.time_signatures.clone(),
+        }
+    }
+}
+
+/// Ticks in a beat of `time_signature`, counting beats in its beat unit as JACK does.
+fn ticks_per_beat(time_signature: TimeSignature) -> u64 {
+    TICKS_PER_TATUM * TATUM_SUBDIVDISONS_PER_BAR as u64 / time_signature.denominator() as u64
+}
+
+/// Bar, beat and tick of `frame`, all one based apart from the tick. Ticks are the project's
+/// own, so a bar's worth of them matches how far into the song it is.
+pub(crate) fn bbt_at(meter: &Meter, jack_timing_info: &TimingInfo, frame: Frames) -> TransportBBT {
+    let position = jack_timing_info.position_of_frame(&meter.timeline, frame);
+    let tatum = position.ticks() / TICKS_PER_TATUM;
+    let bar = meter.time_signatures.bar_of_tatum(tatum);
+    let time_signature = meter.time_signatures.time_signature_at_bar(bar);
+    let bar_start_tick = meter.time_signatures.bar_start_tatum(bar) * TICKS_PER_TATUM;
+    let ticks_per_beat = ticks_per_beat(time_signature);
+    let ticks_into_bar = position.ticks() - bar_start_tick;
+    // Tempo counts quarter notes, and JACK counts the time signature's beats
+    let quarter_notes_a_minute = meter.timeline.bpm_at_tatum(position.tatums());
+    TransportBBT {
+        bar: bar as usize + 1,
+        beat: (ticks_into_bar / ticks_per_beat) as usize + 1,
+        tick: (ticks_into_bar % ticks_per_beat) as usize,
+        sig_num: time_signature.numerator() as f32,
+        sig_denom: time_signature.denominator() as f32,
+        ticks_per_beat: ticks_per_beat as f64,
+        bpm: quarter_notes_a_minute * time_signature.denominator() as f64 / 4.0,
+        bar_start_tick: bar_start_tick as f64,
+    }
+}
+
+/// The timebase callback's own state, owned by [`TimebaseMaster`] and only touched by JACK.
+struct Timebase {
+    meter: Subscriber<Meter>,
+    jack_timing_info: TimingInfo,
+}
+
+unsafe extern "C" fn timebase_callback(
+    _state: jack_sys::jack_transport_state_t,
+    _n_frames: jack_sys::jack_nframes_t,
+    position: *mut jack_sys::jack_position_t,
+    _new_position: c_int,
+    timebase: *mut c_void,
+) {
+    // SAFETY: the argument is the `Timebase` registered with the callback, which lives until
+    // the callback is released, and JACK calls it from one thread at a time
+    let timebase = unsafe { &mut *(timebase as *mut Timebase) };
+    // SAFETY: `TransportPosition` is a transparent wrapper round `jack_position_t`
+    let position = unsafe { &mut *(position as *mut TransportPosition) };
+    timebase.meter.receive();
+    let bbt = bbt_at(
+        timebase.meter.current(),
+        &timebase.jack_timing_info,
+        position.frame(),
+    );
+    // Everything worked out above is in range, so it always validates
+    let _ = position.set_bbt(Some(bbt));
+}
+
+/// Makes tubular the JACK timebase master, publishing its bar, beat and tempo so other
+/// clients follow its tempo map, until dropped.
+pub(crate) struct TimebaseMaster {
+    client: *mut jack_sys::jack_client_t,
+    timebase: *mut Timebase,
+    publisher: Publisher<Meter>,
+    sent: Meter,
+}
+
+impl TimebaseMaster {
+    /// Take over as timebase master from any other client.
+    pub fn take(
+        client: &jack::Client,
+        project: &ProjectState,
+    ) -> Result<TimebaseMaster, &'static str> {
+        let meter = Meter::of(project);
+        let (publisher, subscriber) = handoff(meter.clone());
+        let timebase = Box::into_raw(Box::new(Timebase {
+            meter: subscriber,
+            jack_timing_info: TimingInfo {
+                frames_per_second: client.sample_rate().into(),
+            },
+        }));
+        // SAFETY: the client outlives the master, which frees `timebase` only once the
+        // callback is released
+        let result = unsafe {
+            jack_sys::jack_set_timebase_callback(
+                client.raw(),
+                0,
+                Some(timebase_callback),
+                timebase as *mut c_void,
+            )
+        };
+        if result != 0 {
+            // SAFETY: never registered, so JACK has no hold on it
+            drop(unsafe { Box::from_raw(timebase) });
+            return Err("Could not become JACK timebase master");
+        }
+        Ok(TimebaseMaster {
+            client: client.raw(),
+            timebase,
+            publisher,
+            sent: meter,
+        })
+    }
+
+    /// Publish the project's tempo map and time signatures if they have changed.
+    pub fn update(&mut self, project: &ProjectState) {
+        let meter = Meter::of(project);
+        if meter == self.sent {
+            self.publisher.reclaim();
+            return;
+        }
+        self.publisher.publish(meter.clone());
+        self.sent = meter;
+    }
+}
+
+impl Drop for TimebaseMaster {
+    fn drop(&mut self) {
+        // SAFETY: once released JACK no longer calls back, so the timebase can be freed
+        unsafe {
+            jack_sys::jack_release_timebase(self.client);
+            drop(Box::from_raw(self.timebase));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{beats_per_minute::BeatsPerMinute, time_signature::TimeSignature},
+        jack::{
+            timebase::{bbt_at, Meter},
+            timing_info::{FramesPerSecond, TimingInfo},
+            transport::position_of_bbt,
+        },
+        model::{project_state::ProjectState, tempo_map::TempoMap},
+    };
+
+    // 80 frames a 4/4 bar at the default 120bpm
+    fn jack_timing_info() -> TimingInfo {
+        TimingInfo {
+            frames_per_second: FramesPerSecond::from(40),
+        }
+    }
+
+    #[test]
+    fn bbt_counts_bars_and_beats_from_one() {
+        let meter = Meter::of(&ProjectState::default());
+        let bbt = bbt_at(&meter, &jack_timing_info(), 190);
+        assert_eq!((bbt.bar, bbt.beat, bbt.tick), (3, 2, 480));
+        assert_eq!((bbt.sig_num, bbt.sig_denom), (4.0, 4.0));
+        assert_eq!(bbt.ticks_per_beat, 960.0);
+        assert_eq!(bbt.bpm, 120.0);
+        assert_eq!(bbt.bar_start_tick, 2.0 * 16.0 * 240.0);
+    }
+
+    #[test]
+    fn bbt_counts_beats_in_beat_unit() {
+        let mut project = ProjectState::default();
+        project.time.tempo = TempoMap::new(BeatsPerMinute::from(60));
+        project
+            .time
+            .time_signatures
+            .set_time_signature(1, TimeSignature::new(6, 8).unwrap());
+        let meter = Meter::of(&project);
+        // A bar of 4/4 at 60bpm is 160 frames, then eighth notes are 20 frames each
+        let bbt = bbt_at(&meter, &jack_timing_info(), 160 + 50);
+        assert_eq!((bbt.bar, bbt.beat, bbt.tick), (2, 3, 240));
+        assert_eq!((bbt.sig_num, bbt.sig_denom), (6.0, 8.0));
+        assert_eq!(bbt.bpm, 120.0);
+    }
+
+    #[test]
+    fn bbt_round_trips_through_transport_following() {
+        let mut project = ProjectState::default();
+        project
+            .time
+            .time_signatures
+            .set_time_signature(2, TimeSignature::new(7, 8).unwrap());
+        let meter = Meter::of(&project);
+        for frame in [0, 79, 80, 161, 333] {
+            let bbt = bbt_at(&meter, &jack_timing_info(), frame);
+            let position = position_of_bbt(&bbt, &meter.time_signatures);
+            assert_eq!(
+                position,
+                jack_timing_info().position_of_frame(&meter.timeline, frame)
+            );
+        }
+    }
+}
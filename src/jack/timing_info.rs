@@ -1,15 +1,10 @@
-use std::ops::{Add, Sub};
-
-
 use jack::Frames;
 
 use crate::{
-    data_types::tatum::{self},
-    model::project_time_info::ProjectTimeInfo,
+    data_types::musical_position::{MusicalPosition, TICKS_PER_TATUM},
+    model::project_time_info::SongTime,
 };
 
-use super::sequence_translation::FrameOffset;
-
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
 pub(crate) struct FramesPerSecond(usize);
 
@@ -19,144 +14,287 @@ impl From<usize> for FramesPerSecond {
     }
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
-pub(crate) struct FramesPerBar(Frames);
-
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
-pub(crate) struct FramesPerBeat(Frames);
-
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
-pub(crate) struct FramesPerTatum(Frames);
-
-impl From<FramesPerTatum> for Frames {
-    fn from(value: FramesPerTatum) -> Self {
+impl From<FramesPerSecond> for usize {
+    fn from(value: FramesPerSecond) -> Self {
         value.0
     }
 }
 
-impl From<FramesPerBar> for Frames {
-    fn from(value: FramesPerBar) -> Self {
+/// Length of a lane's loop, which may be shorter than a bar for polymetric lanes.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub(crate) struct FramesPerLoop(Frames);
+
+impl From<FramesPerLoop> for Frames {
+    fn from(value: FramesPerLoop) -> Self {
         value.0
     }
 }
 
-impl FramesPerBar {
-    pub(crate) fn frames_through_bar(&self, total_frames: &Frames) -> FrameOffset {
-        let frames_through_bar = total_frames.rem_euclid(self.0);
-        FrameOffset::from(frames_through_bar)
+impl FramesPerLoop {
+    /// The last frame of the loop, counting from its start.
+    pub(crate) fn end_of_loop(&self) -> Frames {
+        self.0 - 1
     }
 }
 
-impl Sub<FrameOffset> for FramesPerBar {
-    type Output = FrameOffset;
-
-    fn sub(self, rhs: FrameOffset) -> Self::Output {
-        let rhs_as_number: u32 = rhs.into();
-        if rhs_as_number > self.0 {
-            panic!("Frame offset bigger than frames per bar");
-        }
-        let offset: u32 = self.0 - rhs_as_number;
-        FrameOffset::from(offset)
-    }
+pub(crate) struct TimingInfo {
+    pub(crate) frames_per_second: FramesPerSecond,
 }
 
-impl Add<FrameOffset> for Frames {
-    type Output = Frames;
-
-    fn add(self, rhs: FrameOffset) -> Self::Output {
-        let offset: u32 = rhs.into();
-        self + offset
-    }
+/// One pass through a lane's loop, which lasts longer or shorter as the tempo changes.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct LoopSpan {
+    /// Loops since the start of the song
+    pub(crate) number: u64,
+    pub(crate) start_frame: Frames,
+    pub(crate) length: FramesPerLoop,
 }
 
-impl Sub<FrameOffset> for Frames {
-    type Output = Frames;
-
-    fn sub(self, rhs: FrameOffset) -> Self::Output {
-        let offset: u32 = rhs.into();
-        self - offset
+/// Converts musical positions to frames. Every conversion is made from the start of the song
+/// and rounded once, rather than by adding up rounded lengths of beats or tatums, so the
+/// rounding error is carried along instead of building up: each process cycle, loop and event
+/// lands on the frame nearest its true time however long playback runs.
+impl TimingInfo {
+    /// Frame a position lands on, following the tempo map. Not rounded, so callers can decide
+    /// where the rounding happens.
+    pub fn frame_of_position(&self, time_info: &impl SongTime, position: MusicalPosition) -> f64 {
+        time_info.seconds_at_tatum(position.tatums()) * self.frames_per_second.0 as f64
     }
-}
 
-pub(crate) struct TimingInfo {
-    pub(crate) frames_per_second: FramesPerSecond,
-}
+    /// Nearest frame a position lands on.
+    pub fn nearest_frame(&self, time_info: &impl SongTime, position: MusicalPosition) -> Frames {
+        self.frame_of_position(time_info, position).round() as Frames
+    }
 
-impl TimingInfo {
-    pub fn frames_per_beat(&self, time_info: &ProjectTimeInfo) -> FramesPerBeat {
-        let beats_per_second: f32 = time_info.bpm.beats_per_second().into();
-        FramesPerBeat(((self.frames_per_second.0 as f32) / beats_per_second).round() as u32)
+    /// The last whole tick played by `frame`.
+    pub fn position_of_frame(&self, time_info: &impl SongTime, frame: Frames) -> MusicalPosition {
+        let tatums = time_info.tatum_at_seconds(frame as f64 / self.frames_per_second.0 as f64);
+        MusicalPosition::from_ticks((tatums * TICKS_PER_TATUM as f64).floor() as u64)
     }
 
-    pub fn frames_per_tatum(&self, time_info: &ProjectTimeInfo) -> FramesPerTatum {
-        let frames_per_beat = self.frames_per_beat(time_info);
-        let tatums_per_beat = (tatum::TATUM_SUBDIVDISONS_PER_BAR as u32) / time_info.beats_per_bar;
-        FramesPerTatum(frames_per_beat.0 / tatums_per_beat)
+    fn loop_start_frame(&self, time_info: &impl SongTime, steps: usize, number: u64) -> Frames {
+        self.nearest_frame(
+            time_info,
+            MusicalPosition::from_tatums(number * steps as u64),
+        )
     }
 
-    pub fn frames_per_bar(&self, time_info: &ProjectTimeInfo) -> FramesPerBar {
-        let frames_per_beat = self.frames_per_beat(time_info);
-        FramesPerBar(frames_per_beat.0 * time_info.beats_per_bar)
+    pub fn loop_span(&self, time_info: &impl SongTime, steps: usize, number: u64) -> LoopSpan {
+        let start_frame = self.loop_start_frame(time_info, steps, number);
+        let next_start_frame = self.loop_start_frame(time_info, steps, number + 1);
+        LoopSpan {
+            number,
+            start_frame,
+            length: FramesPerLoop(next_start_frame - start_frame),
+        }
     }
 
-    pub fn frames_end_of_bar(&self, time_info: &ProjectTimeInfo) -> FrameOffset {
-        FrameOffset::from((self.frames_per_beat(time_info).0 * time_info.beats_per_bar) - 1)
+    /// The pass through a `steps` long loop that is playing at `frame`.
+    pub fn loop_at_frame(
+        &self,
+        time_info: &impl SongTime,
+        frame: Frames,
+        steps: usize,
+    ) -> LoopSpan {
+        let position = self.position_of_frame(time_info, frame);
+        let mut number = position.ticks() / (steps as u64 * TICKS_PER_TATUM);
+        // The estimate can be a loop out either side of a rounded boundary
+        if number > 0 && self.loop_start_frame(time_info, steps, number) > frame {
+            number -= 1;
+        }
+        let span = self.loop_span(time_info, steps, number);
+        if span.start_frame + Frames::from(span.length) <= frame {
+            return self.loop_span(time_info, steps, number + 1);
+        }
+        span
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        data_types::beats_per_minute::BeatsPerMinute,
-        jack::{
-            sequence_translation::FrameOffset,
-            timing_info::{FramesPerBeat, FramesPerSecond, FramesPerTatum, TimingInfo},
+        data_types::{beats_per_minute::BeatsPerMinute, musical_position::MusicalPosition},
+        jack::timing_info::{FramesPerLoop, FramesPerSecond, LoopSpan, TimingInfo},
+        model::{
+            project_time_info::ProjectTimeInfo,
+            tempo_map::{TempoChange, TempoCurve, TempoMap},
         },
-        model::project_time_info::ProjectTimeInfo,
     };
 
     #[test]
-    fn test_frames_per_beat() {
+    fn test_frame_of_position() {
         let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
+            tempo: TempoMap::new(BeatsPerMinute::from(120)),
+            ..ProjectTimeInfo::default()
         };
         let jack_timing_info = TimingInfo {
             frames_per_second: FramesPerSecond::from(30),
         };
+        // A quarter note is four tatums
+        let quarter_note = MusicalPosition::from_tatums(4);
         assert_eq!(
-            jack_timing_info.frames_per_beat(&project_time_info),
-            FramesPerBeat(15)
-        )
+            jack_timing_info.frame_of_position(&project_time_info, quarter_note),
+            15.0
+        );
+        assert_eq!(
+            jack_timing_info.position_of_frame(&project_time_info, 15),
+            quarter_note
+        );
+        // Half way through a tatum
+        assert_eq!(
+            jack_timing_info
+                .frame_of_position(&project_time_info, MusicalPosition::from_ticks(120)),
+            1.875
+        );
     }
 
     #[test]
-    fn test_frames_per_tatum() {
+    fn test_frames_at_end_of_bar() {
         let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
+            tempo: TempoMap::new(BeatsPerMinute::from(120)),
+            ..ProjectTimeInfo::default()
         };
         let jack_timing_info = TimingInfo {
             frames_per_second: FramesPerSecond::from(40),
         };
         assert_eq!(
-            jack_timing_info.frames_per_tatum(&project_time_info),
-            FramesPerTatum(5)
-        )
+            jack_timing_info
+                .loop_span(&project_time_info, 16, 0)
+                .length
+                .end_of_loop(),
+            79
+        );
     }
 
     #[test]
-    fn test_frames_at_end_of_bar() {
+    fn test_frames_per_loop() {
         let project_time_info = ProjectTimeInfo {
-            bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
+            tempo: TempoMap::new(BeatsPerMinute::from(120)),
+            ..ProjectTimeInfo::default()
         };
         let jack_timing_info = TimingInfo {
             frames_per_second: FramesPerSecond::from(40),
         };
         assert_eq!(
-            jack_timing_info.frames_end_of_bar(&project_time_info),
-            FrameOffset::from(79)
+            jack_timing_info.loop_span(&project_time_info, 16, 0).length,
+            FramesPerLoop(80)
+        );
+        assert_eq!(
+            jack_timing_info.loop_span(&project_time_info, 12, 0).length,
+            FramesPerLoop(60)
+        );
+        assert_eq!(
+            jack_timing_info.loop_span(&project_time_info, 7, 0).length,
+            FramesPerLoop(35)
+        );
+    }
+
+    #[test]
+    fn test_loop_at_frame_with_constant_tempo() {
+        let project_time_info = ProjectTimeInfo::default();
+        let jack_timing_info = TimingInfo {
+            frames_per_second: FramesPerSecond::from(40),
+        };
+        assert_eq!(
+            jack_timing_info.loop_at_frame(&project_time_info, 159, 16),
+            LoopSpan {
+                number: 1,
+                start_frame: 80,
+                length: FramesPerLoop(80)
+            }
+        );
+        assert_eq!(
+            jack_timing_info
+                .loop_at_frame(&project_time_info, 160, 16)
+                .number,
+            2
         );
     }
+
+    #[test]
+    fn test_loops_lengthen_through_ritardando() {
+        let mut project_time_info = ProjectTimeInfo::default();
+        project_time_info.tempo.set_change(TempoChange {
+            bar: 0,
+            bpm: BeatsPerMinute::from(120),
+            curve: TempoCurve::Linear,
+        });
+        project_time_info.tempo.set_change(TempoChange {
+            bar: 4,
+            bpm: BeatsPerMinute::from(60),
+            curve: TempoCurve::Step,
+        });
+        let jack_timing_info = TimingInfo {
+            frames_per_second: FramesPerSecond::from(48000),
+        };
+        let lengths: Vec<u32> = (0..6)
+            .map(|bar| {
+                let frame = jack_timing_info
+                    .nearest_frame(&project_time_info, MusicalPosition::from_tatums(bar * 16));
+                jack_timing_info
+                    .loop_at_frame(&project_time_info, frame, 16)
+                    .length
+                    .into()
+            })
+            .collect();
+        assert!(lengths.windows(2).take(4).all(|pair| pair[0] < pair[1]));
+        // After the ramp a bar at 60bpm is four seconds
+        assert_eq!(lengths[4], 192000);
+        assert_eq!(lengths[5], 192000);
+    }
+
+    #[test]
+    fn test_loop_spans_tile_frames() {
+        let mut project_time_info = ProjectTimeInfo::default();
+        project_time_info.tempo.set_change(TempoChange {
+            bar: 0,
+            bpm: BeatsPerMinute::from(97),
+            curve: TempoCurve::Curved(2.0),
+        });
+        project_time_info.tempo.set_change(TempoChange {
+            bar: 2,
+            bpm: BeatsPerMinute::from(131),
+            curve: TempoCurve::Step,
+        });
+        let jack_timing_info = TimingInfo {
+            frames_per_second: FramesPerSecond::from(44100),
+        };
+        let mut frame = 0;
+        for expected_number in 0..20 {
+            let span = jack_timing_info.loop_at_frame(&project_time_info, frame, 7);
+            assert_eq!(span.number, expected_number);
+            assert_eq!(span.start_frame, frame);
+            let length: u32 = span.length.into();
+            assert_eq!(
+                jack_timing_info
+                    .loop_at_frame(&project_time_info, frame + length - 1, 7)
+                    .number,
+                expected_number
+            );
+            frame += length;
+        }
+    }
+
+    #[test]
+    fn test_fractional_tempo_does_not_drift() {
+        let bpm = BeatsPerMinute::try_from(97.3).unwrap();
+        let project_time_info = ProjectTimeInfo {
+            tempo: TempoMap::new(bpm),
+            ..ProjectTimeInfo::default()
+        };
+        let jack_timing_info = TimingInfo {
+            frames_per_second: FramesPerSecond::from(44100),
+        };
+        // About seven hours of bars
+        let bars = 10000;
+        let exact_frames = bars as f64 * 4.0 * 60.0 / f32::from(bpm) as f64 * 44100.0;
+        let span =
+            jack_timing_info.loop_at_frame(&project_time_info, exact_frames.round() as u32, 16);
+        assert_eq!(span.number, bars);
+        assert_eq!(span.start_frame, exact_frames.round() as u32);
+        // Bars are a frame longer or shorter as needed to stay on the true tempo
+        let length: u32 = span.length.into();
+        let exact_length = exact_frames / bars as f64;
+        assert!((length as f64 - exact_length).abs() < 1.0);
+    }
 }
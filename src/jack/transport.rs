@@ -0,0 +1,100 @@
+use jack::{Frames, TransportBBT, TransportState};
+
+use crate::{
+    data_types::{
+        musical_position::{MusicalPosition, TICKS_PER_TATUM},
+        tatum::TATUM_SUBDIVDISONS_PER_BAR,
+    },
+    model::time_signature_map::TimeSignatureMap,
+};
+
+/// The JACK transport at the start of a process cycle, which is all the sequencer follows.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) struct TransportCycle {
+    pub rolling: bool,
+    /// Transport frame at the start of the cycle
+    pub frame: Frames,
+    /// Bar, beat and tick from the timebase master, when there is one
+    pub bbt: Option<TransportBBT>,
+}
+
+impl TransportCycle {
+    /// The transport as it is for this cycle. Only safe to call from the process callback, so
+    /// the state holds for the whole cycle.
+    pub fn query(client: &jack::Client) -> TransportCycle {
+        match client.transport().query() {
+            Ok(transport) => TransportCycle {
+                // Starting means waiting for slow clients, which tubular does not need to do
+                rolling: transport.state == TransportState::Rolling,
+                frame: transport.pos.frame(),
+                bbt: transport.pos.bbt(),
+            },
+            Err(_) => TransportCycle {
+                rolling: false,
+                frame: 0,
+                bbt: None,
+            },
+        }
+    }
+}
+
+/// Where `bbt` falls in the song. Bars are counted with the project's time signatures, and
+/// beats with the master's beat unit, so bars line up even if the signatures disagree.
+pub(crate) fn position_of_bbt(
+    bbt: &TransportBBT,
+    time_signatures: &TimeSignatureMap,
+) -> MusicalPosition {
+    let bar_start = time_signatures.bar_start_tatum(bbt.bar.saturating_sub(1) as u32);
+    let ticks_per_beat =
+        TICKS_PER_TATUM as f64 * TATUM_SUBDIVDISONS_PER_BAR as f64 / bbt.sig_denom.max(1.0) as f64;
+    let beats = bbt.beat.saturating_sub(1) as f64 + bbt.tick as f64 / bbt.ticks_per_beat.max(1.0);
+    MusicalPosition::from_ticks(
+        bar_start * TICKS_PER_TATUM + (beats * ticks_per_beat).round() as u64,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use jack::TransportBBT;
+
+    use crate::{
+        data_types::{musical_position::MusicalPosition, time_signature::TimeSignature},
+        jack::transport::position_of_bbt,
+        model::time_signature_map::TimeSignatureMap,
+    };
+
+    fn bbt(bar: usize, beat: usize, tick: usize, sig_denom: f32) -> TransportBBT {
+        TransportBBT {
+            bar,
+            beat,
+            tick,
+            sig_denom,
+            ticks_per_beat: 1920.0,
+            ..TransportBBT::default()
+        }
+    }
+
+    #[test]
+    fn bbt_counts_from_one() {
+        let time_signatures = TimeSignatureMap::default();
+        assert_eq!(
+            position_of_bbt(&bbt(1, 1, 0, 4.0), &time_signatures),
+            MusicalPosition::from_tatums(0)
+        );
+        assert_eq!(
+            position_of_bbt(&bbt(3, 2, 960, 4.0), &time_signatures),
+            MusicalPosition::from_tatums(38)
+        );
+    }
+
+    #[test]
+    fn bbt_follows_project_time_signatures() {
+        let mut time_signatures = TimeSignatureMap::default();
+        time_signatures.set_time_signature(1, TimeSignature::new(6, 8).unwrap());
+        // Bar 3 starts after a bar of 4/4 and a bar of 6/8, and beats are eighths
+        assert_eq!(
+            position_of_bbt(&bbt(3, 3, 0, 8.0), &time_signatures),
+            MusicalPosition::from_tatums(16 + 12 + 4)
+        );
+    }
+}
@@ -2,14 +2,26 @@ use std::{
     cell::RefCell,
     rc::Rc,
     sync::{Arc, RwLock},
+    time::{Duration, Instant},
 };
 
-use ::jack::AsyncClient;
-use jack::jack_processor::JackProcessor;
-use model::{gui_state::GuiState, make_application_state, project_state::ProjectState};
-use view_model::chord_sequencer_vm::ChordSequencerVm;
+use jack::{
+    backend::Backend,
+    jack_backend::JackBackend,
+    key_queue::{key_queue, KeyReceiver},
+    offline_backend::OfflineBackend,
+    schedule::{schedule_channel, ScheduleSender},
+    timebase::TimebaseMaster,
+    timing_info::{FramesPerSecond, TimingInfo},
+};
+use model::{
+    gui_state::GuiState, make_application_state, project_state::ProjectState,
+    recording::RecordMode, transport_request::TransportRequest,
+};
+use view_model::{chord_sequencer_vm::ChordSequencerVm, melody_vm::MelodyVm};
 
 pub mod data_types;
+pub mod generators;
 pub mod jack;
 pub mod model;
 pub mod music_theory;
@@ -20,7 +32,15 @@ struct TubularApp {
     project_state: Arc<RwLock<ProjectState>>,
     gui_state: Rc<RefCell<GuiState>>,
     chord_sequencer_vm: ChordSequencerVm,
-    jack_client: Option<AsyncClient<(), JackProcessor>>,
+    melody_vm: MelodyVm,
+    schedule_sender: ScheduleSender,
+    /// Keys from the keyboard input, for recording
+    keys: KeyReceiver,
+    backend: Option<Box<dyn Backend>>,
+    /// When the backend started, for backends that keep time by the wall clock
+    started: Instant,
+    /// Held while the timebase master box is ticked
+    timebase_master: Option<TimebaseMaster>,
 }
 
 impl TubularApp {
@@ -32,26 +52,126 @@ impl TubularApp {
 
         let chord_sequencer_vm =
             ChordSequencerVm::new(gui_state_pointer.clone(), project_state_pointer.clone());
+        let melody_vm = MelodyVm::new(gui_state_pointer.clone(), project_state_pointer.clone());
 
-        let jack_client = JackProcessor::activate_async(project_state_pointer.clone());
+        let (mut schedule_sender, schedule) =
+            schedule_channel(&project_state_pointer.read().unwrap());
+        let playhead = project_state_pointer.read().unwrap().playhead.clone();
+        // Far more than can be played between two frames of the GUI
+        let (key_sender, mut keys) = key_queue(1024);
+        let backend: Box<dyn Backend> =
+            match JackBackend::activate(schedule, playhead.clone(), key_sender) {
+                Ok(backend) => Box::new(backend),
+                Err(message) => {
+                    // Without a JACK server the project can still be edited and played
+                    // silently, with the engine on a virtual clock
+                    gui_state_pointer.borrow_mut().status_message = Some(message.to_string());
+                    let schedule;
+                    (schedule_sender, schedule) =
+                        schedule_channel(&project_state_pointer.read().unwrap());
+                    let key_sender;
+                    (key_sender, keys) = key_queue(1024);
+                    Box::new(OfflineBackend::new(
+                        schedule,
+                        playhead,
+                        key_sender,
+                        FramesPerSecond::from(48000),
+                        256,
+                    ))
+                }
+            };
 
         TubularApp {
             project_state: project_state_pointer,
             gui_state: gui_state_pointer,
             chord_sequencer_vm,
-            jack_client: Some(jack_client),
+            melody_vm,
+            schedule_sender,
+            keys,
+            backend: Some(backend),
+            started: Instant::now(),
+            timebase_master: None,
+        }
+    }
+
+    /// Make the transport changes asked for through the views.
+    fn drive_transport(&mut self) {
+        let Some(backend) = &mut self.backend else {
+            return;
+        };
+        let project = self.project_state.read().unwrap();
+        let requests = std::mem::take(&mut self.gui_state.as_ref().borrow_mut().transport_requests);
+        for request in requests {
+            let result = match request {
+                TransportRequest::Start => backend.start_transport(),
+                TransportRequest::Stop => backend.stop_transport(),
+                TransportRequest::Locate(position) => {
+                    let jack_timing_info = TimingInfo {
+                        frames_per_second: backend.frames_per_second(),
+                    };
+                    backend.locate(jack_timing_info.nearest_frame(&project.time, position))
+                }
+            };
+            if let Err(message) = result {
+                self.gui_state.as_ref().borrow_mut().status_message = Some(message.to_string());
+            }
+        }
+    }
+
+    /// Take or release the timebase as the GUI asks, and keep the master's tempo map current.
+    fn update_timebase_master(&mut self) {
+        let Some(client) = self
+            .backend
+            .as_ref()
+            .and_then(|backend| backend.jack_client())
+        else {
+            return;
+        };
+        let wanted = self.gui_state.as_ref().borrow().timebase_master;
+        let project = self.project_state.read().unwrap();
+        match (&mut self.timebase_master, wanted) {
+            (Some(master), true) => master.update(&project),
+            (Some(_), false) => self.timebase_master = None,
+            (None, true) => match TimebaseMaster::take(client, &project) {
+                Ok(master) => self.timebase_master = Some(master),
+                Err(message) => {
+                    let mut gui_state = self.gui_state.as_ref().borrow_mut();
+                    gui_state.status_message = Some(message.to_string());
+                    gui_state.timebase_master = false;
+                }
+            },
+            (None, false) => {}
         }
     }
 }
 
 impl eframe::App for TubularApp {
     fn update(&mut self, ctx: &eframe::egui::Context, frame: &mut eframe::Frame) {
+        while let Some(key) = self.keys.receive() {
+            self.chord_sequencer_vm.record_key(key);
+        }
+        // Keys arrive without the GUI being touched, so keep looking for them while recording
+        if self.gui_state.as_ref().borrow().record_mode != RecordMode::Off {
+            ctx.request_repaint_after(Duration::from_millis(20));
+        }
+        // Panels go before the chord sequencer's central panel
+        view::melody_roll::update(&mut self.melody_vm, ctx, frame);
         view::chord_sequencer::update(&mut self.chord_sequencer_vm, ctx, frame);
+        // Edits all come through the views, so the engine is sent them once they are done
+        self.schedule_sender
+            .update(&self.project_state.read().unwrap());
+        self.update_timebase_master();
+        self.drive_transport();
+        if let Some(backend) = &mut self.backend {
+            backend.catch_up(self.started.elapsed());
+        }
     }
 
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
-        let jack_client = std::mem::take(&mut self.jack_client);
-        jack_client.unwrap().deactivate().unwrap();
+        // The timebase callback goes before the client it is registered with
+        self.timebase_master = None;
+        // Stops the engine, deactivating the JACK client if there is one
+        self.backend = None;
     }
 }
 
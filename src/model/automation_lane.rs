@@ -0,0 +1,236 @@
+use std::fmt;
+
+use crate::data_types::{
+    musical_position::{MusicalPosition, TICKS_PER_TATUM},
+    tatum::MAX_TATUMS_PER_LANE,
+};
+
+/// Interpolated values are sent eight times a tatum, fine enough for smooth sweeps without
+/// flooding the port.
+const INTERPOLATION_TICKS: u64 = TICKS_PER_TATUM / 8;
+/// Control numbers above this are channel mode messages, not controllers
+pub(crate) const MAX_CONTROLLER: u8 = 119;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) enum AutomationTarget {
+    ControlChange(u8),
+    PitchBend,
+    ChannelPressure,
+}
+
+impl AutomationTarget {
+    /// Controller 74 is filter cutoff on most synths, the usual thing to sweep
+    pub(crate) const ALL: [AutomationTarget; 3] = [
+        AutomationTarget::ControlChange(74),
+        AutomationTarget::PitchBend,
+        AutomationTarget::ChannelPressure,
+    ];
+
+    pub fn max_value(&self) -> u16 {
+        match self {
+            AutomationTarget::PitchBend => 16383,
+            _ => 127,
+        }
+    }
+
+    /// Where a new point starts, which for pitch bend is no bend at all.
+    pub fn default_value(&self) -> u16 {
+        match self {
+            AutomationTarget::ControlChange(_) => 64,
+            AutomationTarget::PitchBend => 8192,
+            AutomationTarget::ChannelPressure => 0,
+        }
+    }
+
+    fn is_same_kind(&self, other: &AutomationTarget) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+impl fmt::Display for AutomationTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AutomationTarget::ControlChange(controller) => write!(f, "CC {}", controller),
+            AutomationTarget::PitchBend => write!(f, "pitch bend"),
+            AutomationTarget::ChannelPressure => write!(f, "pressure"),
+        }
+    }
+}
+
+/// Values for one controller of a track, set per step. Steps without a value carry on from
+/// the step before, either holding or ramping towards the next value.
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct AutomationLane {
+    /// The chord lane this automates, and loops with
+    pub track: usize,
+    target: AutomationTarget,
+    // Long enough for any lane, so the track can change length without losing points
+    points: [Option<u16>; MAX_TATUMS_PER_LANE],
+    pub interpolate: bool,
+}
+
+impl AutomationLane {
+    pub fn new(track: usize, target: AutomationTarget) -> AutomationLane {
+        AutomationLane {
+            track,
+            target,
+            points: [None; MAX_TATUMS_PER_LANE],
+            interpolate: true,
+        }
+    }
+
+    pub fn target(&self) -> AutomationTarget {
+        self.target
+    }
+
+    /// Change what is automated. Points are kept when the range is the same, and cleared when
+    /// they would mean something else.
+    pub fn set_target(&mut self, target: AutomationTarget) {
+        if !self.target.is_same_kind(&target) {
+            self.points = [None; MAX_TATUMS_PER_LANE];
+        }
+        self.target = target;
+    }
+
+    pub fn point(&self, step: usize) -> Option<u16> {
+        self.points[step]
+    }
+
+    pub fn set_point(&mut self, step: usize, value: Option<u16>) -> Result<(), &'static str> {
+        if step >= MAX_TATUMS_PER_LANE {
+            return Err("Step past the end of the lane");
+        }
+        if value.is_some_and(|v| v > self.target.max_value()) {
+            return Err("Automation value out of range");
+        }
+        self.points[step] = value;
+        Ok(())
+    }
+
+    /// The values to send through one pass of a loop `steps` long, as positions from the start
+    /// of the loop. Only changes are included, apart from the value the loop starts on.
+    pub fn values_for_loop(&self, steps: usize) -> Vec<(MusicalPosition, u16)> {
+        let points: Vec<(u64, u16)> = self.points[..steps]
+            .iter()
+            .enumerate()
+            .filter_map(|(step, value)| value.map(|v| (step as u64 * TICKS_PER_TATUM, v)))
+            .collect();
+        let (Some(&first), Some(&last)) = (points.first(), points.last()) else {
+            return vec![];
+        };
+        let loop_ticks = steps as u64 * TICKS_PER_TATUM;
+        let sample_ticks = if self.interpolate {
+            INTERPOLATION_TICKS
+        } else {
+            TICKS_PER_TATUM
+        };
+
+        let mut values: Vec<(MusicalPosition, u16)> = vec![];
+        for tick in (0..loop_ticks).step_by(sample_ticks as usize) {
+            // The loop wraps, so before the first point comes from the last point of the loop
+            // before, and after the last point heads to the first point of the next
+            let next_index = points.partition_point(|(point_tick, _)| *point_tick <= tick);
+            let (previous_tick, previous_value) = match next_index {
+                0 => (last.0 as i64 - loop_ticks as i64, last.1),
+                index => (points[index - 1].0 as i64, points[index - 1].1),
+            };
+            let value = if self.interpolate {
+                let (next_tick, next_value) = match points.get(next_index) {
+                    Some(&(point_tick, value)) => (point_tick as i64, value),
+                    None => ((first.0 + loop_ticks) as i64, first.1),
+                };
+                let progress =
+                    (tick as i64 - previous_tick) as f64 / (next_tick - previous_tick) as f64;
+                (previous_value as f64 + (next_value as f64 - previous_value as f64) * progress)
+                    .round() as u16
+            } else {
+                previous_value
+            };
+            if values.last().map(|(_, v)| *v) != Some(value) {
+                values.push((MusicalPosition::from_ticks(tick), value));
+            }
+        }
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::musical_position::MusicalPosition,
+        model::automation_lane::{AutomationLane, AutomationTarget},
+    };
+
+    fn at_tatum(tatum: u64, value: u16) -> (MusicalPosition, u16) {
+        (MusicalPosition::from_tatums(tatum), value)
+    }
+
+    #[test]
+    fn empty_lane_sends_nothing() {
+        let lane = AutomationLane::new(0, AutomationTarget::ChannelPressure);
+        assert!(lane.values_for_loop(16).is_empty());
+    }
+
+    #[test]
+    fn stepped_values_hold_until_next_point() {
+        let mut lane = AutomationLane::new(0, AutomationTarget::ControlChange(74));
+        lane.interpolate = false;
+        lane.set_point(0, Some(10)).unwrap();
+        lane.set_point(2, Some(10)).unwrap();
+        lane.set_point(3, Some(100)).unwrap();
+        assert_eq!(
+            lane.values_for_loop(4),
+            vec![at_tatum(0, 10), at_tatum(3, 100)]
+        );
+    }
+
+    #[test]
+    fn interpolates_between_points() {
+        let mut lane = AutomationLane::new(0, AutomationTarget::ControlChange(74));
+        lane.set_point(0, Some(0)).unwrap();
+        lane.set_point(2, Some(16)).unwrap();
+        let values = lane.values_for_loop(4);
+        // Ramps up one value every eighth of a tatum over two tatums
+        assert_eq!(values[0], at_tatum(0, 0));
+        assert_eq!(values[8], at_tatum(1, 8));
+        assert_eq!(values[16], at_tatum(2, 16));
+        // Then back down towards the first point of the next loop
+        assert_eq!(values.last().unwrap().1, 1);
+    }
+
+    #[test]
+    fn wraps_from_last_point_before_first() {
+        let mut lane = AutomationLane::new(0, AutomationTarget::ChannelPressure);
+        lane.set_point(2, Some(100)).unwrap();
+        lane.set_point(3, Some(0)).unwrap();
+        let values = lane.values_for_loop(4);
+        // A third of the way from the last point of the previous loop to the first
+        assert_eq!(values[0], at_tatum(0, 33));
+    }
+
+    #[test]
+    fn points_past_end_of_short_loop_are_ignored() {
+        let mut lane = AutomationLane::new(0, AutomationTarget::ChannelPressure);
+        lane.interpolate = false;
+        lane.set_point(1, Some(5)).unwrap();
+        lane.set_point(6, Some(99)).unwrap();
+        assert_eq!(lane.values_for_loop(4), vec![at_tatum(0, 5)]);
+    }
+
+    #[test]
+    fn values_are_limited_by_target() {
+        let mut lane = AutomationLane::new(0, AutomationTarget::PitchBend);
+        assert!(lane.set_point(0, Some(16383)).is_ok());
+        lane.set_target(AutomationTarget::ChannelPressure);
+        assert_eq!(lane.point(0), None);
+        assert!(lane.set_point(0, Some(128)).is_err());
+    }
+
+    #[test]
+    fn changing_controller_keeps_points() {
+        let mut lane = AutomationLane::new(0, AutomationTarget::ControlChange(74));
+        lane.set_point(0, Some(30)).unwrap();
+        lane.set_target(AutomationTarget::ControlChange(1));
+        assert_eq!(lane.point(0), Some(30));
+    }
+}
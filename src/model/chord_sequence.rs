@@ -5,19 +5,25 @@ use std::{
 
 use crate::data_types::{
     chord_degree::ChordDegree,
-    tatum::{self, Tatum, TATUM_SUBDIVDISONS_PER_BAR},
+    tatum::{self, Tatum, MAX_TATUMS_PER_LANE},
+};
+
+use super::{
+    clip::{Clip, ClipStep},
+    step_condition::StepCondition,
+    step_timing::StepTiming,
 };
 
 #[derive(Clone, PartialEq, Debug)]
 pub(crate) struct ChordSequence {
     chords: Vec<Option<ChordDegree>>,
+    conditions: Vec<StepCondition>,
+    timings: Vec<StepTiming>,
 }
 
 impl Default for ChordSequence {
     fn default() -> Self {
-        Self {
-            chords: vec![None; tatum::TATUM_SUBDIVDISONS_PER_BAR],
-        }
+        ChordSequence::with_steps(tatum::TATUM_SUBDIVDISONS_PER_BAR).unwrap()
     }
 }
 
@@ -25,12 +31,17 @@ impl ChordSequence {
     // Can we use https://doc.rust-lang.org/beta/nightly-rustc/rustc_index/macro.newtype_index.html
     // with https://doc.rust-lang.org/beta/nightly-rustc/rustc_index/vec/struct.IndexVec.html
 
+    #[cfg(test)]
     pub fn new(chords: Vec<Option<ChordDegree>>) -> Result<ChordSequence, &'static str> {
-        if chords.len() > TATUM_SUBDIVDISONS_PER_BAR {
+        if chords.len() > tatum::TATUM_SUBDIVDISONS_PER_BAR {
             return Err("Invalid chord sequence");
         }
-        if chords.len() == TATUM_SUBDIVDISONS_PER_BAR {
-            Ok(ChordSequence { chords })
+        if chords.len() == tatum::TATUM_SUBDIVDISONS_PER_BAR {
+            Ok(ChordSequence {
+                conditions: vec![StepCondition::Always; chords.len()],
+                timings: vec![StepTiming::default(); chords.len()],
+                chords,
+            })
         } else {
             let mut chord_sequence = ChordSequence::default();
             for (index, &c) in chords.iter().enumerate() {
@@ -40,9 +51,99 @@ impl ChordSequence {
         }
     }
 
-    pub fn iter(&self) -> Iter<Option<ChordDegree>> {
+    /// Make an empty sequence that loops after `steps` tatums rather than a whole bar.
+    pub fn with_steps(steps: usize) -> Result<ChordSequence, &'static str> {
+        if steps == 0 || steps > MAX_TATUMS_PER_LANE {
+            return Err("Invalid number of steps");
+        }
+        Ok(ChordSequence {
+            chords: vec![None; steps],
+            conditions: vec![StepCondition::Always; steps],
+            timings: vec![StepTiming::default(); steps],
+        })
+    }
+
+    pub fn steps(&self) -> usize {
+        self.chords.len()
+    }
+
+    /// Change how many tatums the sequence loops over, dropping chords past the new end.
+    pub fn set_steps(&mut self, steps: usize) -> Result<(), &'static str> {
+        if steps == 0 || steps > MAX_TATUMS_PER_LANE {
+            return Err("Invalid number of steps");
+        }
+        self.chords.resize(steps, None);
+        self.conditions.resize(steps, StepCondition::Always);
+        self.timings.resize(steps, StepTiming::default());
+        Ok(())
+    }
+
+    pub fn iter(&self) -> Iter<'_, Option<ChordDegree>> {
         self.chords.iter()
     }
+
+    /// Copy the steps from `start` to `end` inclusive.
+    pub fn clip(&self, start: Tatum, end: Tatum) -> Clip {
+        let steps = (usize::from(start)..=usize::from(end))
+            .map(|step| ClipStep {
+                chord: self.chords[step],
+                condition: self.conditions[step],
+                timing: self.timings[step],
+            })
+            .collect();
+        Clip::new(steps)
+    }
+
+    /// Paste a clip over the steps from `at`, cutting it short at the end of the lane. Returns
+    /// how many steps were pasted.
+    pub fn paste(&mut self, at: Tatum, clip: &Clip) -> usize {
+        let start = usize::from(at);
+        let pasted = clip.steps().len().min(self.steps() - start);
+        for (offset, step) in clip.steps().iter().take(pasted).enumerate() {
+            self.chords[start + offset] = step.chord;
+            self.conditions[start + offset] = step.condition;
+            self.timings[start + offset] = step.timing;
+        }
+        pasted
+    }
+
+    /// Empty the steps from `start` to `end` inclusive.
+    pub fn clear(&mut self, start: Tatum, end: Tatum) {
+        for step in usize::from(start)..=usize::from(end) {
+            self.chords[step] = None;
+            self.conditions[step] = StepCondition::Always;
+            self.timings[step] = StepTiming::default();
+        }
+    }
+
+    /// The chord at `step`, or the last one before it, as the harmony a melody there sits over.
+    pub fn latest_chord_at(&self, step: usize) -> Option<&ChordDegree> {
+        self.chords
+            .iter()
+            .take(step + 1)
+            .rev()
+            .find_map(|chord| chord.as_ref())
+    }
+
+    pub fn condition(&self, step: Tatum) -> StepCondition {
+        self.conditions[usize::from(step)]
+    }
+
+    pub fn set_condition(&mut self, step: Tatum, condition: StepCondition) {
+        self.conditions[usize::from(step)] = condition;
+    }
+
+    pub fn timing(&self, step: Tatum) -> StepTiming {
+        self.timings[usize::from(step)]
+    }
+
+    pub fn set_timing(&mut self, step: Tatum, timing: StepTiming) {
+        self.timings[usize::from(step)] = timing;
+    }
+
+    pub fn conditions(&self) -> &[StepCondition] {
+        &self.conditions
+    }
 }
 
 impl Index<Tatum> for ChordSequence {
@@ -65,9 +166,69 @@ impl IndexMut<Tatum> for ChordSequence {
 mod tests {
     use crate::{
         data_types::{chord_degree::ChordDegree, tatum::Tatum},
-        model::chord_sequence::ChordSequence,
+        model::{
+            chord_sequence::ChordSequence, step_condition::StepCondition, step_timing::StepTiming,
+        },
     };
 
+    #[test]
+    fn clip_and_paste_carry_conditions() {
+        let mut sequence =
+            ChordSequence::new(vec![Some(ChordDegree::I), None, Some(ChordDegree::V)]).unwrap();
+        sequence.set_condition(Tatum::try_from(2).unwrap(), StepCondition::Fill);
+        let clip = sequence.clip(Tatum::try_from(1).unwrap(), Tatum::try_from(2).unwrap());
+        assert_eq!(sequence.paste(Tatum::try_from(8).unwrap(), &clip), 2);
+        assert_eq!(sequence[Tatum::try_from(9).unwrap()], Some(ChordDegree::V));
+        assert_eq!(
+            sequence.condition(Tatum::try_from(9).unwrap()),
+            StepCondition::Fill
+        );
+    }
+
+    #[test]
+    fn paste_stops_at_end_of_lane() {
+        let mut sequence = ChordSequence::with_steps(4).unwrap();
+        let clip = "I II III".parse().unwrap();
+        assert_eq!(sequence.paste(Tatum::try_from(2).unwrap(), &clip), 2);
+        assert_eq!(
+            sequence
+                .clip(Tatum::try_from(0).unwrap(), Tatum::try_from(3).unwrap())
+                .to_string(),
+            ". . I II"
+        );
+    }
+
+    #[test]
+    fn clear_empties_range() {
+        let mut sequence = ChordSequence::new(vec![Some(ChordDegree::I); 4]).unwrap();
+        sequence.set_condition(Tatum::try_from(1).unwrap(), StepCondition::Fill);
+        sequence.clear(Tatum::try_from(1).unwrap(), Tatum::try_from(2).unwrap());
+        assert_eq!(
+            sequence
+                .clip(Tatum::try_from(0).unwrap(), Tatum::try_from(3).unwrap())
+                .to_string(),
+            "I . . I"
+        );
+        assert_eq!(
+            sequence.condition(Tatum::try_from(1).unwrap()),
+            StepCondition::Always
+        );
+    }
+
+    #[test]
+    fn latest_chord_at_looks_back() {
+        let sequence = ChordSequence::new(vec![
+            None,
+            Some(ChordDegree::IV),
+            None,
+            Some(ChordDegree::V),
+        ])
+        .unwrap();
+        assert_eq!(sequence.latest_chord_at(0), None);
+        assert_eq!(sequence.latest_chord_at(2), Some(&ChordDegree::IV));
+        assert_eq!(sequence.latest_chord_at(3), Some(&ChordDegree::V));
+    }
+
     #[test]
     fn new_chord_sequence_from_too_long_array() {
         assert!(ChordSequence::new(Vec::from([None; 17])).is_err());
@@ -100,10 +261,37 @@ mod tests {
         assert_eq!(ChordSequence::default().chords.len(), 16);
     }
 
+    #[test]
+    fn make_chord_sequence_with_fewer_steps() {
+        assert_eq!(ChordSequence::with_steps(7).unwrap().steps(), 7);
+    }
+
+    #[test]
+    fn make_chord_sequence_with_invalid_steps() {
+        assert!(ChordSequence::with_steps(0).is_err());
+        assert!(ChordSequence::with_steps(33).is_err());
+    }
+
+    #[test]
+    fn make_chord_sequence_for_a_bar_of_twelve_eight() {
+        assert_eq!(ChordSequence::with_steps(24).unwrap().steps(), 24);
+    }
+
+    #[test]
+    fn shrinking_sequence_drops_trailing_chords() {
+        let mut sequence = ChordSequence::new(Vec::from([Some(ChordDegree::II); 16])).unwrap();
+        sequence.set_steps(12).unwrap();
+        assert_eq!(sequence.chords, Vec::from([Some(ChordDegree::II); 12]));
+        sequence.set_steps(13).unwrap();
+        assert_eq!(sequence.chords[12], None);
+    }
+
     #[test]
     fn get_chord_from_sequence() {
         let sequence = ChordSequence {
             chords: vec![Some(ChordDegree::I)],
+            conditions: vec![StepCondition::Always],
+            timings: vec![StepTiming::default()],
         };
         assert_eq!(sequence[Tatum::try_from(0).unwrap()], Some(ChordDegree::I));
     }
@@ -114,4 +302,16 @@ mod tests {
         sequence[Tatum::try_from(0).unwrap()] = Some(ChordDegree::I);
         assert_eq!(sequence.chords[0], Some(ChordDegree::I));
     }
+
+    #[test]
+    fn shrinking_sequence_drops_trailing_conditions() {
+        let mut sequence = ChordSequence::default();
+        sequence.set_condition(Tatum::try_from(15).unwrap(), StepCondition::Fill);
+        sequence.set_steps(8).unwrap();
+        sequence.set_steps(16).unwrap();
+        assert_eq!(
+            sequence.condition(Tatum::try_from(15).unwrap()),
+            StepCondition::Always
+        );
+    }
 }
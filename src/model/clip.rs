@@ -0,0 +1,146 @@
+use std::{fmt, str::FromStr};
+
+use crate::data_types::chord_degree::ChordDegree;
+
+use super::{step_condition::StepCondition, step_timing::StepTiming};
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) struct ClipStep {
+    pub chord: Option<ChordDegree>,
+    pub condition: StepCondition,
+    pub timing: StepTiming,
+}
+
+/// Steps copied out of a lane, to paste into any lane or share as text. The text is the
+/// progression as it reads on the grid, e.g. "I . IV V?1:2", so it can be pasted into a chat
+/// and back. Ratchets and nudges follow the chord, as in "Vx2-30".
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct Clip {
+    steps: Vec<ClipStep>,
+}
+
+impl Clip {
+    pub fn new(steps: Vec<ClipStep>) -> Clip {
+        Clip { steps }
+    }
+
+    pub fn steps(&self) -> &[ClipStep] {
+        &self.steps
+    }
+}
+
+impl fmt::Display for Clip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tokens: Vec<String> = self
+            .steps
+            .iter()
+            .map(|step| {
+                let mut token = match step.chord {
+                    Some(chord) => chord.to_string(),
+                    None => ".".to_string(),
+                };
+                if step.chord.is_some() {
+                    token.push_str(&step.timing.to_string());
+                }
+                if step.chord.is_some() && step.condition.is_conditional() {
+                    token.push_str(&format!("?{}", step.condition));
+                }
+                token
+            })
+            .collect();
+        write!(f, "{}", tokens.join(" "))
+    }
+}
+
+impl FromStr for Clip {
+    type Err = &'static str;
+
+    /// Bar lines are allowed, to make longer progressions easier to write, but are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let steps = s
+            .split_whitespace()
+            .filter(|token| *token != "|")
+            .map(|token| {
+                let (chord, condition) = match token.split_once('?') {
+                    Some((chord, condition)) => (chord, condition.parse()?),
+                    None => (token, StepCondition::Always),
+                };
+                let (chord, timing) = match chord {
+                    "." | "-" => (None, StepTiming::default()),
+                    chord => {
+                        let (chord, timing) =
+                            chord.split_at(chord.find(['x', '+', '-']).unwrap_or(chord.len()));
+                        (Some(chord.parse()?), timing.parse()?)
+                    }
+                };
+                Ok(ClipStep {
+                    chord,
+                    condition,
+                    timing,
+                })
+            })
+            .collect::<Result<Vec<ClipStep>, &'static str>>()?;
+        if steps.is_empty() {
+            return Err("Nothing to paste");
+        }
+        Ok(Clip { steps })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::chord_degree::ChordDegree,
+        model::{
+            clip::{Clip, ClipStep},
+            step_condition::StepCondition,
+            step_timing::StepTiming,
+        },
+    };
+
+    fn step(chord: Option<ChordDegree>, condition: StepCondition) -> ClipStep {
+        ClipStep {
+            chord,
+            condition,
+            timing: StepTiming::default(),
+        }
+    }
+
+    #[test]
+    fn clip_reads_like_the_grid() {
+        let clip = Clip::new(vec![
+            step(Some(ChordDegree::I), StepCondition::Always),
+            step(None, StepCondition::Fill),
+            step(Some(ChordDegree::IV), StepCondition::NotFirstLoop),
+        ]);
+        assert_eq!(clip.to_string(), "I . IV?!1st");
+    }
+
+    #[test]
+    fn clip_round_trips_text() {
+        let text = "I . VI?1:2 V";
+        assert_eq!(text.parse::<Clip>().unwrap().to_string(), text);
+    }
+
+    #[test]
+    fn clip_round_trips_timing() {
+        let text = "Vx3 . IV-30?fill iix2+60";
+        assert_eq!(
+            text.parse::<Clip>().unwrap().to_string(),
+            "Vx3 . IV-30?fill IIx2+60"
+        );
+    }
+
+    #[test]
+    fn parse_ignores_bar_lines_and_case() {
+        let clip: Clip = "ii - | v I".parse().unwrap();
+        assert_eq!(clip.to_string(), "II . V I");
+    }
+
+    #[test]
+    fn parse_rejects_nonsense() {
+        assert!("I VIII".parse::<Clip>().is_err());
+        assert!("I?often".parse::<Clip>().is_err());
+        assert!("  ".parse::<Clip>().is_err());
+    }
+}
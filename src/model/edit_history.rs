@@ -0,0 +1,253 @@
+use std::{cell::RefCell, collections::VecDeque, sync::RwLock};
+
+use super::{
+    automation_lane::AutomationLane, chord_sequence::ChordSequence, gui_state::GuiState,
+    melody_lane::MelodyLane, output_settings::OutputSettings, project_state::ProjectState,
+    project_time_info::ProjectTimeInfo,
+};
+
+/// Edits kept for undo, oldest dropped first
+pub(crate) const HISTORY_LIMIT: usize = 100;
+
+/// The parts of the project that edits change. Generated takes are a log of what was
+/// generated rather than part of the song, and fill is only held while performing, so undo
+/// leaves both alone.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct ProjectSnapshot {
+    lanes: Vec<ChordSequence>,
+    scenes: Vec<Vec<ChordSequence>>,
+    scene: usize,
+    time: ProjectTimeInfo,
+    melody: MelodyLane,
+    automation: Vec<AutomationLane>,
+    chord_output: OutputSettings,
+    melody_output: OutputSettings,
+}
+
+impl ProjectSnapshot {
+    pub fn of(project: &ProjectState) -> ProjectSnapshot {
+        ProjectSnapshot {
+            lanes: project.lanes.clone(),
+            scenes: project.scenes.clone(),
+            scene: project.scene,
+            time: project.time.clone(),
+            melody: project.melody.clone(),
+            automation: project.automation.clone(),
+            chord_output: project.chord_output,
+            melody_output: project.melody_output,
+        }
+    }
+
+    pub fn restore(self, project: &mut ProjectState) {
+        project.lanes = self.lanes;
+        project.scenes = self.scenes;
+        project.scene = self.scene;
+        project.time = self.time;
+        project.melody = self.melody;
+        project.automation = self.automation;
+        project.chord_output = self.chord_output;
+        project.melody_output = self.melody_output;
+    }
+}
+
+/// Change the project shared with the engine through the GUI's edit history, so the change
+/// can be undone. The project stays locked for the whole edit.
+pub(crate) fn edit_project<T>(
+    project_state: &RwLock<ProjectState>,
+    gui_state: &RefCell<GuiState>,
+    description: &'static str,
+    grouped: bool,
+    edit: impl FnOnce(&mut ProjectState) -> T,
+) -> T {
+    let mut project_state = project_state.write().unwrap();
+    gui_state
+        .borrow_mut()
+        .history
+        .apply(&mut project_state, description, grouped, edit)
+}
+
+/// A reversible change, recorded as the project before and after it.
+#[derive(Clone, Debug)]
+struct Edit {
+    description: &'static str,
+    grouped: bool,
+    before: ProjectSnapshot,
+    after: ProjectSnapshot,
+}
+
+/// Undo and redo stacks for changes made to the project from the GUI.
+#[derive(Default, Debug)]
+pub(crate) struct EditHistory {
+    undo: VecDeque<Edit>,
+    redo: Vec<Edit>,
+    /// Whether another grouped edit with the same description joins the last one
+    group_open: bool,
+}
+
+impl EditHistory {
+    /// Make a change to the project, recording it if anything changed. Grouped edits made one
+    /// after another with the same description undo together, such as each frame of dragging
+    /// a value, until the group is ended.
+    pub fn apply<T>(
+        &mut self,
+        project: &mut ProjectState,
+        description: &'static str,
+        grouped: bool,
+        edit: impl FnOnce(&mut ProjectState) -> T,
+    ) -> T {
+        let before = ProjectSnapshot::of(project);
+        let result = edit(project);
+        let after = ProjectSnapshot::of(project);
+        if before == after {
+            return result;
+        }
+        self.redo.clear();
+        match self.undo.back_mut() {
+            Some(last)
+                if grouped
+                    && self.group_open
+                    && last.grouped
+                    && last.description == description =>
+            {
+                last.after = after;
+            }
+            _ => {
+                self.undo.push_back(Edit {
+                    description,
+                    grouped,
+                    before,
+                    after,
+                });
+                if self.undo.len() > HISTORY_LIMIT {
+                    self.undo.pop_front();
+                }
+            }
+        }
+        self.group_open = grouped;
+        result
+    }
+
+    /// Stop further edits joining the last one.
+    pub fn end_group(&mut self) {
+        self.group_open = false;
+    }
+
+    /// Put back the project as it was before the last edit, returning what was undone.
+    pub fn undo(&mut self, project: &mut ProjectState) -> Option<&'static str> {
+        let edit = self.undo.pop_back()?;
+        self.group_open = false;
+        edit.before.clone().restore(project);
+        let description = edit.description;
+        self.redo.push(edit);
+        Some(description)
+    }
+
+    /// Make the last undone edit again, returning what was redone.
+    pub fn redo(&mut self, project: &mut ProjectState) -> Option<&'static str> {
+        let edit = self.redo.pop()?;
+        self.group_open = false;
+        edit.after.clone().restore(project);
+        let description = edit.description;
+        self.undo.push_back(edit);
+        Some(description)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{chord_degree::ChordDegree, tatum::Tatum},
+        model::{
+            edit_history::{EditHistory, HISTORY_LIMIT},
+            project_state::ProjectState,
+        },
+    };
+
+    fn set_chord(
+        history: &mut EditHistory,
+        project: &mut ProjectState,
+        step: usize,
+        chord: Option<ChordDegree>,
+    ) {
+        history.apply(project, "Set chord", false, |project| {
+            project.update_chord_sequence(0, Tatum::try_from(step).unwrap(), chord)
+        });
+    }
+
+    #[test]
+    fn undo_and_redo_restore_project() {
+        let mut project = ProjectState::default();
+        let mut history = EditHistory::default();
+        set_chord(&mut history, &mut project, 3, Some(ChordDegree::V));
+        assert_eq!(history.undo(&mut project), Some("Set chord"));
+        assert_eq!(project.lanes[0][Tatum::try_from(3).unwrap()], None);
+        assert_eq!(history.undo(&mut project), None);
+        assert_eq!(history.redo(&mut project), Some("Set chord"));
+        assert_eq!(
+            project.lanes[0][Tatum::try_from(3).unwrap()],
+            Some(ChordDegree::V)
+        );
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut project = ProjectState::default();
+        let mut history = EditHistory::default();
+        set_chord(&mut history, &mut project, 3, Some(ChordDegree::V));
+        history.undo(&mut project);
+        set_chord(&mut history, &mut project, 4, Some(ChordDegree::V));
+        assert!(!history.can_redo());
+    }
+
+    #[test]
+    fn unchanged_project_is_not_recorded() {
+        let mut project = ProjectState::default();
+        let mut history = EditHistory::default();
+        set_chord(&mut history, &mut project, 0, None);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn grouped_edits_undo_together_until_group_ends() {
+        let mut project = ProjectState::default();
+        let mut history = EditHistory::default();
+        for amount in [1.0, 1.0, 1.0] {
+            history.apply(&mut project, "Nudge tempo", true, |project| {
+                project.nudge_tempo(amount)
+            });
+        }
+        history.end_group();
+        history.apply(&mut project, "Nudge tempo", true, |project| {
+            project.nudge_tempo(1.0)
+        });
+        history.undo(&mut project);
+        assert_eq!(f32::from(project.time.tempo.changes()[0].bpm), 123.0);
+        history.undo(&mut project);
+        assert_eq!(f32::from(project.time.tempo.changes()[0].bpm), 120.0);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn history_is_limited() {
+        let mut project = ProjectState::default();
+        let mut history = EditHistory::default();
+        for step in 0..HISTORY_LIMIT + 5 {
+            // Alternate filling and emptying the lane so every edit changes something
+            let chord = (step / 16 % 2 == 0).then_some(ChordDegree::V);
+            set_chord(&mut history, &mut project, step % 16, chord);
+        }
+        let mut undone = 0;
+        while history.undo(&mut project).is_some() {
+            undone += 1;
+        }
+        assert_eq!(undone, HISTORY_LIMIT);
+    }
+}
@@ -0,0 +1,13 @@
+use crate::generators::random_progression::ProgressionConstraints;
+
+use super::chord_sequence::ChordSequence;
+
+/// Takes kept to recall, oldest dropped first
+pub(crate) const TAKE_LIMIT: usize = 32;
+
+/// A generated sequence along with the constraints and seed that produced it.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct GeneratedTake {
+    pub constraints: ProgressionConstraints,
+    pub sequence: ChordSequence,
+}
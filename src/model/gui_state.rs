@@ -1,13 +1,95 @@
-use crate::data_types::tatum::Tatum;
+use crate::{data_types::tatum::Tatum, generators::random_progression::ProgressionConstraints};
+
+use super::{
+    edit_history::EditHistory,
+    recording::{ChordCapture, RecordMode},
+    scene::SceneQuantise,
+    tap_tempo::TapTempo,
+    transport_request::TransportRequest,
+};
+
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct EuclideanSettings {
+    pub pulses: usize,
+    pub steps: usize,
+    pub rotation: usize,
+    pub progression: String,
+}
+
+impl Default for EuclideanSettings {
+    fn default() -> Self {
+        Self {
+            pulses: 3,
+            steps: 8,
+            rotation: 0,
+            progression: "I".to_string(),
+        }
+    }
+}
+
+/// How new notes are placed in the melody lane.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct MelodySettings {
+    /// Round note starts to the nearest tatum
+    pub quantise: bool,
+    /// Move pitches to the nearest tone of the chord they are played over
+    pub snap_to_chords: bool,
+    /// Tatums a new note lasts
+    pub length: usize,
+}
+
+impl Default for MelodySettings {
+    fn default() -> Self {
+        Self {
+            quantise: true,
+            snap_to_chords: true,
+            length: 2,
+        }
+    }
+}
 
 pub(crate) struct GuiState {
+    pub selected_lane: usize,
     pub selected_chord: Tatum,
+    /// The other end of a range selection, with `selected_chord` as the end that moves
+    pub selection_anchor: Option<Tatum>,
+    pub euclidean: EuclideanSettings,
+    pub generation: ProgressionConstraints,
+    pub status_message: Option<String>,
+    pub tap_tempo: TapTempo,
+    pub melody: MelodySettings,
+    pub history: EditHistory,
+    /// Where scene switches wait for
+    pub scene_quantise: SceneQuantise,
+    /// Made on the JACK transport once the frame's views are done
+    pub transport_requests: Vec<TransportRequest>,
+    /// Zero based bar the locate button moves the transport to
+    pub locate_bar: u32,
+    /// Publish the project's tempo map for other JACK clients to follow
+    pub timebase_master: bool,
+    pub record_mode: RecordMode,
+    /// Keys played on the keyboard input so far towards the next chord
+    pub chord_capture: ChordCapture,
 }
 
 impl Default for GuiState {
     fn default() -> Self {
         Self {
+            selected_lane: 0,
             selected_chord: Tatum::try_from(0).unwrap(),
+            selection_anchor: None,
+            euclidean: EuclideanSettings::default(),
+            generation: ProgressionConstraints::default(),
+            status_message: None,
+            tap_tempo: TapTempo::default(),
+            melody: MelodySettings::default(),
+            history: EditHistory::default(),
+            scene_quantise: SceneQuantise::default(),
+            transport_requests: vec![],
+            locate_bar: 0,
+            timebase_master: false,
+            record_mode: RecordMode::default(),
+            chord_capture: ChordCapture::default(),
         }
     }
 }
@@ -0,0 +1,189 @@
+use crate::data_types::{
+    musical_position::{MusicalPosition, TICKS_PER_TATUM},
+    note::Note,
+    tatum::{MAX_TATUMS_PER_LANE, TATUM_SUBDIVDISONS_PER_BAR},
+    velocity::Velocity,
+};
+
+/// A note in a melody lane, placed anywhere in the loop rather than on a step.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct MelodyNote {
+    pub note: Note,
+    /// From the start of the lane's loop
+    pub start: MusicalPosition,
+    pub length: MusicalPosition,
+    pub velocity: Velocity,
+}
+
+impl MelodyNote {
+    pub fn end(&self) -> MusicalPosition {
+        self.start + self.length
+    }
+
+    fn overlaps(&self, other: &MelodyNote) -> bool {
+        self.note == other.note && self.start < other.end() && other.start < self.end()
+    }
+}
+
+/// Round `position` to the nearest tatum, or leave it where it is when playing off the grid.
+pub(crate) fn quantise(position: MusicalPosition, to_grid: bool) -> MusicalPosition {
+    if !to_grid {
+        return position;
+    }
+    let tatums = (position.ticks() + TICKS_PER_TATUM / 2) / TICKS_PER_TATUM;
+    MusicalPosition::from_tatums(tatums)
+}
+
+/// A piano roll of free notes that loops like a chord lane.
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct MelodyLane {
+    steps: usize,
+    // Always sorted by start
+    notes: Vec<MelodyNote>,
+}
+
+impl Default for MelodyLane {
+    fn default() -> Self {
+        Self {
+            steps: TATUM_SUBDIVDISONS_PER_BAR,
+            notes: vec![],
+        }
+    }
+}
+
+impl MelodyLane {
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn set_steps(&mut self, steps: usize) -> Result<(), &'static str> {
+        if steps == 0 || steps > MAX_TATUMS_PER_LANE {
+            return Err("Lane length out of range");
+        }
+        self.steps = steps;
+        let loop_end = self.loop_end();
+        self.notes.retain(|n| n.start < loop_end);
+        for note in self.notes.iter_mut() {
+            note.length = MusicalPosition::from_ticks(
+                note.length
+                    .ticks()
+                    .min(loop_end.ticks() - note.start.ticks()),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn notes(&self) -> &[MelodyNote] {
+        &self.notes
+    }
+
+    fn loop_end(&self) -> MusicalPosition {
+        MusicalPosition::from_tatums(self.steps as u64)
+    }
+
+    /// Add a note, cut short at the end of the loop. Notes of the same pitch it overlaps are
+    /// replaced, as they could not both sound.
+    pub fn add_note(&mut self, mut note: MelodyNote) -> Result<(), &'static str> {
+        let loop_end = self.loop_end();
+        if note.start >= loop_end {
+            return Err("Note starts after the end of the lane");
+        }
+        if note.length.ticks() == 0 {
+            return Err("Note has no length");
+        }
+        note.length = MusicalPosition::from_ticks(
+            note.length
+                .ticks()
+                .min(loop_end.ticks() - note.start.ticks()),
+        );
+        self.notes.retain(|existing| !existing.overlaps(&note));
+        let index = self.notes.partition_point(|n| n.start <= note.start);
+        self.notes.insert(index, note);
+        Ok(())
+    }
+
+    /// Remove the note of `pitch` sounding at `position`, returning whether there was one.
+    pub fn remove_note_at(&mut self, pitch: Note, position: MusicalPosition) -> bool {
+        let notes_before = self.notes.len();
+        self.notes
+            .retain(|n| !(n.note == pitch && n.start <= position && position < n.end()));
+        self.notes.len() != notes_before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{musical_position::MusicalPosition, note::Note, velocity::Velocity},
+        model::melody_lane::{quantise, MelodyLane, MelodyNote},
+    };
+
+    fn note(pitch: u8, start_tatum: u64, length_tatums: u64) -> MelodyNote {
+        MelodyNote {
+            note: Note::from(pitch),
+            start: MusicalPosition::from_tatums(start_tatum),
+            length: MusicalPosition::from_tatums(length_tatums),
+            velocity: Velocity::default(),
+        }
+    }
+
+    #[test]
+    fn quantise_rounds_to_nearest_tatum() {
+        assert_eq!(
+            quantise(MusicalPosition::from_ticks(359), true),
+            MusicalPosition::from_tatums(1)
+        );
+        assert_eq!(
+            quantise(MusicalPosition::from_ticks(360), true),
+            MusicalPosition::from_tatums(2)
+        );
+        assert_eq!(
+            quantise(MusicalPosition::from_ticks(359), false),
+            MusicalPosition::from_ticks(359)
+        );
+    }
+
+    #[test]
+    fn notes_are_kept_in_order() {
+        let mut lane = MelodyLane::default();
+        lane.add_note(note(67, 8, 2)).unwrap();
+        lane.add_note(note(60, 0, 4)).unwrap();
+        let starts: Vec<u64> = lane.notes().iter().map(|n| n.start.ticks()).collect();
+        assert_eq!(starts, vec![0, 8 * 240]);
+    }
+
+    #[test]
+    fn notes_are_cut_at_end_of_loop() {
+        let mut lane = MelodyLane::default();
+        lane.add_note(note(60, 14, 4)).unwrap();
+        assert_eq!(lane.notes()[0].length, MusicalPosition::from_tatums(2));
+        assert!(lane.add_note(note(60, 16, 1)).is_err());
+    }
+
+    #[test]
+    fn overlapping_note_of_same_pitch_is_replaced() {
+        let mut lane = MelodyLane::default();
+        lane.add_note(note(60, 0, 4)).unwrap();
+        lane.add_note(note(64, 0, 4)).unwrap();
+        lane.add_note(note(60, 2, 1)).unwrap();
+        assert_eq!(lane.notes(), &[note(64, 0, 4), note(60, 2, 1)]);
+    }
+
+    #[test]
+    fn remove_note_under_position() {
+        let mut lane = MelodyLane::default();
+        lane.add_note(note(60, 4, 4)).unwrap();
+        assert!(!lane.remove_note_at(Note::from(60), MusicalPosition::from_tatums(8)));
+        assert!(lane.remove_note_at(Note::from(60), MusicalPosition::from_tatums(7)));
+        assert!(lane.notes().is_empty());
+    }
+
+    #[test]
+    fn shortening_lane_drops_and_cuts_notes() {
+        let mut lane = MelodyLane::default();
+        lane.add_note(note(60, 4, 8)).unwrap();
+        lane.add_note(note(62, 12, 2)).unwrap();
+        lane.set_steps(8).unwrap();
+        assert_eq!(lane.notes(), &[note(60, 4, 4)]);
+    }
+}
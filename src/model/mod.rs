@@ -1,9 +1,26 @@
 use self::{gui_state::GuiState, project_state::ProjectState};
 
+pub mod automation_lane;
 pub mod chord_sequence;
+pub mod clip;
+pub mod edit_history;
+pub mod generated_take;
 pub mod gui_state;
+pub mod melody_lane;
+pub mod output_settings;
+pub mod playhead;
 pub mod project_state;
 pub mod project_time_info;
+pub mod recording;
+pub mod scene;
+pub mod step_condition;
+pub mod step_timing;
+pub mod sync_source;
+pub mod tap_tempo;
+pub mod tempo_map;
+pub mod time_signature_map;
+pub mod transport_request;
+pub mod trigger_layout;
 
 pub(crate) fn make_application_state() -> (ProjectState, GuiState) {
     (ProjectState::default(), GuiState::default())
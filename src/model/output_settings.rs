@@ -0,0 +1,89 @@
+use std::fmt;
+
+use crate::data_types::{midi_channel::MidiChannel, velocity::Velocity};
+
+/// Highest bank a bank select reaches, sent as two seven bit controllers
+pub(crate) const MAX_BANK: u16 = 0x3FFF;
+
+/// The patch an output asks its instrument for, with a bank select when a bank is given.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub(crate) struct ProgramChange {
+    bank: Option<u16>,
+    program: u8,
+}
+
+impl ProgramChange {
+    pub fn new(bank: Option<u16>, program: u8) -> Result<ProgramChange, &'static str> {
+        if bank.is_some_and(|bank| bank > MAX_BANK) {
+            return Err("Bank larger than 16383");
+        }
+        if program > 127 {
+            return Err("Program larger than 127");
+        }
+        Ok(ProgramChange { bank, program })
+    }
+
+    pub fn bank(&self) -> Option<u16> {
+        self.bank
+    }
+
+    pub fn program(&self) -> u8 {
+        self.program
+    }
+}
+
+/// The ports that send notes, each set up on its own.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) enum NoteOutput {
+    Chords,
+    Melody,
+}
+
+impl NoteOutput {
+    pub(crate) const ALL: [NoteOutput; 2] = [NoteOutput::Chords, NoteOutput::Melody];
+}
+
+impl fmt::Display for NoteOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NoteOutput::Chords => write!(f, "chords"),
+            NoteOutput::Melody => write!(f, "melody"),
+        }
+    }
+}
+
+/// How one of the note outputs talks to the instrument on the end of it.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct OutputSettings {
+    pub channel: MidiChannel,
+    /// Given to notes that carry no velocity of their own, so chord steps, and new melody
+    /// notes as they are drawn
+    pub velocity: Velocity,
+    pub note_off_velocity: Velocity,
+    /// Sent when the engine starts, when playback starts and when it is changed
+    pub program: Option<ProgramChange>,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self {
+            channel: MidiChannel::default(),
+            velocity: Velocity::default(),
+            note_off_velocity: Velocity::try_from(64).unwrap(),
+            program: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::output_settings::ProgramChange;
+
+    #[test]
+    fn program_change_is_checked() {
+        let change = ProgramChange::new(Some(16383), 127).unwrap();
+        assert_eq!((change.bank(), change.program()), (Some(16383), 127));
+        assert!(ProgramChange::new(Some(16384), 0).is_err());
+        assert!(ProgramChange::new(None, 128).is_err());
+    }
+}
@@ -0,0 +1,18 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::data_types::musical_position::MusicalPosition;
+
+/// How far playback has got, written by the engine every process cycle and read by the GUI to
+/// place queued changes. Atomic, so the engine can move it without locking.
+#[derive(Default, Debug)]
+pub(crate) struct Playhead(AtomicU64);
+
+impl Playhead {
+    pub fn position(&self) -> MusicalPosition {
+        MusicalPosition::from_ticks(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, position: MusicalPosition) {
+        self.0.store(position.ticks(), Ordering::Relaxed);
+    }
+}
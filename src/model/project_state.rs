@@ -1,51 +1,398 @@
-use crate::data_types::{chord_degree::ChordDegree, tatum::Tatum};
+use std::sync::Arc;
 
-use super::{chord_sequence::ChordSequence, project_time_info::ProjectTimeInfo};
+use crate::data_types::{
+    beats_per_minute::BeatsPerMinute, chord_degree::ChordDegree, musical_position::MusicalPosition,
+    note::Note, tatum::Tatum, time_signature::TimeSignature, velocity::Velocity,
+};
+
+use super::{
+    automation_lane::{AutomationLane, AutomationTarget},
+    chord_sequence::ChordSequence,
+    clip::Clip,
+    generated_take::{GeneratedTake, TAKE_LIMIT},
+    melody_lane::{MelodyLane, MelodyNote},
+    output_settings::{NoteOutput, OutputSettings},
+    playhead::Playhead,
+    project_time_info::ProjectTimeInfo,
+    scene::{SceneChange, SceneQuantise},
+    step_condition::StepCondition,
+    step_timing::StepTiming,
+    sync_source::SyncSource,
+    tempo_map::TempoChange,
+    trigger_layout::TriggerLayout,
+};
 
-#[derive(Default)]
 pub(crate) struct ProjectState {
-    pub chord_sequence: ChordSequence,
+    /// Lanes of the scene that is playing, or queued to play
+    pub lanes: Vec<ChordSequence>,
+    /// Lanes of every scene. The current scene's are kept in `lanes` while it plays, and only
+    /// stored back here when switching away from it.
+    pub scenes: Vec<Vec<ChordSequence>>,
+    pub scene: usize,
+    /// The last switch between scenes, so the engine can finish the old scene's bar
+    pub scene_change: Option<SceneChange>,
+    /// Shared with the engine, which moves it as it plays
+    pub playhead: Arc<Playhead>,
     pub time: ProjectTimeInfo,
+    pub generated_takes: Vec<GeneratedTake>,
+    /// Free notes for a top line, played on their own port
+    pub melody: MelodyLane,
+    pub automation: Vec<AutomationLane>,
+    pub chord_output: OutputSettings,
+    pub melody_output: OutputSettings,
+    /// Held while performing a fill, for steps with fill conditions
+    pub fill: bool,
+    /// Where playback follows, which is part of the rig rather than the song, so not undone
+    pub sync_source: SyncSource,
+    /// How keys on the trigger input map to chords, also part of the rig
+    pub trigger_layout: TriggerLayout,
+}
+
+impl Default for ProjectState {
+    fn default() -> Self {
+        Self {
+            lanes: vec![ChordSequence::default()],
+            scenes: vec![vec![ChordSequence::default()]],
+            scene: 0,
+            scene_change: None,
+            playhead: Arc::default(),
+            time: ProjectTimeInfo::default(),
+            generated_takes: vec![],
+            melody: MelodyLane::default(),
+            automation: vec![],
+            chord_output: OutputSettings::default(),
+            melody_output: OutputSettings {
+                velocity: Velocity::try_from(100).unwrap(),
+                ..OutputSettings::default()
+            },
+            fill: false,
+            sync_source: SyncSource::default(),
+            trigger_layout: TriggerLayout::default(),
+        }
+    }
 }
 
 impl ProjectState {
-    pub fn update_chord_sequence(&mut self, chord_position: Tatum, new_chord: Option<ChordDegree>) {
-        self.chord_sequence[chord_position] = new_chord;
+    pub fn update_chord_sequence(
+        &mut self,
+        lane: usize,
+        chord_position: Tatum,
+        new_chord: Option<ChordDegree>,
+    ) {
+        self.lanes[lane][chord_position] = new_chord;
+    }
+
+    pub fn set_condition(&mut self, lane: usize, step: Tatum, condition: StepCondition) {
+        self.lanes[lane].set_condition(step, condition);
+    }
+
+    pub fn set_timing(&mut self, lane: usize, step: Tatum, timing: StepTiming) {
+        self.lanes[lane].set_timing(step, timing);
+    }
+
+    /// Add a lane one bar long in the opening time signature.
+    pub fn add_lane(&mut self) -> usize {
+        let steps = self.opening_bar_tatums();
+        self.lanes.push(ChordSequence::with_steps(steps).unwrap());
+        self.lanes.len() - 1
+    }
+
+    /// Set the time signature from `bar` onwards. Changing the opening time signature also
+    /// resizes lanes that were exactly one bar long, so they stay one bar long.
+    pub fn set_time_signature(&mut self, bar: u32, time_signature: TimeSignature) {
+        let old_bar_tatums = self.opening_bar_tatums();
+        self.time
+            .time_signatures
+            .set_time_signature(bar, time_signature);
+        let new_bar_tatums = self.opening_bar_tatums();
+        if bar == 0 {
+            for lane in self
+                .lanes
+                .iter_mut()
+                .chain(self.scenes.iter_mut().flatten())
+                .filter(|l| l.steps() == old_bar_tatums)
+            {
+                lane.set_steps(new_bar_tatums).unwrap();
+            }
+            if self.melody.steps() == old_bar_tatums {
+                self.melody.set_steps(new_bar_tatums).unwrap();
+            }
+        }
+    }
+
+    pub fn output(&self, output: NoteOutput) -> OutputSettings {
+        match output {
+            NoteOutput::Chords => self.chord_output,
+            NoteOutput::Melody => self.melody_output,
+        }
+    }
+
+    pub fn set_output(&mut self, output: NoteOutput, settings: OutputSettings) {
+        match output {
+            NoteOutput::Chords => self.chord_output = settings,
+            NoteOutput::Melody => self.melody_output = settings,
+        }
+    }
+
+    pub fn remove_time_signature_change(&mut self, bar: u32) {
+        self.time.time_signatures.remove_change(bar);
+    }
+
+    pub fn set_tempo_change(&mut self, change: TempoChange) {
+        self.time.tempo.set_change(change);
+    }
+
+    pub fn set_opening_tempo(&mut self, bpm: BeatsPerMinute) {
+        self.time.tempo.set_opening_tempo(bpm);
+    }
+
+    pub fn nudge_tempo(&mut self, amount: f32) {
+        self.time.tempo.nudge(amount);
+    }
+
+    pub fn remove_tempo_change(&mut self, bar: u32) {
+        self.time.tempo.remove_change(bar);
+    }
+
+    fn opening_bar_tatums(&self) -> usize {
+        self.time
+            .time_signatures
+            .time_signature_at_bar(0)
+            .tatums_per_bar()
+    }
+
+    /// Add a scene starting as a copy of the current one, to make a variation of it.
+    pub fn add_scene(&mut self) -> usize {
+        self.scenes.push(self.lanes.clone());
+        self.scenes.len() - 1
+    }
+
+    /// Start playing `scene` at the next boundary after the playhead. The old scene keeps
+    /// playing until then, even if a switch was already waiting.
+    pub fn switch_scene(&mut self, scene: usize, quantise: SceneQuantise) {
+        if scene == self.scene || scene >= self.scenes.len() {
+            return;
+        }
+        let playhead = self.playhead.position();
+        let previous_lanes = match self.scene_change.take() {
+            Some(pending) if pending.at > playhead => pending.previous_lanes,
+            _ => self.lanes.clone(),
+        };
+        let lanes = std::mem::replace(&mut self.lanes, self.scenes[scene].clone());
+        self.scenes[self.scene] = lanes;
+        self.scene = scene;
+        self.scene_change = Some(SceneChange {
+            at: quantise.next_boundary(&self.time.time_signatures, playhead),
+            previous_lanes,
+        });
+    }
+
+    pub fn paste_clip(&mut self, lane: usize, at: Tatum, clip: &Clip) -> usize {
+        self.lanes[lane].paste(at, clip)
+    }
+
+    pub fn clear_steps(&mut self, lane: usize, start: Tatum, end: Tatum) {
+        self.lanes[lane].clear(start, end);
+    }
+
+    pub fn replace_lane(&mut self, lane: usize, sequence: ChordSequence) {
+        self.lanes[lane] = sequence;
+    }
+
+    pub fn add_generated_take(&mut self, take: GeneratedTake) -> usize {
+        if self.generated_takes.len() == TAKE_LIMIT {
+            self.generated_takes.remove(0);
+        }
+        self.generated_takes.push(take);
+        self.generated_takes.len() - 1
+    }
+
+    pub fn add_melody_note(&mut self, note: MelodyNote) -> Result<(), &'static str> {
+        self.melody.add_note(note)
+    }
+
+    pub fn remove_melody_note(&mut self, pitch: Note, position: MusicalPosition) -> bool {
+        self.melody.remove_note_at(pitch, position)
+    }
+
+    pub fn set_melody_steps(&mut self, steps: usize) -> Result<(), &'static str> {
+        self.melody.set_steps(steps)
+    }
+
+    pub fn add_automation_lane(&mut self, track: usize, target: AutomationTarget) -> usize {
+        self.automation.push(AutomationLane::new(track, target));
+        self.automation.len() - 1
+    }
+
+    pub fn remove_automation_lane(&mut self, index: usize) {
+        self.automation.remove(index);
+    }
+
+    pub fn set_automation_target(&mut self, index: usize, target: AutomationTarget) {
+        self.automation[index].set_target(target);
+    }
+
+    pub fn set_automation_interpolate(&mut self, index: usize, interpolate: bool) {
+        self.automation[index].interpolate = interpolate;
+    }
+
+    pub fn set_automation_point(
+        &mut self,
+        index: usize,
+        step: usize,
+        value: Option<u16>,
+    ) -> Result<(), &'static str> {
+        self.automation[index].set_point(step, value)
+    }
+
+    pub fn set_lane_steps(&mut self, lane: usize, steps: usize) -> Result<(), &'static str> {
+        self.lanes[lane].set_steps(steps)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        data_types::{chord_degree::ChordDegree, tatum::Tatum},
+        data_types::{
+            chord_degree::ChordDegree, musical_position::MusicalPosition, tatum::Tatum,
+            time_signature::TimeSignature,
+        },
+        generators::random_progression::ProgressionConstraints,
         model::{
-            chord_sequence::ChordSequence, project_state::ProjectState,
+            chord_sequence::ChordSequence,
+            generated_take::{GeneratedTake, TAKE_LIMIT},
+            project_state::ProjectState,
             project_time_info::ProjectTimeInfo,
+            scene::SceneQuantise,
         },
     };
 
+    #[test]
+    fn switch_scene_waits_for_next_bar() {
+        let mut project_state = ProjectState::default();
+        let scene = project_state.add_scene();
+        project_state.update_chord_sequence(0, Tatum::try_from(0).unwrap(), Some(ChordDegree::V));
+        project_state.playhead.set(MusicalPosition::from_tatums(20));
+        project_state.switch_scene(scene, SceneQuantise::Bar);
+        assert_eq!(project_state.scene, scene);
+        let change = project_state.scene_change.as_ref().unwrap();
+        assert_eq!(change.at, MusicalPosition::from_tatums(32));
+        assert_eq!(
+            change.previous_lanes[0][Tatum::try_from(0).unwrap()],
+            Some(ChordDegree::V)
+        );
+        assert_eq!(project_state.lanes[0][Tatum::try_from(0).unwrap()], None);
+        // The scene switched away from keeps its chords
+        assert_eq!(
+            project_state.scenes[0][0][Tatum::try_from(0).unwrap()],
+            Some(ChordDegree::V)
+        );
+    }
+
+    #[test]
+    fn switching_again_before_boundary_keeps_playing_scene() {
+        let mut project_state = ProjectState::default();
+        project_state.update_chord_sequence(0, Tatum::try_from(0).unwrap(), Some(ChordDegree::V));
+        let second = project_state.add_scene();
+        let third = project_state.add_scene();
+        project_state.playhead.set(MusicalPosition::from_tatums(4));
+        project_state.switch_scene(second, SceneQuantise::Bar);
+        project_state.update_chord_sequence(0, Tatum::try_from(0).unwrap(), Some(ChordDegree::II));
+        project_state.switch_scene(third, SceneQuantise::Bar);
+        let change = project_state.scene_change.as_ref().unwrap();
+        assert_eq!(
+            change.previous_lanes[0][Tatum::try_from(0).unwrap()],
+            Some(ChordDegree::V)
+        );
+        assert_eq!(
+            project_state.scenes[second][0][Tatum::try_from(0).unwrap()],
+            Some(ChordDegree::II)
+        );
+    }
+
+    #[test]
+    fn generated_takes_are_limited() {
+        let mut project_state = ProjectState::default();
+        for seed in 0..TAKE_LIMIT as u64 + 3 {
+            let take = GeneratedTake {
+                constraints: ProgressionConstraints {
+                    seed,
+                    ..ProgressionConstraints::default()
+                },
+                sequence: ChordSequence::default(),
+            };
+            assert_eq!(
+                project_state.add_generated_take(take),
+                (seed as usize).min(TAKE_LIMIT - 1)
+            );
+        }
+        assert_eq!(project_state.generated_takes.len(), TAKE_LIMIT);
+        assert_eq!(project_state.generated_takes[0].constraints.seed, 3);
+    }
+
     #[test]
     fn update_chord_sequence_with_new_chord() {
         let mut project_state = ProjectState {
-            chord_sequence: ChordSequence::default(),
+            lanes: vec![ChordSequence::default()],
             time: ProjectTimeInfo::default(),
+            ..ProjectState::default()
         };
         let chord_pos = Tatum::try_from(0).unwrap();
-        project_state.update_chord_sequence(chord_pos, Some(ChordDegree::II));
-        assert_eq!(
-            project_state.chord_sequence[chord_pos],
-            Some(ChordDegree::II)
-        );
+        project_state.update_chord_sequence(0, chord_pos, Some(ChordDegree::II));
+        assert_eq!(project_state.lanes[0][chord_pos], Some(ChordDegree::II));
     }
 
     #[test]
     fn update_chord_sequence_with_removing_chord() {
         let mut project_state = ProjectState {
-            chord_sequence: ChordSequence::new(Vec::from([Some(ChordDegree::II)])).unwrap(),
+            lanes: vec![ChordSequence::new(Vec::from([Some(ChordDegree::II)])).unwrap()],
             time: ProjectTimeInfo::default(),
+            ..ProjectState::default()
         };
         let chord_pos = Tatum::try_from(0).unwrap();
-        project_state.update_chord_sequence(chord_pos, None);
-        assert_eq!(project_state.chord_sequence[chord_pos], None);
+        project_state.update_chord_sequence(0, chord_pos, None);
+        assert_eq!(project_state.lanes[0][chord_pos], None);
+    }
+
+    #[test]
+    fn update_chord_sequence_in_second_lane() {
+        let mut project_state = ProjectState::default();
+        let lane = project_state.add_lane();
+        let chord_pos = Tatum::try_from(3).unwrap();
+        project_state.update_chord_sequence(lane, chord_pos, Some(ChordDegree::V));
+        assert_eq!(project_state.lanes[0][chord_pos], None);
+        assert_eq!(project_state.lanes[1][chord_pos], Some(ChordDegree::V));
+    }
+
+    #[test]
+    fn set_lane_steps_only_changes_that_lane() {
+        let mut project_state = ProjectState::default();
+        let lane = project_state.add_lane();
+        project_state.set_lane_steps(lane, 7).unwrap();
+        assert_eq!(project_state.lanes[0].steps(), 16);
+        assert_eq!(project_state.lanes[1].steps(), 7);
+    }
+
+    #[test]
+    fn changing_opening_time_signature_resizes_bar_long_lanes() {
+        let mut project_state = ProjectState::default();
+        let short_lane = project_state.add_lane();
+        project_state.set_lane_steps(short_lane, 7).unwrap();
+        project_state.set_time_signature(0, TimeSignature::new(12, 8).unwrap());
+        assert_eq!(project_state.lanes[0].steps(), 24);
+        assert_eq!(project_state.lanes[short_lane].steps(), 7);
+        let new_lane = project_state.add_lane();
+        assert_eq!(project_state.lanes[new_lane].steps(), 24);
+        assert_eq!(project_state.melody.steps(), 24);
+    }
+
+    #[test]
+    fn later_time_signature_change_leaves_lanes() {
+        let mut project_state = ProjectState::default();
+        project_state.set_time_signature(4, TimeSignature::new(3, 4).unwrap());
+        assert_eq!(project_state.lanes[0].steps(), 16);
+        assert_eq!(project_state.time.time_signatures.changes().len(), 2);
+        project_state.remove_time_signature_change(4);
+        assert_eq!(project_state.time.time_signatures.changes().len(), 1);
     }
 }
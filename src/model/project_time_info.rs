@@ -1,15 +1,176 @@
-use crate::data_types::beats_per_minute::BeatsPerMinute;
+use crate::data_types::{beats_per_minute::BeatsPerMinute, tatum::TATUMS_PER_QUARTER_NOTE};
 
+use super::{
+    tempo_map::{TempoMap, TempoSegment},
+    time_signature_map::TimeSignatureMap,
+};
+
+#[derive(Clone, PartialEq, Debug)]
 pub(crate) struct ProjectTimeInfo {
-    pub(crate) bpm: BeatsPerMinute,
-    pub(crate) beats_per_bar: u32,
+    /// Tempo counts quarter notes whatever the time signature
+    pub(crate) tempo: TempoMap,
+    pub(crate) time_signatures: TimeSignatureMap,
 }
 
 impl Default for ProjectTimeInfo {
     fn default() -> Self {
         Self {
+            tempo: TempoMap::new(BeatsPerMinute::from(120)),
+            time_signatures: TimeSignatureMap::default(),
+        }
+    }
+}
+
+/// Converts between tatums and seconds from the start of the song.
+pub(crate) trait SongTime {
+    fn seconds_at_tatum(&self, tatums: f64) -> f64;
+    fn tatum_at_seconds(&self, seconds: f64) -> f64;
+}
+
+impl ProjectTimeInfo {
+    /// The tempo map worked out into segments, for converting times without allocating.
+    pub fn timeline(&self) -> Timeline {
+        Timeline {
+            segments: self
+                .tempo
+                .segments(|bar| self.time_signatures.bar_start_tatum(bar)),
+        }
+    }
+}
+
+impl SongTime for ProjectTimeInfo {
+    fn seconds_at_tatum(&self, tatums: f64) -> f64 {
+        self.timeline().seconds_at_tatum(tatums)
+    }
+
+    fn tatum_at_seconds(&self, seconds: f64) -> f64 {
+        self.timeline().tatum_at_seconds(seconds)
+    }
+}
+
+/// The tempo map as consecutive segments, ready to follow on the audio thread.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) struct Timeline {
+    segments: Vec<TempoSegment>,
+}
+
+impl Timeline {
+    /// The segment playing `tatums` into the song.
+    fn segment_at_tatum(&self, tatums: f64) -> &TempoSegment {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start <= tatums);
+        &self.segments[index.saturating_sub(1)]
+    }
+
+    /// Quarter notes a minute `tatums` into the song.
+    pub fn bpm_at_tatum(&self, tatums: f64) -> f64 {
+        let segment = self.segment_at_tatum(tatums);
+        segment.rate_at(tatums - segment.start) * 60.0 / TATUMS_PER_QUARTER_NOTE as f64
+    }
+}
+
+impl SongTime for Timeline {
+    /// Seconds from the start of the song until `tatums` have played, following the tempo map.
+    fn seconds_at_tatum(&self, tatums: f64) -> f64 {
+        let segment = self.segment_at_tatum(tatums);
+        segment.start_seconds + segment.seconds_at(tatums - segment.start)
+    }
+
+    /// How many tatums have played `seconds` after the start of the song.
+    fn tatum_at_seconds(&self, seconds: f64) -> f64 {
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start_seconds <= seconds);
+        let segment = &self.segments[index.saturating_sub(1)];
+        segment.start + segment.tatums_at(seconds - segment.start_seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::beats_per_minute::BeatsPerMinute,
+        model::{
+            project_time_info::{ProjectTimeInfo, SongTime},
+            tempo_map::{TempoChange, TempoCurve, RATE_EVALUATIONS},
+        },
+    };
+
+    fn ritardando() -> ProjectTimeInfo {
+        // 120bpm for a bar, slowing to 60bpm over the next bar
+        let mut time_info = ProjectTimeInfo::default();
+        time_info.tempo.set_change(TempoChange {
+            bar: 1,
             bpm: BeatsPerMinute::from(120),
-            beats_per_bar: 4,
+            curve: TempoCurve::Linear,
+        });
+        time_info.tempo.set_change(TempoChange {
+            bar: 2,
+            bpm: BeatsPerMinute::from(60),
+            curve: TempoCurve::Step,
+        });
+        time_info
+    }
+
+    #[test]
+    fn timeline_gives_tempo_through_ramp() {
+        let timeline = ritardando().timeline();
+        assert_eq!(timeline.bpm_at_tatum(0.0), 120.0);
+        assert_eq!(timeline.bpm_at_tatum(24.0), 90.0);
+        assert_eq!(timeline.bpm_at_tatum(40.0), 60.0);
+    }
+
+    #[test]
+    fn constant_tempo_seconds() {
+        let time_info = ProjectTimeInfo::default();
+        // 120bpm is 8 tatums a second
+        assert_eq!(time_info.seconds_at_tatum(16.0), 2.0);
+        assert_eq!(time_info.tatum_at_seconds(3.0), 24.0);
+    }
+
+    #[test]
+    fn ramp_takes_longer_than_starting_tempo() {
+        let time_info = ritardando();
+        assert_eq!(time_info.seconds_at_tatum(16.0), 2.0);
+        let ramp_seconds = time_info.seconds_at_tatum(32.0) - 2.0;
+        assert!(ramp_seconds > 2.0 && ramp_seconds < 4.0);
+        // Then a bar at 60bpm takes four seconds
+        let after_ramp = time_info.seconds_at_tatum(48.0) - time_info.seconds_at_tatum(32.0);
+        assert!((after_ramp - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn seconds_and_tatums_round_trip_through_changes() {
+        let time_info = ritardando();
+        for tatums in [0.0, 5.0, 16.0, 20.5, 32.0, 40.0, 100.0] {
+            let seconds = time_info.seconds_at_tatum(tatums);
+            assert!((time_info.tatum_at_seconds(seconds) - tatums).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn conversions_on_curved_map_are_cheap() {
+        // 32 bars of curved ramps, alternately speeding up and slowing down
+        let mut time_info = ProjectTimeInfo::default();
+        for bar in 0..=32 {
+            time_info.tempo.set_change(TempoChange {
+                bar,
+                bpm: BeatsPerMinute::from(if bar % 2 == 0 { 80 } else { 160 }),
+                curve: TempoCurve::Curved(2.0),
+            });
+        }
+        let timeline = time_info.timeline();
+        let rate_evaluations = |convert: &dyn Fn() -> f64| {
+            RATE_EVALUATIONS.with(|count| count.set(0));
+            convert();
+            RATE_EVALUATIONS.with(|count| count.get())
+        };
+        for tatums in [0.0, 100.5, 511.0, 600.0] {
+            let seconds = timeline.seconds_at_tatum(tatums);
+            assert!(rate_evaluations(&|| timeline.seconds_at_tatum(tatums)) <= 8);
+            assert!(rate_evaluations(&|| timeline.tatum_at_seconds(seconds)) <= 32);
+            assert!((timeline.tatum_at_seconds(seconds) - tatums).abs() < 1e-9);
         }
     }
 }
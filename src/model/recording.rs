@@ -0,0 +1,124 @@
+use std::fmt;
+
+use crate::data_types::{musical_position::MusicalPosition, note::Note};
+
+/// What playing chords on the keyboard input writes into the selected lane.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub(crate) enum RecordMode {
+    #[default]
+    Off,
+    /// Each chord goes into the selected step, then the cursor moves on
+    Step,
+    /// Chords played while the transport rolls go into the step they were played on
+    Loop,
+}
+
+impl RecordMode {
+    pub(crate) const ALL: [RecordMode; 3] = [RecordMode::Off, RecordMode::Step, RecordMode::Loop];
+}
+
+impl fmt::Display for RecordMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordMode::Off => write!(f, "off"),
+            RecordMode::Step => write!(f, "step"),
+            RecordMode::Loop => write!(f, "loop"),
+        }
+    }
+}
+
+/// A key pressed or let go on the keyboard input, as the engine heard it.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct RecordedKey {
+    pub note: Note,
+    pub pressed: bool,
+    /// Whether the transport was rolling, as `at` only moves while it is
+    pub rolling: bool,
+    pub at: MusicalPosition,
+}
+
+/// The notes of one chord played on the keyboard, from the first key down to the last key up.
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct CapturedChord {
+    pub notes: Vec<Note>,
+    /// When the first key went down
+    pub at: MusicalPosition,
+    pub rolling: bool,
+}
+
+/// Gathers keys into chords. Keys are rarely pressed at exactly the same time, so a chord is
+/// every key pressed until they have all been let go.
+#[derive(Default, Debug)]
+pub(crate) struct ChordCapture {
+    held: Vec<Note>,
+    played: Vec<Note>,
+    started: Option<RecordedKey>,
+}
+
+impl ChordCapture {
+    /// Follow a key, returning the chord once the last key is let go.
+    pub fn key(&mut self, key: RecordedKey) -> Option<CapturedChord> {
+        if key.pressed {
+            self.started.get_or_insert(key);
+            if !self.held.contains(&key.note) {
+                self.held.push(key.note);
+            }
+            if !self.played.contains(&key.note) {
+                self.played.push(key.note);
+            }
+            return None;
+        }
+        self.held.retain(|note| *note != key.note);
+        if !self.held.is_empty() {
+            return None;
+        }
+        let started = self.started.take()?;
+        Some(CapturedChord {
+            notes: std::mem::take(&mut self.played),
+            at: started.at,
+            rolling: started.rolling,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{musical_position::MusicalPosition, note::Note},
+        model::recording::{CapturedChord, ChordCapture, RecordedKey},
+    };
+
+    fn key(note: u8, pressed: bool, ticks: u64) -> RecordedKey {
+        RecordedKey {
+            note: Note::from(note),
+            pressed,
+            rolling: true,
+            at: MusicalPosition::from_ticks(ticks),
+        }
+    }
+
+    #[test]
+    fn chord_ends_when_last_key_is_let_go() {
+        let mut capture = ChordCapture::default();
+        assert_eq!(capture.key(key(60, true, 10)), None);
+        assert_eq!(capture.key(key(64, true, 20)), None);
+        assert_eq!(capture.key(key(60, false, 30)), None);
+        assert_eq!(capture.key(key(67, true, 40)), None);
+        assert_eq!(capture.key(key(64, false, 50)), None);
+        assert_eq!(
+            capture.key(key(67, false, 60)),
+            Some(CapturedChord {
+                notes: [60, 64, 67].map(Note::from).to_vec(),
+                at: MusicalPosition::from_ticks(10),
+                rolling: true,
+            })
+        );
+        // Starts afresh, and a stray release is no chord
+        assert_eq!(capture.key(key(62, false, 70)), None);
+        capture.key(key(62, true, 80));
+        assert_eq!(
+            capture.key(key(62, false, 90)).unwrap().notes,
+            [Note::from(62)]
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+
+use crate::data_types::musical_position::{MusicalPosition, TICKS_PER_TATUM};
+
+use super::{chord_sequence::ChordSequence, time_signature_map::TimeSignatureMap};
+
+/// Where a scene switch waits for, so the change lands in time rather than mid-bar.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub(crate) enum SceneQuantise {
+    #[default]
+    Bar,
+    Beat,
+}
+
+impl SceneQuantise {
+    pub(crate) const ALL: [SceneQuantise; 2] = [SceneQuantise::Bar, SceneQuantise::Beat];
+
+    /// The first bar or beat boundary at or after `position`, following the time signatures.
+    pub fn next_boundary(
+        &self,
+        time_signatures: &TimeSignatureMap,
+        position: MusicalPosition,
+    ) -> MusicalPosition {
+        let tatum = position.ticks().div_ceil(TICKS_PER_TATUM);
+        let boundary = match self {
+            SceneQuantise::Bar => {
+                let bar = time_signatures.bar_of_tatum(tatum);
+                let bar_start = time_signatures.bar_start_tatum(bar);
+                if bar_start == tatum {
+                    bar_start
+                } else {
+                    time_signatures.bar_start_tatum(bar + 1)
+                }
+            }
+            // Beats can be uneven in odd meters, so walk forward to the next one
+            SceneQuantise::Beat => (tatum..)
+                .find(|&t| time_signatures.position_of_tatum(t).tatum_in_beat == 0)
+                .unwrap(),
+        };
+        MusicalPosition::from_tatums(boundary)
+    }
+}
+
+impl fmt::Display for SceneQuantise {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneQuantise::Bar => write!(f, "next bar"),
+            SceneQuantise::Beat => write!(f, "next beat"),
+        }
+    }
+}
+
+/// A switch between scenes. The lanes that were playing carry on until `at`.
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct SceneChange {
+    pub at: MusicalPosition,
+    pub previous_lanes: Vec<ChordSequence>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{musical_position::MusicalPosition, time_signature::TimeSignature},
+        model::{scene::SceneQuantise, time_signature_map::TimeSignatureMap},
+    };
+
+    #[test]
+    fn bar_boundary_waits_for_next_bar() {
+        let time_signatures = TimeSignatureMap::default();
+        assert_eq!(
+            SceneQuantise::Bar.next_boundary(&time_signatures, MusicalPosition::from_ticks(1)),
+            MusicalPosition::from_tatums(16)
+        );
+        assert_eq!(
+            SceneQuantise::Bar.next_boundary(&time_signatures, MusicalPosition::from_tatums(16)),
+            MusicalPosition::from_tatums(16)
+        );
+    }
+
+    #[test]
+    fn bar_boundary_follows_time_signature_changes() {
+        let mut time_signatures = TimeSignatureMap::default();
+        time_signatures.set_time_signature(1, TimeSignature::new(3, 4).unwrap());
+        assert_eq!(
+            SceneQuantise::Bar.next_boundary(&time_signatures, MusicalPosition::from_tatums(17)),
+            MusicalPosition::from_tatums(28)
+        );
+    }
+
+    #[test]
+    fn beat_boundary_follows_uneven_beats() {
+        // 7/8 is grouped 2+2+3 eighths, so beats start on tatums 0, 4 and 8
+        let time_signatures = TimeSignatureMap::new(TimeSignature::new(7, 8).unwrap());
+        assert_eq!(
+            SceneQuantise::Beat.next_boundary(&time_signatures, MusicalPosition::from_tatums(5)),
+            MusicalPosition::from_tatums(8)
+        );
+        assert_eq!(
+            SceneQuantise::Beat.next_boundary(&time_signatures, MusicalPosition::from_tatums(9)),
+            MusicalPosition::from_tatums(14)
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::{fmt, str::FromStr};
+
+/// Decides whether a step plays on a given pass through its lane, like the trig conditions
+/// on hardware sequencers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub(crate) enum StepCondition {
+    #[default]
+    Always,
+    /// Play on loop `loop_number` of every `loop_count` loops, both counting from 1
+    LoopOf {
+        loop_number: u32,
+        loop_count: u32,
+    },
+    FirstLoop,
+    NotFirstLoop,
+    Fill,
+    NotFill,
+    /// Play if the previous conditional step in the loop played
+    Previous,
+    NotPrevious,
+}
+
+impl StepCondition {
+    pub(crate) const ALL: [StepCondition; 8] = [
+        StepCondition::Always,
+        StepCondition::LoopOf {
+            loop_number: 1,
+            loop_count: 2,
+        },
+        StepCondition::FirstLoop,
+        StepCondition::NotFirstLoop,
+        StepCondition::Fill,
+        StepCondition::NotFill,
+        StepCondition::Previous,
+        StepCondition::NotPrevious,
+    ];
+
+    /// Whether the step plays on the zero based `loop_index`, given whether fill is held and
+    /// the result of the previous conditional step.
+    pub(crate) fn evaluate(&self, loop_index: u64, fill: bool, previous: bool) -> bool {
+        match *self {
+            StepCondition::Always => true,
+            StepCondition::LoopOf {
+                loop_number,
+                loop_count,
+            } => {
+                // Loops count from 1, so a zero anywhere never plays rather than underflowing
+                loop_count != 0
+                    && loop_number != 0
+                    && loop_index % loop_count as u64 == loop_number as u64 - 1
+            }
+            StepCondition::FirstLoop => loop_index == 0,
+            StepCondition::NotFirstLoop => loop_index != 0,
+            StepCondition::Fill => fill,
+            StepCondition::NotFill => !fill,
+            StepCondition::Previous => previous,
+            StepCondition::NotPrevious => !previous,
+        }
+    }
+
+    pub(crate) fn is_conditional(&self) -> bool {
+        *self != StepCondition::Always
+    }
+}
+
+/// Which steps with these conditions play on the zero based `loop_index`, one bit per step.
+/// Previous conditions start each loop as failed. Works without allocating, so the engine can
+/// evaluate it every process cycle.
+pub(crate) fn steps_playing(conditions: &[StepCondition], loop_index: u64, fill: bool) -> u32 {
+    let mut previous = false;
+    let mut playing = 0;
+    for (step, condition) in conditions.iter().enumerate() {
+        let plays = if condition.is_conditional() {
+            previous = condition.evaluate(loop_index, fill, previous);
+            previous
+        } else {
+            true
+        };
+        if plays {
+            playing |= 1 << step;
+        }
+    }
+    playing
+}
+
+impl fmt::Display for StepCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StepCondition::Always => write!(f, "always"),
+            StepCondition::LoopOf {
+                loop_number,
+                loop_count,
+            } => write!(f, "{}:{}", loop_number, loop_count),
+            StepCondition::FirstLoop => write!(f, "1st"),
+            StepCondition::NotFirstLoop => write!(f, "!1st"),
+            StepCondition::Fill => write!(f, "fill"),
+            StepCondition::NotFill => write!(f, "!fill"),
+            StepCondition::Previous => write!(f, "pre"),
+            StepCondition::NotPrevious => write!(f, "!pre"),
+        }
+    }
+}
+
+impl FromStr for StepCondition {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(StepCondition::Always),
+            "1st" => Ok(StepCondition::FirstLoop),
+            "!1st" => Ok(StepCondition::NotFirstLoop),
+            "fill" => Ok(StepCondition::Fill),
+            "!fill" => Ok(StepCondition::NotFill),
+            "pre" => Ok(StepCondition::Previous),
+            "!pre" => Ok(StepCondition::NotPrevious),
+            _ => {
+                let (loop_number, loop_count) = s.split_once(':').ok_or("Not a step condition")?;
+                let loop_number = loop_number.parse().map_err(|_| "Not a step condition")?;
+                let loop_count = loop_count.parse().map_err(|_| "Not a step condition")?;
+                if loop_number == 0 || loop_number > loop_count {
+                    return Err("Loop number must be between 1 and the loop count");
+                }
+                Ok(StepCondition::LoopOf {
+                    loop_number,
+                    loop_count,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::step_condition::{steps_playing, StepCondition};
+
+    #[test]
+    fn steps_playing_drops_failing_steps() {
+        let conditions = [
+            StepCondition::Always,
+            StepCondition::FirstLoop,
+            StepCondition::NotFirstLoop,
+            StepCondition::Fill,
+        ];
+        assert_eq!(steps_playing(&conditions, 0, false), 0b0011);
+        assert_eq!(steps_playing(&conditions, 1, true), 0b1101);
+    }
+
+    #[test]
+    fn steps_playing_previous_follows_last_conditional_step() {
+        let conditions = [
+            StepCondition::LoopOf {
+                loop_number: 1,
+                loop_count: 2,
+            },
+            StepCondition::Always,
+            StepCondition::Previous,
+            StepCondition::NotPrevious,
+        ];
+        assert_eq!(steps_playing(&conditions, 0, false), 0b0111);
+        assert_eq!(steps_playing(&conditions, 1, false), 0b1010);
+    }
+
+    #[test]
+    fn parse_round_trips_display() {
+        let conditions = StepCondition::ALL
+            .into_iter()
+            .chain([StepCondition::LoopOf {
+                loop_number: 3,
+                loop_count: 4,
+            }]);
+        for condition in conditions {
+            assert_eq!(condition.to_string().parse(), Ok(condition));
+        }
+    }
+
+    #[test]
+    fn parse_rejects_impossible_loop() {
+        assert!("0:2".parse::<StepCondition>().is_err());
+        assert!("3:2".parse::<StepCondition>().is_err());
+        assert!("sometimes".parse::<StepCondition>().is_err());
+    }
+
+    #[test]
+    fn loop_of_zero_never_plays() {
+        for (loop_number, loop_count) in [(0, 2), (1, 0), (0, 0)] {
+            let condition = StepCondition::LoopOf {
+                loop_number,
+                loop_count,
+            };
+            assert!((0..4).all(|loop_index| !condition.evaluate(loop_index, false, false)));
+        }
+    }
+
+    #[test]
+    fn loop_of_plays_on_matching_loops() {
+        let condition = StepCondition::LoopOf {
+            loop_number: 2,
+            loop_count: 3,
+        };
+        let plays: Vec<bool> = (0..6)
+            .map(|loop_index| condition.evaluate(loop_index, false, false))
+            .collect();
+        assert_eq!(plays, vec![false, true, false, false, true, false]);
+    }
+
+    #[test]
+    fn first_loop_conditions() {
+        assert!(StepCondition::FirstLoop.evaluate(0, false, false));
+        assert!(!StepCondition::FirstLoop.evaluate(1, false, false));
+        assert!(!StepCondition::NotFirstLoop.evaluate(0, false, false));
+        assert!(StepCondition::NotFirstLoop.evaluate(5, false, false));
+    }
+
+    #[test]
+    fn fill_conditions() {
+        assert!(StepCondition::Fill.evaluate(0, true, false));
+        assert!(!StepCondition::Fill.evaluate(0, false, false));
+        assert!(StepCondition::NotFill.evaluate(0, false, false));
+    }
+
+    #[test]
+    fn previous_conditions() {
+        assert!(StepCondition::Previous.evaluate(0, false, true));
+        assert!(!StepCondition::NotPrevious.evaluate(0, false, true));
+    }
+}
@@ -0,0 +1,140 @@
+use std::{fmt, str::FromStr};
+
+use crate::data_types::musical_position::TICKS_PER_TATUM;
+
+/// Most hits a step can be split into
+pub(crate) const MAX_RATCHET: u8 = 8;
+/// Furthest a step can be nudged either way, half a tatum, so nudged steps never pass each
+/// other
+pub(crate) const MAX_NUDGE_TICKS: i16 = (TICKS_PER_TATUM / 2) as i16;
+
+/// How a step's chord is played within the step: repeated `ratchet` times, and moved
+/// `nudge` ticks earlier or later than the grid.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct StepTiming {
+    ratchet: u8,
+    nudge: i16,
+}
+
+impl Default for StepTiming {
+    fn default() -> Self {
+        StepTiming::ON_GRID
+    }
+}
+
+impl StepTiming {
+    /// One hit, right on the grid
+    pub(crate) const ON_GRID: StepTiming = StepTiming {
+        ratchet: 1,
+        nudge: 0,
+    };
+
+    pub fn new(ratchet: u8, nudge: i16) -> Result<StepTiming, &'static str> {
+        if ratchet == 0 || ratchet > MAX_RATCHET {
+            return Err("Ratchet out of range");
+        }
+        if nudge.abs() > MAX_NUDGE_TICKS {
+            return Err("Nudge is more than half a tatum");
+        }
+        Ok(StepTiming { ratchet, nudge })
+    }
+
+    pub fn ratchet(&self) -> u8 {
+        self.ratchet
+    }
+
+    pub fn nudge(&self) -> i16 {
+        self.nudge
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == StepTiming::default()
+    }
+
+    /// Start and end of each hit in ticks from the start of the step, which can be negative
+    /// for a step nudged earlier. Hits split the step evenly, to the nearest tick.
+    pub fn hits(&self) -> impl Iterator<Item = (i64, i64)> + '_ {
+        let tatum = TICKS_PER_TATUM as i64;
+        let ratchet = self.ratchet as i64;
+        (0..ratchet).map(move |hit| {
+            let nudge = self.nudge as i64;
+            (
+                nudge + hit * tatum / ratchet,
+                nudge + (hit + 1) * tatum / ratchet,
+            )
+        })
+    }
+}
+
+/// Written after the chord of a step in a clip, e.g. "x3" for a ratchet and "+60" for a
+/// nudge, so "Vx3-30" is V played three times, 30 ticks early.
+impl fmt::Display for StepTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ratchet > 1 {
+            write!(f, "x{}", self.ratchet)?;
+        }
+        if self.nudge != 0 {
+            write!(f, "{:+}", self.nudge)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for StepTiming {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ratchet, nudge) = match s.find(['+', '-']) {
+            Some(index) => s.split_at(index),
+            None => (s, "0"),
+        };
+        let ratchet = match ratchet {
+            "" => 1,
+            ratchet => ratchet
+                .strip_prefix('x')
+                .and_then(|count| count.parse().ok())
+                .ok_or("Not a ratchet")?,
+        };
+        let nudge = nudge.parse().map_err(|_| "Not a nudge")?;
+        StepTiming::new(ratchet, nudge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::step_timing::{StepTiming, MAX_NUDGE_TICKS};
+
+    #[test]
+    fn ratchet_splits_step_evenly() {
+        let timing = StepTiming::new(3, 0).unwrap();
+        assert_eq!(
+            timing.hits().collect::<Vec<_>>(),
+            vec![(0, 80), (80, 160), (160, 240)]
+        );
+    }
+
+    #[test]
+    fn nudge_moves_every_hit() {
+        let timing = StepTiming::new(2, -30).unwrap();
+        assert_eq!(
+            timing.hits().collect::<Vec<_>>(),
+            vec![(-30, 90), (90, 210)]
+        );
+    }
+
+    #[test]
+    fn timing_out_of_range_is_rejected() {
+        assert!(StepTiming::new(0, 0).is_err());
+        assert!(StepTiming::new(9, 0).is_err());
+        assert!(StepTiming::new(1, MAX_NUDGE_TICKS + 1).is_err());
+    }
+
+    #[test]
+    fn timing_round_trips_text() {
+        for text in ["", "x3", "-30", "x2+60"] {
+            assert_eq!(text.parse::<StepTiming>().unwrap().to_string(), text);
+        }
+        assert!("x".parse::<StepTiming>().is_err());
+        assert!("3".parse::<StepTiming>().is_err());
+    }
+}
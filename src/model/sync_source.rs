@@ -0,0 +1,24 @@
+use std::fmt;
+
+/// What the engine takes its position and tempo from.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub(crate) enum SyncSource {
+    /// The JACK transport, at the project's tempo
+    #[default]
+    JackTransport,
+    /// MIDI beat clock arriving on the clock input, at whatever tempo it runs
+    MidiClock,
+}
+
+impl SyncSource {
+    pub(crate) const ALL: [SyncSource; 2] = [SyncSource::JackTransport, SyncSource::MidiClock];
+}
+
+impl fmt::Display for SyncSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncSource::JackTransport => write!(f, "JACK transport"),
+            SyncSource::MidiClock => write!(f, "MIDI clock"),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+
+use crate::data_types::beats_per_minute::BeatsPerMinute;
+
+/// Taps further apart than this start a new tempo rather than averaging with the old one
+const TAP_TIMEOUT_SECONDS: f64 = 2.0;
+/// How many of the most recent taps are averaged
+const TAPS_AVERAGED: usize = 5;
+
+/// Works out a tempo from quarter notes tapped in by hand.
+#[derive(Default, Debug)]
+pub(crate) struct TapTempo {
+    taps: VecDeque<f64>,
+}
+
+impl TapTempo {
+    /// Record a tap at `seconds` on any steadily increasing clock. Returns the tempo once
+    /// there are at least two taps to measure between.
+    pub fn tap(&mut self, seconds: f64) -> Option<BeatsPerMinute> {
+        if let Some(&last_tap) = self.taps.back() {
+            if seconds - last_tap > TAP_TIMEOUT_SECONDS || seconds <= last_tap {
+                self.taps.clear();
+            }
+        }
+        self.taps.push_back(seconds);
+        if self.taps.len() > TAPS_AVERAGED {
+            self.taps.pop_front();
+        }
+        let first_tap = self.taps.front()?;
+        let last_tap = self.taps.back()?;
+        let intervals = self.taps.len() - 1;
+        if intervals == 0 {
+            return None;
+        }
+        let seconds_per_beat = (last_tap - first_tap) / intervals as f64;
+        BeatsPerMinute::try_from((60.0 / seconds_per_beat) as f32).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{data_types::beats_per_minute::BeatsPerMinute, model::tap_tempo::TapTempo};
+
+    #[test]
+    fn first_tap_has_no_tempo() {
+        let mut tap_tempo = TapTempo::default();
+        assert_eq!(tap_tempo.tap(10.0), None);
+    }
+
+    #[test]
+    fn averages_recent_taps() {
+        let mut tap_tempo = TapTempo::default();
+        tap_tempo.tap(0.0);
+        tap_tempo.tap(0.6);
+        tap_tempo.tap(1.25);
+        // Three taps at an average of 0.625 seconds apart
+        assert_eq!(tap_tempo.tap(1.875), BeatsPerMinute::try_from(96.0).ok());
+    }
+
+    #[test]
+    fn only_most_recent_taps_count() {
+        let mut tap_tempo = TapTempo::default();
+        for tap in [0.0, 1.0, 2.0] {
+            tap_tempo.tap(tap);
+        }
+        let mut tempo = None;
+        for beat in 0..5 {
+            tempo = tap_tempo.tap(2.5 + beat as f64 * 0.5);
+        }
+        assert_eq!(tempo, BeatsPerMinute::try_from(120.0).ok());
+    }
+
+    #[test]
+    fn long_pause_starts_again() {
+        let mut tap_tempo = TapTempo::default();
+        tap_tempo.tap(0.0);
+        tap_tempo.tap(1.0);
+        assert_eq!(tap_tempo.tap(5.0), None);
+        assert_eq!(tap_tempo.tap(5.5), BeatsPerMinute::try_from(120.0).ok());
+    }
+}
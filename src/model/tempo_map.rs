@@ -0,0 +1,399 @@
+use std::fmt;
+
+use crate::data_types::{beats_per_minute::BeatsPerMinute, tatum::TATUMS_PER_QUARTER_NOTE};
+
+/// How the tempo moves from one change to the next.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) enum TempoCurve {
+    /// Hold the tempo, jumping to the next tempo at the next change
+    Step,
+    /// Ramp evenly to the next tempo
+    Linear,
+    /// Ramp to the next tempo along `progress.powf(shape)`, so shapes above one change slowly
+    /// at first and shapes below one change quickly at first
+    Curved(f32),
+}
+
+impl TempoCurve {
+    pub(crate) const ALL: [TempoCurve; 3] = [
+        TempoCurve::Step,
+        TempoCurve::Linear,
+        TempoCurve::Curved(2.0),
+    ];
+}
+
+impl fmt::Display for TempoCurve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TempoCurve::Step => write!(f, "step"),
+            TempoCurve::Linear => write!(f, "linear"),
+            TempoCurve::Curved(_) => write!(f, "curved"),
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) struct TempoChange {
+    /// Zero based bar the tempo is reached at
+    pub bar: u32,
+    pub bpm: BeatsPerMinute,
+    /// How the tempo gets from here to the next change
+    pub curve: TempoCurve,
+}
+
+/// A stretch of the song between two tempo changes, measured in tatums per second.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) struct TempoSegment {
+    /// Where the segment starts in the song, in tatums and in seconds
+    pub start: f64,
+    pub start_seconds: f64,
+    pub start_rate: f64,
+    pub end_rate: f64,
+    /// Length in tatums, infinite for the last segment
+    pub length: f64,
+    pub curve: TempoCurve,
+    /// Curved ramps are followed along a stretched position, which reaches a progress of
+    /// `stretched.powi(stretch)` through the segment. Stretching ramps that change tempo
+    /// quickly at first spreads that change out, so it can be integrated accurately.
+    stretch: i32,
+    /// Seconds taken to reach each of the evenly spaced stretched positions along a curved
+    /// ramp, so the engine only has to integrate from the nearest one
+    curve_table: [f64; CURVE_TABLE_INTERVALS + 1],
+}
+
+/// Intervals a curved ramp is split into for its table
+const CURVE_TABLE_INTERVALS: usize = 64;
+
+/// Simpson's rule steps across at most one interval of the table. The curve is smooth so this
+/// is far below a frame of error.
+const CURVE_INTEGRATION_STEPS: usize = 4;
+
+/// Newton's method steps to find where a curved ramp is at a time, starting from a guess
+/// within one interval of the table
+const CURVE_NEWTON_STEPS: usize = 4;
+
+#[cfg(test)]
+thread_local! {
+    /// Tempos worked out on this thread, to check conversions stay cheap enough for the engine
+    pub(crate) static RATE_EVALUATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+impl TempoSegment {
+    /// A segment starting at the start of the song, with its curve tabulated if it has one.
+    pub fn new(start_rate: f64, end_rate: f64, length: f64, curve: TempoCurve) -> TempoSegment {
+        let stretch = match curve {
+            TempoCurve::Curved(shape) if shape < 1.0 => (1.0 / shape as f64).ceil() as i32,
+            _ => 1,
+        };
+        let mut segment = TempoSegment {
+            start: 0.0,
+            start_seconds: 0.0,
+            start_rate,
+            end_rate,
+            length,
+            curve,
+            stretch,
+            curve_table: [0.0; CURVE_TABLE_INTERVALS + 1],
+        };
+        if segment.is_curved() {
+            for index in 1..=CURVE_TABLE_INTERVALS {
+                let (from, to) = Self::table_interval(index - 1);
+                segment.curve_table[index] =
+                    segment.curve_table[index - 1] + segment.integrate(from, to);
+            }
+        }
+        segment
+    }
+
+    /// Tatums a second, `tatums` into the segment.
+    pub fn rate_at(&self, tatums: f64) -> f64 {
+        self.rate_at_progress(tatums / self.length)
+    }
+
+    fn rate_at_progress(&self, progress: f64) -> f64 {
+        #[cfg(test)]
+        RATE_EVALUATIONS.with(|count| count.set(count.get() + 1));
+        match self.curve {
+            TempoCurve::Step => self.start_rate,
+            TempoCurve::Linear => self.start_rate + (self.end_rate - self.start_rate) * progress,
+            TempoCurve::Curved(shape) => {
+                self.start_rate + (self.end_rate - self.start_rate) * progress.powf(shape as f64)
+            }
+        }
+    }
+
+    fn is_constant(&self) -> bool {
+        self.curve == TempoCurve::Step
+            || self.start_rate == self.end_rate
+            || self.length.is_infinite()
+    }
+
+    fn is_curved(&self) -> bool {
+        matches!(self.curve, TempoCurve::Curved(_)) && !self.is_constant()
+    }
+
+    /// The stretched positions interval `index` of the table runs between.
+    fn table_interval(index: usize) -> (f64, f64) {
+        let interval = 1.0 / CURVE_TABLE_INTERVALS as f64;
+        (index as f64 * interval, (index + 1) as f64 * interval)
+    }
+
+    /// Seconds a unit of stretched position takes to play at `stretched`.
+    fn seconds_per_stretched(&self, stretched: f64) -> f64 {
+        let tatums_per_stretched =
+            self.length * self.stretch as f64 * stretched.powi(self.stretch - 1);
+        tatums_per_stretched / self.rate_at_progress(stretched.powi(self.stretch))
+    }
+
+    /// Seconds taken to play between two stretched positions, by Simpson's rule.
+    fn integrate(&self, from: f64, to: f64) -> f64 {
+        let step = (to - from) / CURVE_INTEGRATION_STEPS as f64;
+        let mut total = self.seconds_per_stretched(from) + self.seconds_per_stretched(to);
+        for i in 1..CURVE_INTEGRATION_STEPS {
+            let weight = if i % 2 == 1 { 4.0 } else { 2.0 };
+            total += weight * self.seconds_per_stretched(from + i as f64 * step);
+        }
+        total * step / 3.0
+    }
+
+    /// Seconds taken to play the first `tatums` of the segment.
+    pub fn seconds_at(&self, tatums: f64) -> f64 {
+        if self.is_constant() {
+            return tatums / self.start_rate;
+        }
+        match self.curve {
+            TempoCurve::Linear => {
+                let slope = (self.end_rate - self.start_rate) / self.length;
+                (self.rate_at(tatums) / self.start_rate).ln() / slope
+            }
+            _ => {
+                let stretched = (tatums / self.length).powf(1.0 / self.stretch as f64);
+                let index = ((stretched * CURVE_TABLE_INTERVALS as f64) as usize)
+                    .min(CURVE_TABLE_INTERVALS - 1);
+                let (from, _) = Self::table_interval(index);
+                self.curve_table[index] + self.integrate(from, stretched)
+            }
+        }
+    }
+
+    /// How many tatums of the segment have played after `seconds`.
+    pub fn tatums_at(&self, seconds: f64) -> f64 {
+        if self.is_constant() {
+            return seconds * self.start_rate;
+        }
+        match self.curve {
+            TempoCurve::Linear => {
+                let slope = (self.end_rate - self.start_rate) / self.length;
+                self.start_rate * ((seconds * slope).exp() - 1.0) / slope
+            }
+            _ => {
+                let index = self
+                    .curve_table
+                    .partition_point(|reached| *reached <= seconds)
+                    .clamp(1, CURVE_TABLE_INTERVALS)
+                    - 1;
+                let (from, to) = Self::table_interval(index);
+                let (from_seconds, to_seconds) =
+                    (self.curve_table[index], self.curve_table[index + 1]);
+                let mut stretched =
+                    from + (to - from) * (seconds - from_seconds) / (to_seconds - from_seconds);
+                for _ in 0..CURVE_NEWTON_STEPS {
+                    let error = from_seconds + self.integrate(from, stretched) - seconds;
+                    if error == 0.0 {
+                        break;
+                    }
+                    stretched =
+                        (stretched - error / self.seconds_per_stretched(stretched)).clamp(from, to);
+                }
+                self.length * stretched.clamp(from, to).powi(self.stretch)
+            }
+        }
+    }
+}
+
+/// The tempo through the song, as changes at bar boundaries joined by curves.
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct TempoMap {
+    // Always sorted by bar, with the first change at bar zero
+    changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+    pub fn new(bpm: BeatsPerMinute) -> TempoMap {
+        TempoMap {
+            changes: vec![TempoChange {
+                bar: 0,
+                bpm,
+                curve: TempoCurve::Step,
+            }],
+        }
+    }
+
+    pub fn changes(&self) -> &[TempoChange] {
+        &self.changes
+    }
+
+    pub fn set_change(&mut self, change: TempoChange) {
+        match self.changes.binary_search_by_key(&change.bar, |c| c.bar) {
+            Ok(index) => self.changes[index] = change,
+            Err(index) => self.changes.insert(index, change),
+        }
+    }
+
+    /// Set the tempo the song starts at, keeping how it moves on to the next change.
+    pub fn set_opening_tempo(&mut self, bpm: BeatsPerMinute) {
+        self.changes[0].bpm = bpm;
+    }
+
+    /// Speed up or slow down the whole song by `amount` BPM, keeping the shape of any ramps.
+    pub fn nudge(&mut self, amount: f32) {
+        for change in self.changes.iter_mut() {
+            change.bpm = change.bpm.nudged(amount);
+        }
+    }
+
+    /// Remove the change at `bar`. The opening tempo can only be replaced, not removed.
+    pub fn remove_change(&mut self, bar: u32) {
+        if bar != 0 {
+            self.changes.retain(|c| c.bar != bar);
+        }
+    }
+
+    /// Split the map into segments, given where each bar starts in tatums. Curves are worked
+    /// out here, so following the segments afterwards is cheap.
+    pub fn segments(&self, bar_start_tatum: impl Fn(u32) -> u64) -> Vec<TempoSegment> {
+        let tatums_per_second = |bpm: BeatsPerMinute| {
+            f64::from(bpm.beats_per_second()) * TATUMS_PER_QUARTER_NOTE as f64
+        };
+        let mut start_seconds = 0.0;
+        self.changes
+            .iter()
+            .enumerate()
+            .map(|(index, change)| {
+                let mut segment = match self.changes.get(index + 1) {
+                    Some(next) => TempoSegment::new(
+                        tatums_per_second(change.bpm),
+                        tatums_per_second(next.bpm),
+                        (bar_start_tatum(next.bar) - bar_start_tatum(change.bar)) as f64,
+                        change.curve,
+                    ),
+                    None => TempoSegment::new(
+                        tatums_per_second(change.bpm),
+                        tatums_per_second(change.bpm),
+                        f64::INFINITY,
+                        TempoCurve::Step,
+                    ),
+                };
+                segment.start = bar_start_tatum(change.bar) as f64;
+                segment.start_seconds = start_seconds;
+                start_seconds += segment.seconds_at(segment.length);
+                segment
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::beats_per_minute::BeatsPerMinute,
+        model::tempo_map::{TempoChange, TempoCurve, TempoMap, TempoSegment},
+    };
+
+    fn ramp(curve: TempoCurve) -> TempoSegment {
+        // 8 tatums per second ramping to 16 over 16 tatums
+        TempoSegment::new(8.0, 16.0, 16.0, curve)
+    }
+
+    #[test]
+    fn constant_segment_is_linear_in_time() {
+        let segment = ramp(TempoCurve::Step);
+        assert_eq!(segment.seconds_at(16.0), 2.0);
+        assert_eq!(segment.tatums_at(0.5), 4.0);
+    }
+
+    #[test]
+    fn linear_ramp_is_quicker_than_holding_start_tempo() {
+        let segment = ramp(TempoCurve::Linear);
+        let seconds = segment.seconds_at(16.0);
+        assert!(seconds < 2.0 && seconds > 1.0);
+        assert!((seconds - 2.0 * 2.0_f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn linear_ramp_round_trips() {
+        let segment = ramp(TempoCurve::Linear);
+        for tatums in [0.0, 1.0, 7.5, 16.0] {
+            assert!((segment.tatums_at(segment.seconds_at(tatums)) - tatums).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn curved_ramp_matches_linear_with_shape_one() {
+        let curved = ramp(TempoCurve::Curved(1.0));
+        let linear = ramp(TempoCurve::Linear);
+        assert!((curved.seconds_at(16.0) - linear.seconds_at(16.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn curved_ramp_with_slow_start_takes_longer() {
+        let slow_start = ramp(TempoCurve::Curved(3.0));
+        let linear = ramp(TempoCurve::Linear);
+        assert!(slow_start.seconds_at(16.0) > linear.seconds_at(16.0));
+        let seconds = slow_start.seconds_at(10.0);
+        assert!((slow_start.tatums_at(seconds) - 10.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn curved_ramp_matches_fine_integral() {
+        for shape in [0.1, 0.5, 0.7, 1.5, 2.0, 3.0, 8.0] {
+            let segment = TempoSegment::new(8.0, 3.0, 256.0, TempoCurve::Curved(shape));
+            let steps = 1_000_000;
+            let step = 200.0 / steps as f64;
+            let fine: f64 = (0..steps)
+                .map(|i| step / segment.rate_at((i as f64 + 0.5) * step))
+                .sum();
+            let seconds = segment.seconds_at(200.0);
+            assert!((seconds - fine).abs() < 1e-6, "{shape}: {seconds} {fine}");
+            assert!((segment.tatums_at(seconds) - 200.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn segments_cover_map() {
+        let mut map = TempoMap::new(BeatsPerMinute::from(120));
+        map.set_change(TempoChange {
+            bar: 2,
+            bpm: BeatsPerMinute::from(60),
+            curve: TempoCurve::Step,
+        });
+        let segments = map.segments(|bar| bar as u64 * 16);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].start_rate, 8.0);
+        assert_eq!(segments[0].length, 32.0);
+        assert_eq!(segments[1].start, 32.0);
+        assert_eq!(segments[1].start_seconds, 4.0);
+        assert_eq!(segments[1].start_rate, 4.0);
+        assert!(segments[1].length.is_infinite());
+    }
+
+    #[test]
+    fn nudge_moves_every_change() {
+        let mut map = TempoMap::new(BeatsPerMinute::from(120));
+        map.set_change(TempoChange {
+            bar: 4,
+            bpm: BeatsPerMinute::from(90),
+            curve: TempoCurve::Step,
+        });
+        map.nudge(-0.5);
+        let tempos: Vec<f32> = map.changes().iter().map(|c| f32::from(c.bpm)).collect();
+        assert_eq!(tempos, vec![119.5, 89.5]);
+    }
+
+    #[test]
+    fn opening_tempo_cannot_be_removed() {
+        let mut map = TempoMap::new(BeatsPerMinute::from(120));
+        map.remove_change(0);
+        assert_eq!(map.changes().len(), 1);
+    }
+}
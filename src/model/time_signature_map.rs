@@ -0,0 +1,243 @@
+use crate::data_types::time_signature::TimeSignature;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct TimeSignatureChange {
+    /// Zero based bar the time signature starts at
+    pub bar: u32,
+    pub time_signature: TimeSignature,
+}
+
+/// Where a tatum falls in the song, all zero based.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct BarPosition {
+    pub bar: u32,
+    pub beat: u32,
+    pub tatum_in_beat: u32,
+}
+
+/// The time signature of every bar, changing only at bar boundaries.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub(crate) struct TimeSignatureMap {
+    // Always sorted by bar, with the first change at bar zero
+    changes: Vec<TimeSignatureChange>,
+}
+
+impl Default for TimeSignatureMap {
+    fn default() -> Self {
+        TimeSignatureMap::new(TimeSignature::default())
+    }
+}
+
+impl TimeSignatureMap {
+    pub fn new(time_signature: TimeSignature) -> TimeSignatureMap {
+        TimeSignatureMap {
+            changes: vec![TimeSignatureChange {
+                bar: 0,
+                time_signature,
+            }],
+        }
+    }
+
+    pub fn changes(&self) -> &[TimeSignatureChange] {
+        &self.changes
+    }
+
+    /// Change the time signature from `bar` onwards, until the next change.
+    pub fn set_time_signature(&mut self, bar: u32, time_signature: TimeSignature) {
+        let change = TimeSignatureChange {
+            bar,
+            time_signature,
+        };
+        match self.changes.binary_search_by_key(&bar, |c| c.bar) {
+            Ok(index) => self.changes[index] = change,
+            Err(index) => self.changes.insert(index, change),
+        }
+    }
+
+    /// Remove the change at `bar`, so the previous time signature carries on. The opening
+    /// time signature can only be replaced, not removed.
+    pub fn remove_change(&mut self, bar: u32) {
+        if bar != 0 {
+            self.changes.retain(|c| c.bar != bar);
+        }
+    }
+
+    pub fn time_signature_at_bar(&self, bar: u32) -> TimeSignature {
+        self.changes
+            .iter()
+            .rev()
+            .find(|c| c.bar <= bar)
+            .unwrap()
+            .time_signature
+    }
+
+    pub fn bar_start_tatum(&self, bar: u32) -> u64 {
+        let mut tatum = 0;
+        for (index, change) in self.changes.iter().enumerate() {
+            if change.bar >= bar {
+                break;
+            }
+            let section_end = self
+                .changes
+                .get(index + 1)
+                .map_or(bar, |next| next.bar.min(bar));
+            tatum +=
+                (section_end - change.bar) as u64 * change.time_signature.tatums_per_bar() as u64;
+        }
+        tatum
+    }
+
+    /// The change whose section `tatum` falls in, and the tatum that section starts at.
+    fn section_of_tatum(&self, tatum: u64) -> (&TimeSignatureChange, u64) {
+        let mut section_start_tatum = 0;
+        let mut section_change = &self.changes[0];
+        for (index, change) in self.changes.iter().enumerate() {
+            section_change = change;
+            let Some(next) = self.changes.get(index + 1) else {
+                break;
+            };
+            let section_tatums =
+                (next.bar - change.bar) as u64 * change.time_signature.tatums_per_bar() as u64;
+            if tatum < section_start_tatum + section_tatums {
+                break;
+            }
+            section_start_tatum += section_tatums;
+        }
+        (section_change, section_start_tatum)
+    }
+
+    /// The bar `tatum` falls in. Unlike [`TimeSignatureMap::position_of_tatum`] this allocates
+    /// nothing, so the engine can call it.
+    pub fn bar_of_tatum(&self, tatum: u64) -> u32 {
+        let (change, section_start_tatum) = self.section_of_tatum(tatum);
+        let tatums_per_bar = change.time_signature.tatums_per_bar() as u64;
+        change.bar + ((tatum - section_start_tatum) / tatums_per_bar) as u32
+    }
+
+    pub fn position_of_tatum(&self, tatum: u64) -> BarPosition {
+        let (position_change, section_start_tatum) = self.section_of_tatum(tatum);
+        let time_signature = position_change.time_signature;
+        let tatums_per_bar = time_signature.tatums_per_bar() as u64;
+        let tatums_into_section = tatum - section_start_tatum;
+        let bar = position_change.bar + (tatums_into_section / tatums_per_bar) as u32;
+        let mut tatum_in_beat = (tatums_into_section % tatums_per_bar) as usize;
+        let mut beat = 0;
+        for group in time_signature.beat_groups() {
+            if tatum_in_beat < group {
+                break;
+            }
+            tatum_in_beat -= group;
+            beat += 1;
+        }
+        BarPosition {
+            bar,
+            beat,
+            tatum_in_beat: tatum_in_beat as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::time_signature::TimeSignature,
+        model::time_signature_map::{BarPosition, TimeSignatureMap},
+    };
+
+    fn three_four_then_seven_eight() -> TimeSignatureMap {
+        let mut map = TimeSignatureMap::new(TimeSignature::new(3, 4).unwrap());
+        map.set_time_signature(2, TimeSignature::new(7, 8).unwrap());
+        map
+    }
+
+    #[test]
+    fn time_signature_at_bar_follows_changes() {
+        let map = three_four_then_seven_eight();
+        assert_eq!(
+            map.time_signature_at_bar(1),
+            TimeSignature::new(3, 4).unwrap()
+        );
+        assert_eq!(
+            map.time_signature_at_bar(2),
+            TimeSignature::new(7, 8).unwrap()
+        );
+        assert_eq!(
+            map.time_signature_at_bar(50),
+            TimeSignature::new(7, 8).unwrap()
+        );
+    }
+
+    #[test]
+    fn bar_start_tatum_sums_earlier_bars() {
+        let map = three_four_then_seven_eight();
+        assert_eq!(map.bar_start_tatum(0), 0);
+        assert_eq!(map.bar_start_tatum(1), 12);
+        assert_eq!(map.bar_start_tatum(2), 24);
+        assert_eq!(map.bar_start_tatum(4), 24 + 2 * 14);
+    }
+
+    #[test]
+    fn position_of_tatum_in_first_section() {
+        let map = three_four_then_seven_eight();
+        assert_eq!(
+            map.position_of_tatum(17),
+            BarPosition {
+                bar: 1,
+                beat: 1,
+                tatum_in_beat: 1
+            }
+        );
+    }
+
+    #[test]
+    fn position_of_tatum_after_change_uses_new_grouping() {
+        let map = three_four_then_seven_eight();
+        // 7/8 groups as 4 + 4 + 6 tatums, so tatum 10 of the bar is in the last beat
+        assert_eq!(
+            map.position_of_tatum(24 + 14 + 10),
+            BarPosition {
+                bar: 3,
+                beat: 2,
+                tatum_in_beat: 2
+            }
+        );
+    }
+
+    #[test]
+    fn position_of_first_tatum_of_each_bar() {
+        let map = three_four_then_seven_eight();
+        let bar_starts = [0, 12, 24, 38, 52];
+        for (bar, tatum) in bar_starts.into_iter().enumerate() {
+            assert_eq!(
+                map.position_of_tatum(tatum),
+                BarPosition {
+                    bar: bar as u32,
+                    beat: 0,
+                    tatum_in_beat: 0
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn bar_of_tatum_matches_position_of_tatum() {
+        let mut map = three_four_then_seven_eight();
+        map.set_time_signature(4, TimeSignature::new(12, 8).unwrap());
+        for tatum in 0..200 {
+            assert_eq!(map.bar_of_tatum(tatum), map.position_of_tatum(tatum).bar);
+        }
+    }
+
+    #[test]
+    fn replacing_and_removing_changes() {
+        let mut map = three_four_then_seven_eight();
+        map.set_time_signature(0, TimeSignature::new(4, 4).unwrap());
+        map.remove_change(0);
+        map.remove_change(2);
+        assert_eq!(map.changes().len(), 1);
+        assert_eq!(
+            map.time_signature_at_bar(3),
+            TimeSignature::new(4, 4).unwrap()
+        );
+    }
+}
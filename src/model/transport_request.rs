@@ -0,0 +1,9 @@
+use crate::data_types::musical_position::MusicalPosition;
+
+/// A change to the JACK transport asked for from the GUI, for the engine's client to make.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) enum TransportRequest {
+    Start,
+    Stop,
+    Locate(MusicalPosition),
+}
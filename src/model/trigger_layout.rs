@@ -0,0 +1,67 @@
+use std::fmt;
+
+use crate::{
+    data_types::{chord_degree::ChordDegree, note::Note},
+    music_theory::chords::KEY_PITCH_CLASSES,
+};
+
+/// Which keys on the trigger input play which chords, in any octave.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub(crate) enum TriggerLayout {
+    /// The seven keys up from C, black and white, play I to VII
+    #[default]
+    DegreePerKey,
+    /// Each white key plays the chord built on it in the key, so C plays I and A plays VI
+    WhiteKeys,
+}
+
+impl TriggerLayout {
+    pub(crate) const ALL: [TriggerLayout; 2] =
+        [TriggerLayout::DegreePerKey, TriggerLayout::WhiteKeys];
+
+    /// The chord `key` plays, if it plays one.
+    pub fn chord_degree(&self, key: Note) -> Option<ChordDegree> {
+        let pitch_class = u8::from(key) % 12;
+        let index = match self {
+            TriggerLayout::DegreePerKey => pitch_class as usize,
+            TriggerLayout::WhiteKeys => KEY_PITCH_CLASSES
+                .iter()
+                .position(|class| *class == pitch_class)?,
+        };
+        ChordDegree::ALL.get(index).copied()
+    }
+}
+
+impl fmt::Display for TriggerLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TriggerLayout::DegreePerKey => write!(f, "degree per key"),
+            TriggerLayout::WhiteKeys => write!(f, "white keys"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{chord_degree::ChordDegree, note::Note},
+        model::trigger_layout::TriggerLayout,
+    };
+
+    #[test]
+    fn degree_per_key_uses_the_bottom_of_each_octave() {
+        let layout = TriggerLayout::DegreePerKey;
+        assert_eq!(layout.chord_degree(Note::from(48)), Some(ChordDegree::I));
+        assert_eq!(layout.chord_degree(Note::from(61)), Some(ChordDegree::II));
+        assert_eq!(layout.chord_degree(Note::from(66)), Some(ChordDegree::VII));
+        assert_eq!(layout.chord_degree(Note::from(67)), None);
+    }
+
+    #[test]
+    fn white_keys_play_their_own_chord() {
+        let layout = TriggerLayout::WhiteKeys;
+        assert_eq!(layout.chord_degree(Note::from(62)), Some(ChordDegree::II));
+        assert_eq!(layout.chord_degree(Note::from(81)), Some(ChordDegree::VI));
+        assert_eq!(layout.chord_degree(Note::from(61)), None);
+    }
+}
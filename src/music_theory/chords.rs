@@ -24,3 +24,146 @@ pub(crate) fn chord_degreee_to_notes(chord_degree: &ChordDegree) -> [Note; 3] {
         ChordDegree::VII => diminished_triad(root_note + 11),
     }
 }
+
+/// Swap a chord for its relative, the chord a third below or above sharing two of its notes
+/// and the same function: I and VI as tonic, IV and II as subdominant, V and III. VII has no
+/// relative triad in the key, so stays as it is.
+pub(crate) fn relative(chord_degree: ChordDegree) -> ChordDegree {
+    match chord_degree {
+        ChordDegree::I => ChordDegree::VI,
+        ChordDegree::VI => ChordDegree::I,
+        ChordDegree::IV => ChordDegree::II,
+        ChordDegree::II => ChordDegree::IV,
+        ChordDegree::V => ChordDegree::III,
+        ChordDegree::III => ChordDegree::V,
+        ChordDegree::VII => ChordDegree::VII,
+    }
+}
+
+/// The chord in the key that `notes` spell, in any voicing or inversion. Extra notes such as
+/// a seventh are allowed, and when they make more than one chord fit, the one with its root
+/// nearest above the lowest note wins, so A C E G is VI rather than I.
+pub(crate) fn recognise_chord(notes: &[Note]) -> Option<ChordDegree> {
+    let pitch_classes: Vec<u8> = notes.iter().map(|note| u8::from(*note) % 12).collect();
+    let bass = notes.iter().map(|note| u8::from(*note)).min()? % 12;
+    ChordDegree::ALL
+        .into_iter()
+        .filter(|chord| {
+            chord_degreee_to_notes(chord)
+                .iter()
+                .all(|note| pitch_classes.contains(&(u8::from(*note) % 12)))
+        })
+        .min_by_key(|chord| (u8::from(chord_degreee_to_notes(chord)[0]) % 12 + 12 - bass) % 12)
+}
+
+/// Pitch classes of the major scale the chord degrees are built on.
+pub(crate) const KEY_PITCH_CLASSES: [u8; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// Move `note` to the nearest note whose pitch class is in `pitch_classes`, preferring the
+/// lower note when two are equally near.
+fn snap_to_pitch_classes(note: Note, pitch_classes: &[u8]) -> Note {
+    let note: u8 = note.into();
+    (0..=6u8)
+        .flat_map(|distance| [note.checked_sub(distance), note.checked_add(distance)])
+        .flatten()
+        .find(|candidate| *candidate <= 127 && pitch_classes.contains(&(candidate % 12)))
+        .map(Note::from)
+        .unwrap_or(Note::from(note))
+}
+
+/// Snap a note to the nearest tone of `chord_degree` in any octave, or to the key when no
+/// chord is playing.
+pub(crate) fn snap_to_chord_tones(note: Note, chord_degree: Option<&ChordDegree>) -> Note {
+    match chord_degree {
+        Some(chord_degree) => {
+            let pitch_classes = chord_degreee_to_notes(chord_degree).map(|n| u8::from(n) % 12);
+            snap_to_pitch_classes(note, &pitch_classes)
+        }
+        None => snap_to_pitch_classes(note, &KEY_PITCH_CLASSES),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        data_types::{chord_degree::ChordDegree, note::Note},
+        music_theory::chords::{recognise_chord, relative, snap_to_chord_tones},
+    };
+
+    #[test]
+    fn relatives_swap_back() {
+        for degree in ChordDegree::ALL {
+            assert_eq!(relative(relative(degree)), degree);
+        }
+        assert_eq!(relative(ChordDegree::I), ChordDegree::VI);
+    }
+
+    #[test]
+    fn chord_tones_stay_put() {
+        assert_eq!(
+            snap_to_chord_tones(Note::from(76), Some(&ChordDegree::I)),
+            Note::from(76)
+        );
+    }
+
+    #[test]
+    fn snaps_to_nearest_chord_tone_in_any_octave() {
+        // F is nearest the E of a C major triad, B nearest the C above
+        assert_eq!(
+            snap_to_chord_tones(Note::from(65), Some(&ChordDegree::I)),
+            Note::from(64)
+        );
+        assert_eq!(
+            snap_to_chord_tones(Note::from(71), Some(&ChordDegree::I)),
+            Note::from(72)
+        );
+        // D is equally near C and E of a C major triad, so goes down
+        assert_eq!(
+            snap_to_chord_tones(Note::from(50), Some(&ChordDegree::I)),
+            Note::from(48)
+        );
+    }
+
+    #[test]
+    fn snaps_to_key_without_chord() {
+        assert_eq!(snap_to_chord_tones(Note::from(61), None), Note::from(60));
+        assert_eq!(snap_to_chord_tones(Note::from(62), None), Note::from(62));
+    }
+
+    fn notes(notes: &[u8]) -> Vec<Note> {
+        notes.iter().copied().map(Note::from).collect()
+    }
+
+    #[test]
+    fn recognises_chords_in_any_voicing() {
+        assert_eq!(recognise_chord(&notes(&[60, 64, 67])), Some(ChordDegree::I));
+        assert_eq!(recognise_chord(&notes(&[52, 67, 72])), Some(ChordDegree::I));
+        assert_eq!(
+            recognise_chord(&notes(&[50, 65, 69, 74])),
+            Some(ChordDegree::II)
+        );
+        assert_eq!(
+            recognise_chord(&notes(&[71, 62, 65])),
+            Some(ChordDegree::VII)
+        );
+    }
+
+    #[test]
+    fn extra_notes_go_by_the_bass() {
+        assert_eq!(
+            recognise_chord(&notes(&[57, 60, 64, 67])),
+            Some(ChordDegree::VI)
+        );
+        assert_eq!(
+            recognise_chord(&notes(&[60, 64, 67, 69])),
+            Some(ChordDegree::I)
+        );
+    }
+
+    #[test]
+    fn incomplete_or_chromatic_chords_are_not_recognised() {
+        assert_eq!(recognise_chord(&notes(&[60, 64])), None);
+        assert_eq!(recognise_chord(&notes(&[61, 65, 68])), None);
+        assert_eq!(recognise_chord(&[]), None);
+    }
+}
@@ -1,7 +1,25 @@
-use eframe::egui::{self, Color32, FontId, Key, RichText};
+use eframe::egui::{self, Color32, FontId, Key, RichText, Sense};
 
 use crate::{
-    data_types::{chord_degree::ChordDegree, tatum::Tatum},
+    data_types::{
+        beats_per_minute::{BeatsPerMinute, MAX_BPM, MIN_BPM},
+        chord_degree::ChordDegree,
+        midi_channel::MidiChannel,
+        tatum::{Tatum, MAX_TATUMS_PER_LANE},
+        velocity::Velocity,
+    },
+    generators::{random_progression::Cadence, transforms::Transform},
+    model::{
+        automation_lane::{AutomationLane, AutomationTarget, MAX_CONTROLLER},
+        output_settings::{NoteOutput, OutputSettings, ProgramChange, MAX_BANK},
+        recording::RecordMode,
+        scene::SceneQuantise,
+        step_condition::StepCondition,
+        step_timing::{StepTiming, MAX_NUDGE_TICKS, MAX_RATCHET},
+        sync_source::SyncSource,
+        tempo_map::{TempoChange, TempoCurve},
+        trigger_layout::TriggerLayout,
+    },
     view_model::chord_sequencer_vm::ChordSequencerVm,
 };
 
@@ -39,13 +57,43 @@ fn numeric_key_pressed(input_state: &egui::InputState) -> Option<ChordDegree> {
     None
 }
 
-pub(crate) fn update(vm: &mut ChordSequencerVm, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+fn handle_shortcuts(vm: &mut ChordSequencerVm, ctx: &egui::Context) {
+    // Shift extends the selection rather than moving the cursor on its own
+    let shift = ctx.input(|i| i.modifiers.shift);
     if ctx.input(|i| i.key_pressed(Key::ArrowLeft)) {
-        vm.move_left();
+        if shift {
+            vm.extend_selection_left();
+        } else {
+            vm.move_left();
+        }
     }
 
     if ctx.input(|i| i.key_pressed(Key::ArrowRight)) {
-        vm.move_right();
+        if shift {
+            vm.extend_selection_right();
+        } else {
+            vm.move_right();
+        }
+    }
+
+    if ctx.input(|i| i.key_pressed(Key::ArrowUp)) {
+        vm.move_up();
+    }
+
+    if ctx.input(|i| i.key_pressed(Key::ArrowDown)) {
+        vm.move_down();
+    }
+
+    if ctx.input(|i| i.key_pressed(Key::Plus) || i.key_pressed(Key::Equals)) {
+        vm.lengthen_lane();
+    }
+
+    if ctx.input(|i| i.key_pressed(Key::Minus)) {
+        vm.shorten_lane();
+    }
+
+    if ctx.input(|i| i.key_pressed(Key::L)) {
+        vm.add_lane();
     }
 
     if let Some(chord_degree) = ctx.input(numeric_key_pressed) {
@@ -56,26 +104,639 @@ pub(crate) fn update(vm: &mut ChordSequencerVm, ctx: &egui::Context, _frame: &mu
         vm.set_chord(None);
     }
 
-    egui::CentralPanel::default().show(ctx, |ui| {
+    if ctx.input(|i| i.key_pressed(Key::E)) {
+        vm.fill_euclidean();
+    }
+
+    if ctx.input(|i| i.key_pressed(Key::T)) {
+        vm.tap_tempo(ctx.input(|i| i.time));
+    }
+    if ctx.input(|i| i.key_pressed(Key::G)) {
+        vm.generate();
+    }
+
+    if ctx.input(|i| i.modifiers.command && i.key_pressed(Key::Z)) {
+        if shift {
+            vm.redo();
+        } else {
+            vm.undo();
+        }
+    }
+
+    if ctx.input(|i| i.modifiers.command && i.key_pressed(Key::Y)) {
+        vm.redo();
+    }
+
+    if ctx.input(|i| i.modifiers.command && i.key_pressed(Key::D)) {
+        vm.duplicate();
+    }
+
+    handle_clipboard(vm, ctx);
+
+    // Fill is momentary, only while the key is held
+    vm.set_fill(ctx.input(|i| i.key_down(Key::F)));
+}
+
+/// Cut, copy and paste arrive as events rather than key presses, so each platform's own
+/// shortcuts work.
+fn handle_clipboard(vm: &mut ChordSequencerVm, ctx: &egui::Context) {
+    let events = ctx.input(|i| i.events.clone());
+    for event in events {
+        match event {
+            egui::Event::Copy => {
+                let text = vm.copy();
+                ctx.output_mut(|o| o.copied_text = text);
+            }
+            egui::Event::Cut => {
+                let text = vm.cut();
+                ctx.output_mut(|o| o.copied_text = text);
+            }
+            egui::Event::Paste(text) => vm.paste(&text),
+            _ => {}
+        }
+    }
+}
+
+fn condition_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    let mut condition = vm.selected_condition();
+    ui.horizontal(|ui| {
+        ui.label("Condition");
+        egui::ComboBox::from_id_source("condition")
+            .selected_text(condition.to_string())
+            .show_ui(ui, |ui| {
+                for option in StepCondition::ALL {
+                    let is_selected =
+                        std::mem::discriminant(&condition) == std::mem::discriminant(&option);
+                    if ui
+                        .selectable_label(is_selected, option.to_string())
+                        .clicked()
+                    {
+                        condition = option;
+                    }
+                }
+            });
+        if let StepCondition::LoopOf {
+            loop_number,
+            loop_count,
+        } = &mut condition
+        {
+            ui.add(
+                egui::DragValue::new(loop_count)
+                    .clamp_range(1..=16)
+                    .prefix("of "),
+            );
+            ui.add(
+                egui::DragValue::new(loop_number)
+                    .clamp_range(1..=*loop_count)
+                    .prefix("loop "),
+            );
+        }
+    });
+    if condition != vm.selected_condition() {
+        vm.set_condition(condition);
+    }
+}
+
+fn timing_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    let timing = vm.selected_timing();
+    let mut ratchet = timing.ratchet();
+    let mut nudge = timing.nudge();
+    ui.horizontal(|ui| {
+        ui.label("Ratchet");
+        ui.add(egui::DragValue::new(&mut ratchet).clamp_range(1..=MAX_RATCHET));
+        ui.label("Nudge");
+        ui.add(
+            egui::DragValue::new(&mut nudge)
+                .clamp_range(-MAX_NUDGE_TICKS..=MAX_NUDGE_TICKS)
+                .suffix(" ticks"),
+        );
+    });
+    if let Ok(new_timing) = StepTiming::new(ratchet, nudge) {
+        if new_timing != timing {
+            vm.set_timing(new_timing);
+        }
+    }
+}
+
+fn euclidean_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    let mut settings = vm.euclidean_settings();
+    let fill_clicked = ui.horizontal(|ui| {
+        ui.label("Euclidean");
+        ui.add(
+            egui::DragValue::new(&mut settings.pulses)
+                .clamp_range(0..=settings.steps)
+                .prefix("pulses "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut settings.steps)
+                .clamp_range(1..=MAX_TATUMS_PER_LANE)
+                .prefix("steps "),
+        );
+        ui.add(
+            egui::DragValue::new(&mut settings.rotation)
+                .clamp_range(0..=settings.steps - 1)
+                .prefix("rotate "),
+        );
+        ui.add(egui::TextEdit::singleline(&mut settings.progression).desired_width(120.0));
+        ui.button("Fill lane").clicked()
+    });
+    vm.set_euclidean_settings(settings);
+    if fill_clicked.inner {
+        vm.fill_euclidean();
+    }
+}
+
+fn transform_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        ui.label("Transform");
+        for transform in Transform::ALL {
+            if ui.button(transform.to_string()).clicked() {
+                vm.transform(transform);
+            }
+        }
+    });
+}
+
+fn generation_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    let mut constraints = vm.generation_constraints();
+    let (generate_clicked, reroll_clicked) = ui
+        .horizontal(|ui| {
+            ui.label("Generate");
+            ui.checkbox(&mut constraints.start_on_tonic, "start on I");
+            ui.checkbox(&mut constraints.end_on_tonic, "end on I");
+            egui::ComboBox::from_id_source("cadence")
+                .selected_text(constraints.cadence.to_string())
+                .show_ui(ui, |ui| {
+                    for cadence in Cadence::ALL {
+                        ui.selectable_value(&mut constraints.cadence, cadence, cadence.to_string());
+                    }
+                });
+            ui.add(
+                egui::DragValue::new(&mut constraints.density)
+                    .clamp_range(1..=MAX_TATUMS_PER_LANE)
+                    .prefix("chords "),
+            );
+            for degree in ChordDegree::ALL {
+                let mut allowed = constraints.allowed_degrees.contains(&degree);
+                if ui.toggle_value(&mut allowed, degree.to_string()).changed() {
+                    if allowed {
+                        constraints.allowed_degrees.push(degree);
+                    } else {
+                        constraints.allowed_degrees.retain(|&d| d != degree);
+                    }
+                }
+            }
+            ui.add(egui::DragValue::new(&mut constraints.seed).prefix("seed "));
+            (
+                ui.button("Generate").clicked(),
+                ui.button("New seed").clicked(),
+            )
+        })
+        .inner;
+    vm.set_generation_constraints(constraints);
+    if reroll_clicked {
+        vm.reroll_seed();
+    }
+    if generate_clicked || reroll_clicked {
+        vm.generate();
+    }
+
+    let takes = vm.generated_takes();
+    if !takes.is_empty() {
+        let mut recalled_take = None;
+        egui::ComboBox::from_label("Recall take")
+            .selected_text(format!("{} takes", takes.len()))
+            .show_ui(ui, |ui| {
+                for (index, take) in takes.iter().enumerate() {
+                    let description = format!("#{} seed {}", index + 1, take.constraints.seed);
+                    if ui.selectable_label(false, description).clicked() {
+                        recalled_take = Some(index);
+                    }
+                }
+            });
+        if let Some(take_index) = recalled_take {
+            vm.recall_take(take_index);
+        }
+    }
+}
+
+fn time_signature_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        ui.label("Time signature");
+        for change in vm.time_signature_changes() {
+            let mut numerator = change.time_signature.numerator();
+            let mut denominator = change.time_signature.denominator();
+            ui.label(format!("bar {}", change.bar + 1));
+            ui.add(egui::DragValue::new(&mut numerator).clamp_range(1..=32));
+            egui::ComboBox::from_id_source(("denominator", change.bar))
+                .width(40.0)
+                .selected_text(denominator.to_string())
+                .show_ui(ui, |ui| {
+                    for option in [1, 2, 4, 8, 16] {
+                        ui.selectable_value(&mut denominator, option, option.to_string());
+                    }
+                });
+            if (numerator, denominator)
+                != (
+                    change.time_signature.numerator(),
+                    change.time_signature.denominator(),
+                )
+            {
+                vm.enter_time_signature(change.bar, numerator, denominator);
+            }
+            if change.bar != 0 && ui.small_button("x").clicked() {
+                vm.remove_time_signature_change(change.bar);
+            }
+        }
+        if ui.button("Add change").clicked() {
+            let last_change = *vm.time_signature_changes().last().unwrap();
+            vm.set_time_signature(last_change.bar + 1, last_change.time_signature);
+        }
+    });
+}
+
+fn history_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        if ui
+            .add_enabled(vm.can_undo(), egui::Button::new("Undo"))
+            .clicked()
+        {
+            vm.undo();
+        }
+        if ui
+            .add_enabled(vm.can_redo(), egui::Button::new("Redo"))
+            .clicked()
+        {
+            vm.redo();
+        }
+    });
+}
+
+fn transport_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    let mut bar = vm.locate_bar() + 1;
+    let mut timebase_master = vm.timebase_master();
+    let mut sync_source = vm.sync_source();
+    let mut trigger_layout = vm.trigger_layout();
+    ui.horizontal(|ui| {
+        ui.label("Transport");
+        if ui.button("Play").clicked() {
+            vm.start_transport();
+        }
+        if ui.button("Stop").clicked() {
+            vm.stop_transport();
+        }
+        ui.add(
+            egui::DragValue::new(&mut bar)
+                .clamp_range(1..=999)
+                .prefix("bar "),
+        );
+        if ui.button("Locate").clicked() {
+            vm.set_locate_bar(bar - 1);
+            vm.locate();
+        }
+        ui.checkbox(&mut timebase_master, "Timebase master");
+        ui.label("Follow");
+        egui::ComboBox::from_id_source("sync source")
+            .selected_text(sync_source.to_string())
+            .show_ui(ui, |ui| {
+                for option in SyncSource::ALL {
+                    ui.selectable_value(&mut sync_source, option, option.to_string());
+                }
+            });
+        ui.label("Trigger keys");
+        egui::ComboBox::from_id_source("trigger layout")
+            .selected_text(trigger_layout.to_string())
+            .show_ui(ui, |ui| {
+                for option in TriggerLayout::ALL {
+                    ui.selectable_value(&mut trigger_layout, option, option.to_string());
+                }
+            });
+    });
+    vm.set_locate_bar(bar - 1);
+    vm.set_timebase_master(timebase_master);
+    vm.set_sync_source(sync_source);
+    vm.set_trigger_layout(trigger_layout);
+}
+
+fn record_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    let mut record_mode = vm.record_mode();
+    ui.horizontal(|ui| {
+        ui.label("Record");
+        egui::ComboBox::from_id_source("record mode")
+            .selected_text(record_mode.to_string())
+            .show_ui(ui, |ui| {
+                for option in RecordMode::ALL {
+                    ui.selectable_value(&mut record_mode, option, option.to_string());
+                }
+            });
+    });
+    vm.set_record_mode(record_mode);
+}
+
+/// Channels and programs are shown counting from one, as instruments show them.
+fn output_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    for output in NoteOutput::ALL {
+        let settings = vm.output_settings(output);
+        let mut channel = u8::from(settings.channel) + 1;
+        let mut velocity = u8::from(settings.velocity);
+        let mut note_off_velocity = u8::from(settings.note_off_velocity);
+        let mut send_program = settings.program.is_some();
+        let program = settings.program.unwrap_or_default();
+        let mut send_bank = program.bank().is_some();
+        let mut bank = program.bank().unwrap_or(0);
+        let mut program = program.program() + 1;
+        ui.horizontal(|ui| {
+            ui.label(format!("Output {output}"));
+            ui.add(
+                egui::DragValue::new(&mut channel)
+                    .clamp_range(1..=16)
+                    .prefix("channel "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut velocity)
+                    .clamp_range(1..=127)
+                    .prefix("velocity "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut note_off_velocity)
+                    .clamp_range(0..=127)
+                    .prefix("note off "),
+            );
+            ui.checkbox(&mut send_program, "program");
+            if send_program {
+                ui.add(egui::DragValue::new(&mut program).clamp_range(1..=128));
+                ui.checkbox(&mut send_bank, "bank");
+                if send_bank {
+                    ui.add(egui::DragValue::new(&mut bank).clamp_range(0..=MAX_BANK));
+                }
+            }
+        });
+        let program = match send_program {
+            true => ProgramChange::new(send_bank.then_some(bank), program - 1).ok(),
+            false => None,
+        };
+        if let (Ok(channel), Ok(velocity), Ok(note_off_velocity)) = (
+            MidiChannel::try_from(channel - 1),
+            Velocity::try_from(velocity),
+            Velocity::try_from(note_off_velocity),
+        ) {
+            let new_settings = OutputSettings {
+                channel,
+                velocity,
+                note_off_velocity,
+                program,
+            };
+            if new_settings != settings {
+                vm.set_output_settings(output, new_settings);
+            }
+        }
+    }
+}
+
+fn scene_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    let playing_scene = vm.scene();
+    let mut quantise = vm.scene_quantise();
+    ui.horizontal(|ui| {
+        ui.label("Scene");
+        for scene in 0..vm.scene_count() {
+            if ui
+                .selectable_label(scene == playing_scene, (scene + 1).to_string())
+                .clicked()
+            {
+                vm.switch_scene(scene);
+            }
+        }
+        if ui.button("+").clicked() {
+            vm.add_scene();
+        }
+        egui::ComboBox::from_id_source("scene quantise")
+            .selected_text(quantise.to_string())
+            .show_ui(ui, |ui| {
+                for option in SceneQuantise::ALL {
+                    ui.selectable_value(&mut quantise, option, option.to_string());
+                }
+            });
+    });
+    vm.set_scene_quantise(quantise);
+}
+
+fn tempo_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        ui.label("Tempo");
+        for change in vm.tempo_changes() {
+            let mut edited = change;
+            let mut bpm = f32::from(change.bpm);
+            ui.label(format!("bar {}", change.bar + 1));
+            ui.add(
+                egui::DragValue::new(&mut bpm)
+                    .speed(0.1)
+                    .max_decimals(2)
+                    .clamp_range(MIN_BPM..=MAX_BPM),
+            );
+            if let Ok(bpm) = BeatsPerMinute::try_from(bpm) {
+                edited.bpm = bpm;
+            }
+            egui::ComboBox::from_id_source(("tempo curve", change.bar))
+                .width(60.0)
+                .selected_text(change.curve.to_string())
+                .show_ui(ui, |ui| {
+                    for curve in TempoCurve::ALL {
+                        ui.selectable_value(&mut edited.curve, curve, curve.to_string());
+                    }
+                });
+            if let TempoCurve::Curved(mut shape) = edited.curve {
+                ui.add(
+                    egui::DragValue::new(&mut shape)
+                        .speed(0.05)
+                        .clamp_range(0.1..=8.0),
+                );
+                edited.curve = TempoCurve::Curved(shape);
+            }
+            if edited != change {
+                vm.set_tempo_change(edited);
+            }
+            if change.bar != 0 && ui.small_button("x").clicked() {
+                vm.remove_tempo_change(change.bar);
+            }
+        }
+        if ui.button("Add change").clicked() {
+            let last_change = *vm.tempo_changes().last().unwrap();
+            vm.set_tempo_change(TempoChange {
+                bar: last_change.bar + 1,
+                ..last_change
+            });
+        }
+        ui.separator();
+        if ui.button("Tap").clicked() {
+            vm.tap_tempo(ui.input(|i| i.time));
+        }
+        if ui.small_button("-").clicked() {
+            vm.nudge_tempo(-0.1);
+        }
+        if ui.small_button("+").clicked() {
+            vm.nudge_tempo(0.1);
+        }
+        ui.label(format!("{} BPM", vm.tempo_changes()[0].bpm));
+    });
+}
+
+fn automation_target_controls(
+    vm: &mut ChordSequencerVm,
+    ui: &mut egui::Ui,
+    index: usize,
+    automation: &AutomationLane,
+) {
+    let mut target = automation.target();
+    egui::ComboBox::from_id_source(("automation target", index))
+        .width(80.0)
+        .selected_text(target.to_string())
+        .show_ui(ui, |ui| {
+            for option in AutomationTarget::ALL {
+                let is_same = std::mem::discriminant(&option) == std::mem::discriminant(&target);
+                if ui.selectable_label(is_same, option.to_string()).clicked() && !is_same {
+                    target = option;
+                }
+            }
+        });
+    if let AutomationTarget::ControlChange(mut controller) = target {
+        ui.add(egui::DragValue::new(&mut controller).clamp_range(0..=MAX_CONTROLLER));
+        target = AutomationTarget::ControlChange(controller);
+    }
+    if target != automation.target() {
+        vm.set_automation_target(index, target);
+    }
+    let mut interpolate = automation.interpolate;
+    if ui.checkbox(&mut interpolate, "ramp").changed() {
+        vm.set_automation_interpolate(index, interpolate);
+    }
+}
+
+/// Automation of the selected lane, a row of values per controller. Empty steps are added
+/// with a click and cleared with a right click.
+fn automation_controls(vm: &mut ChordSequencerVm, ui: &mut egui::Ui) {
+    let steps = vm.chord_sequence().steps();
+    for (index, automation) in vm.automation_lanes() {
         ui.horizontal(|ui| {
-            for (index, chord) in vm.chord_sequence().iter().enumerate() {
-                let text = match chord {
-                    Some(c) => c.to_string(),
-                    None => ".".to_string(),
-                };
-                let centred_text = format!("{:^3}", text);
-                let (bg_colour, fg_colour) =
-                    if Tatum::try_from(index).unwrap() == vm.selected_chord() {
+            automation_target_controls(vm, ui, index, &automation);
+            let target = automation.target();
+            for step in 0..steps {
+                match automation.point(step) {
+                    Some(mut value) => {
+                        let response = ui.add(
+                            egui::DragValue::new(&mut value).clamp_range(0..=target.max_value()),
+                        );
+                        if response.secondary_clicked() {
+                            vm.set_automation_point(index, step, None);
+                        } else if response.changed() {
+                            vm.set_automation_point(index, step, Some(value));
+                        }
+                    }
+                    None => {
+                        if ui.small_button(".").clicked() {
+                            vm.set_automation_point(index, step, Some(target.default_value()));
+                        }
+                    }
+                }
+            }
+            if ui.small_button("x").clicked() {
+                vm.remove_automation_lane(index);
+            }
+        });
+    }
+    if ui.button("Add automation").clicked() {
+        vm.add_automation_lane();
+    }
+}
+
+pub(crate) fn update(vm: &mut ChordSequencerVm, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    // Don't treat typing into a text box as sequencer commands
+    if !ctx.wants_keyboard_input() {
+        handle_shortcuts(vm, ctx);
+    }
+    // A drag's worth of changes undoes in one go, so start a new group when it is released
+    if ctx.input(|i| i.pointer.any_released()) {
+        vm.end_edit_group();
+    }
+
+    egui::TopBottomPanel::top("step").show(ctx, |ui| {
+        history_controls(vm, ui);
+        transport_controls(vm, ui);
+        record_controls(vm, ui);
+        output_controls(vm, ui);
+        scene_controls(vm, ui);
+        time_signature_controls(vm, ui);
+        tempo_controls(vm, ui);
+        condition_controls(vm, ui);
+        timing_controls(vm, ui);
+    });
+
+    egui::TopBottomPanel::bottom("generators").show(ctx, |ui| {
+        euclidean_controls(vm, ui);
+        transform_controls(vm, ui);
+        generation_controls(vm, ui);
+        if let Some(message) = vm.status_message() {
+            ui.colored_label(Color32::RED, message);
+        }
+    });
+
+    egui::CentralPanel::default().show(ctx, |ui| {
+        let selected_lane = vm.selected_lane();
+        let selected_chord = vm.selected_chord();
+        let (selection_start, selection_end) = vm.selection();
+        let time_signatures = vm.time_signatures();
+        for (lane_index, lane) in vm.lanes().iter().enumerate() {
+            ui.horizontal(|ui| {
+                for (index, chord) in lane.iter().enumerate() {
+                    let tatum = Tatum::try_from(index).unwrap();
+                    // Space the steps out by beat, and further by bar, so odd meters are readable
+                    let position = time_signatures.position_of_tatum(index as u64);
+                    if index != 0 && position.tatum_in_beat == 0 {
+                        if position.beat == 0 {
+                            ui.separator();
+                        } else {
+                            ui.add_space(8.0);
+                        }
+                    }
+                    let mut text = match chord {
+                        Some(c) => c.to_string(),
+                        None => ".".to_string(),
+                    };
+                    if lane.condition(tatum).is_conditional() {
+                        text.push('?');
+                    }
+                    if chord.is_some() && !lane.timing(tatum).is_default() {
+                        text.push('~');
+                    }
+                    let centred_text = format!("{:^4}", text);
+                    let in_lane = lane_index == selected_lane;
+                    let (bg_colour, fg_colour) = if in_lane && tatum == selected_chord {
                         (Color32::BLACK, Color32::WHITE)
+                    } else if in_lane && selection_start <= tatum && tatum <= selection_end {
+                        (Color32::LIGHT_GRAY, Color32::BLACK)
                     } else {
                         (Color32::WHITE, Color32::BLACK)
                     };
-                let rich_text = RichText::new(centred_text)
-                    .background_color(bg_colour)
-                    .color(fg_colour)
-                    .font(FontId::monospace(20.0));
-                ui.label(rich_text);
-            }
-        });
+                    let rich_text = RichText::new(centred_text)
+                        .background_color(bg_colour)
+                        .color(fg_colour)
+                        .font(FontId::monospace(20.0));
+                    let response =
+                        ui.add(egui::Label::new(rich_text).sense(Sense::click_and_drag()));
+                    if response.drag_started() || response.clicked() {
+                        vm.select_steps(lane_index, tatum, tatum);
+                    } else if in_lane
+                        && ctx.dragged_id().is_some()
+                        && ui.input(|i| i.pointer.primary_down())
+                        && ui.rect_contains_pointer(response.rect)
+                    {
+                        // Dragging across a lane selects the steps passed over
+                        vm.extend_selection_to(tatum);
+                    }
+                }
+                ui.label(format!("{} steps", lane.steps()));
+            });
+        }
+        ui.separator();
+        automation_controls(vm, ui);
     });
 }
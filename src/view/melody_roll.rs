@@ -0,0 +1,109 @@
+use eframe::egui::{self, Color32, Pos2, Rect, Sense, Stroke, Vec2};
+
+use crate::{
+    data_types::{
+        musical_position::{MusicalPosition, TICKS_PER_TATUM},
+        note::Note,
+        tatum::{MAX_TATUMS_PER_LANE, TATUMS_PER_QUARTER_NOTE},
+    },
+    view_model::melody_vm::MelodyVm,
+};
+
+/// Two octaves up from middle C
+const LOWEST_PITCH: u8 = 60;
+const PITCHES: u8 = 24;
+const STEP_WIDTH: f32 = 24.0;
+const ROW_HEIGHT: f32 = 10.0;
+
+fn is_black_key(pitch: u8) -> bool {
+    matches!(pitch % 12, 1 | 3 | 6 | 8 | 10)
+}
+
+fn melody_controls(vm: &mut MelodyVm, ui: &mut egui::Ui) {
+    let mut settings = vm.settings();
+    let mut steps = vm.melody().steps();
+    ui.horizontal(|ui| {
+        ui.label("Melody");
+        ui.add(egui::DragValue::new(&mut steps).clamp_range(1..=MAX_TATUMS_PER_LANE));
+        ui.label("steps");
+        ui.checkbox(&mut settings.quantise, "grid");
+        ui.checkbox(&mut settings.snap_to_chords, "snap to chords");
+        ui.label("length");
+        ui.add(egui::DragValue::new(&mut settings.length).clamp_range(1..=MAX_TATUMS_PER_LANE));
+    });
+    if steps != vm.melody().steps() {
+        vm.set_steps(steps);
+    }
+    if settings != vm.settings() {
+        vm.set_settings(settings);
+    }
+}
+
+fn piano_roll(vm: &mut MelodyVm, ui: &mut egui::Ui) {
+    let melody = vm.melody();
+    let size = Vec2::new(
+        melody.steps() as f32 * STEP_WIDTH,
+        PITCHES as f32 * ROW_HEIGHT,
+    );
+    let (response, painter) = ui.allocate_painter(size, Sense::click());
+    let origin = response.rect.min;
+    let row_top = |pitch: u8| origin.y + (LOWEST_PITCH + PITCHES - 1 - pitch) as f32 * ROW_HEIGHT;
+    let x_of = |position: MusicalPosition| origin.x + position.tatums() as f32 * STEP_WIDTH;
+
+    for pitch in LOWEST_PITCH..LOWEST_PITCH + PITCHES {
+        let colour = if is_black_key(pitch) {
+            Color32::from_gray(30)
+        } else {
+            Color32::from_gray(45)
+        };
+        let row = Rect::from_min_size(
+            Pos2::new(origin.x, row_top(pitch)),
+            Vec2::new(size.x, ROW_HEIGHT),
+        );
+        painter.rect_filled(row, 0.0, colour);
+    }
+    for step in 0..=melody.steps() {
+        let x = origin.x + step as f32 * STEP_WIDTH;
+        let colour = if step % TATUMS_PER_QUARTER_NOTE == 0 {
+            Color32::from_gray(110)
+        } else {
+            Color32::from_gray(60)
+        };
+        painter.line_segment(
+            [Pos2::new(x, origin.y), Pos2::new(x, origin.y + size.y)],
+            Stroke::new(1.0, colour),
+        );
+    }
+    for note in melody.notes() {
+        let pitch: u8 = note.note.into();
+        if !(LOWEST_PITCH..LOWEST_PITCH + PITCHES).contains(&pitch) {
+            continue;
+        }
+        let rect = Rect::from_min_max(
+            Pos2::new(x_of(note.start), row_top(pitch)),
+            Pos2::new(x_of(note.end()), row_top(pitch) + ROW_HEIGHT),
+        );
+        painter.rect_filled(rect.shrink(1.0), 2.0, Color32::LIGHT_BLUE);
+    }
+
+    // Clicking adds a note, or removes the one clicked on
+    if let Some(pointer) = response
+        .clicked()
+        .then(|| response.interact_pointer_pos())
+        .flatten()
+    {
+        let row = ((pointer.y - origin.y) / ROW_HEIGHT) as u8;
+        let pitch = LOWEST_PITCH + PITCHES - 1 - row.min(PITCHES - 1);
+        let ticks = ((pointer.x - origin.x) / STEP_WIDTH * TICKS_PER_TATUM as f32).max(0.0);
+        vm.toggle_note(Note::from(pitch), MusicalPosition::from_ticks(ticks as u64));
+    }
+}
+
+pub(crate) fn update(vm: &mut MelodyVm, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    egui::TopBottomPanel::bottom("melody").show(ctx, |ui| {
+        melody_controls(vm, ui);
+        egui::ScrollArea::horizontal().show(ui, |ui| {
+            piano_roll(vm, ui);
+        });
+    });
+}
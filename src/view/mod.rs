@@ -1 +1,2 @@
 pub mod chord_sequencer;
+pub mod melody_roll;
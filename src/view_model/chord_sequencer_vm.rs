@@ -5,8 +5,35 @@ use std::{
 };
 
 use crate::{
-    data_types::{chord_degree::ChordDegree, tatum::Tatum},
-    model::{chord_sequence::ChordSequence, gui_state::GuiState, project_state::ProjectState},
+    data_types::{
+        chord_degree::ChordDegree, musical_position::MusicalPosition, tatum::Tatum,
+        time_signature::TimeSignature,
+    },
+    generators::{
+        euclidean::euclidean_sequence,
+        random_progression::{generate_sequence, ProgressionConstraints, SeededRandom},
+        transforms::Transform,
+    },
+    model::{
+        automation_lane::{AutomationLane, AutomationTarget},
+        chord_sequence::ChordSequence,
+        clip::Clip,
+        edit_history::edit_project,
+        generated_take::GeneratedTake,
+        gui_state::{EuclideanSettings, GuiState},
+        output_settings::{NoteOutput, OutputSettings},
+        project_state::ProjectState,
+        recording::{ChordCapture, RecordMode, RecordedKey},
+        scene::SceneQuantise,
+        step_condition::StepCondition,
+        step_timing::StepTiming,
+        sync_source::SyncSource,
+        tempo_map::TempoChange,
+        time_signature_map::{TimeSignatureChange, TimeSignatureMap},
+        transport_request::TransportRequest,
+        trigger_layout::TriggerLayout,
+    },
+    music_theory::chords::recognise_chord,
 };
 
 pub(crate) struct ChordSequencerVm {
@@ -26,41 +53,672 @@ impl ChordSequencerVm {
     }
 
     pub fn move_left(&mut self) {
+        self.clear_selection();
         self.change_chord(-1);
     }
     pub fn move_right(&mut self) {
+        self.clear_selection();
         self.change_chord(1);
     }
+    pub fn extend_selection_left(&mut self) {
+        self.start_selection();
+        self.change_chord(-1);
+    }
+    pub fn extend_selection_right(&mut self) {
+        self.start_selection();
+        self.change_chord(1);
+    }
+
+    /// Move the cursor to `to`, keeping the other end of the selection where it is.
+    pub fn extend_selection_to(&mut self, to: Tatum) {
+        self.start_selection();
+        self.gui_state.as_ref().borrow_mut().selected_chord = to;
+    }
+
+    /// Select the steps between `from` and `to` in `lane`, with the cursor on `to`.
+    pub fn select_steps(&mut self, lane: usize, from: Tatum, to: Tatum) {
+        let mut gui_state = self.gui_state.as_ref().borrow_mut();
+        gui_state.selected_lane = lane;
+        gui_state.selection_anchor = Some(from);
+        gui_state.selected_chord = to;
+    }
+
+    /// First and last selected steps, which are the same without a range selected.
+    pub fn selection(&mut self) -> (Tatum, Tatum) {
+        let gui_state = self.gui_state.as_ref().borrow();
+        let cursor = gui_state.selected_chord;
+        let anchor = gui_state.selection_anchor.unwrap_or(cursor);
+        (anchor.min(cursor), anchor.max(cursor))
+    }
+
+    /// The selected steps as text, for the clipboard.
+    pub fn copy(&mut self) -> String {
+        let (start, end) = self.selection();
+        self.chord_sequence().clip(start, end).to_string()
+    }
+
+    pub fn cut(&mut self) -> String {
+        let text = self.copy();
+        let (start, end) = self.selection();
+        let selected_lane = self.selected_lane();
+        self.edit("Cut", false, |project| {
+            project.clear_steps(selected_lane, start, end)
+        });
+        text
+    }
+
+    /// Paste steps written as text at the start of the selection, selecting what was pasted.
+    pub fn paste(&mut self, text: &str) {
+        let result = text.parse::<Clip>().map(|clip| {
+            let (start, _) = self.selection();
+            self.paste_clip(start, &clip);
+        });
+        self.gui_state.as_ref().borrow_mut().status_message =
+            result.err().map(|message| message.to_string());
+    }
+
+    /// Copy the selection into the steps straight after it.
+    pub fn duplicate(&mut self) {
+        let (start, end) = self.selection();
+        let clip = self.chord_sequence().clip(start, end);
+        let result = Tatum::try_from(usize::from(end) + 1)
+            .ok()
+            .filter(|at| usize::from(*at) < self.chord_sequence().steps())
+            .map(|at| self.paste_clip(at, &clip))
+            .ok_or("No room to duplicate after the selection");
+        self.gui_state.as_ref().borrow_mut().status_message =
+            result.err().map(|message| message.to_string());
+    }
+    pub fn move_up(&mut self) {
+        self.change_lane(-1);
+    }
+    pub fn move_down(&mut self) {
+        self.change_lane(1);
+    }
     pub fn set_chord(&mut self, chord_degree: Option<ChordDegree>) {
+        let selected_lane = self.selected_lane();
+        let selected_chord = self.selected_chord();
+        self.edit("Set chord", false, |project| {
+            project.update_chord_sequence(selected_lane, selected_chord, chord_degree)
+        });
+    }
+
+    pub fn set_condition(&mut self, condition: StepCondition) {
+        let selected_lane = self.selected_lane();
+        let selected_chord = self.selected_chord();
+        self.edit("Set condition", true, |project| {
+            project.set_condition(selected_lane, selected_chord, condition)
+        });
+    }
+
+    pub fn selected_condition(&mut self) -> StepCondition {
+        let selected_chord = self.selected_chord();
+        self.chord_sequence().condition(selected_chord)
+    }
+
+    pub fn set_timing(&mut self, timing: StepTiming) {
+        let selected_lane = self.selected_lane();
+        let selected_chord = self.selected_chord();
+        self.edit("Set timing", true, |project| {
+            project.set_timing(selected_lane, selected_chord, timing)
+        });
+    }
+
+    pub fn selected_timing(&mut self) -> StepTiming {
+        let selected_chord = self.selected_chord();
+        self.chord_sequence().timing(selected_chord)
+    }
+
+    pub fn set_fill(&mut self, fill: bool) {
+        // Called every frame, so avoid taking the write lock unless fill actually changed
+        if self.project_state.as_ref().read().unwrap().fill != fill {
+            self.project_state.as_ref().write().unwrap().fill = fill;
+        }
+    }
+
+    pub fn add_lane(&mut self) {
+        let new_lane = self.edit("Add lane", false, |project| project.add_lane());
+        let mut gui_state = self.gui_state.as_ref().borrow_mut();
+        gui_state.selected_lane = new_lane;
+        gui_state.selected_chord = Tatum::try_from(0).unwrap();
+        gui_state.selection_anchor = None;
+    }
+
+    pub fn time_signatures(&mut self) -> TimeSignatureMap {
         self.project_state
             .as_ref()
-            .write()
+            .read()
             .unwrap()
-            .update_chord_sequence(
-                self.gui_state.as_ref().borrow().selected_chord,
-                chord_degree,
-            );
+            .time
+            .time_signatures
+            .clone()
     }
 
-    pub fn chord_sequence(&mut self) -> ChordSequence {
-        // TODO: why do we have to clone the sequence, ideally want to extend the lifetime of this reference
-        return self
+    pub fn time_signature_changes(&mut self) -> Vec<TimeSignatureChange> {
+        self.time_signatures().changes().to_vec()
+    }
+
+    pub fn set_time_signature(&mut self, bar: u32, time_signature: TimeSignature) {
+        self.edit("Set time signature", true, |project| {
+            project.set_time_signature(bar, time_signature)
+        });
+        self.clamp_selected_chord();
+    }
+
+    /// Set the time signature at `bar` to one entered in the GUI, saying why if it can't be.
+    pub fn enter_time_signature(&mut self, bar: u32, numerator: u32, denominator: u32) {
+        let result = TimeSignature::new(numerator, denominator)
+            .map(|time_signature| self.set_time_signature(bar, time_signature));
+        self.gui_state.as_ref().borrow_mut().status_message =
+            result.err().map(|message| message.to_string());
+    }
+
+    pub fn remove_time_signature_change(&mut self, bar: u32) {
+        self.edit("Remove time signature", false, |project| {
+            project.remove_time_signature_change(bar)
+        });
+    }
+
+    pub fn tempo_changes(&mut self) -> Vec<TempoChange> {
+        self.project_state
+            .as_ref()
+            .read()
+            .unwrap()
+            .time
+            .tempo
+            .changes()
+            .to_vec()
+    }
+
+    pub fn set_tempo_change(&mut self, change: TempoChange) {
+        self.edit("Set tempo", true, |project| {
+            project.set_tempo_change(change)
+        });
+    }
+
+    /// Tap a quarter note at `seconds`, setting the opening tempo once the taps give one.
+    pub fn tap_tempo(&mut self, seconds: f64) {
+        let tempo = self.gui_state.as_ref().borrow_mut().tap_tempo.tap(seconds);
+        if let Some(bpm) = tempo {
+            self.edit("Tap tempo", true, |project| project.set_opening_tempo(bpm));
+        }
+    }
+
+    pub fn nudge_tempo(&mut self, amount: f32) {
+        self.edit("Nudge tempo", true, |project| project.nudge_tempo(amount));
+    }
+
+    pub fn remove_tempo_change(&mut self, bar: u32) {
+        self.edit("Remove tempo change", false, |project| {
+            project.remove_tempo_change(bar)
+        });
+    }
+
+    /// Automation lanes of the selected lane, with their index in the project.
+    pub fn automation_lanes(&mut self) -> Vec<(usize, AutomationLane)> {
+        let selected_lane = self.selected_lane();
+        self.project_state
+            .as_ref()
+            .read()
+            .unwrap()
+            .automation
+            .iter()
+            .cloned()
+            .enumerate()
+            .filter(|(_, automation)| automation.track == selected_lane)
+            .collect()
+    }
+
+    pub fn add_automation_lane(&mut self) {
+        let selected_lane = self.selected_lane();
+        self.edit("Add automation", false, |project| {
+            project.add_automation_lane(selected_lane, AutomationTarget::ALL[0])
+        });
+    }
+
+    pub fn remove_automation_lane(&mut self, index: usize) {
+        self.edit("Remove automation", false, |project| {
+            project.remove_automation_lane(index)
+        });
+    }
+
+    pub fn set_automation_target(&mut self, index: usize, target: AutomationTarget) {
+        self.edit("Set automation target", false, |project| {
+            project.set_automation_target(index, target)
+        });
+    }
+
+    pub fn set_automation_interpolate(&mut self, index: usize, interpolate: bool) {
+        self.edit("Set automation interpolation", false, |project| {
+            project.set_automation_interpolate(index, interpolate)
+        });
+    }
+
+    pub fn set_automation_point(&mut self, index: usize, step: usize, value: Option<u16>) {
+        let result = self.edit("Set automation point", true, |project| {
+            project.set_automation_point(index, step, value)
+        });
+        self.gui_state.as_ref().borrow_mut().status_message =
+            result.err().map(|message| message.to_string());
+    }
+
+    pub fn scene_count(&mut self) -> usize {
+        self.project_state.as_ref().read().unwrap().scenes.len()
+    }
+
+    pub fn scene(&mut self) -> usize {
+        self.project_state.as_ref().read().unwrap().scene
+    }
+
+    /// Add a scene copying the current one, ready to be turned into a variation.
+    pub fn add_scene(&mut self) {
+        self.edit("Add scene", false, |project| project.add_scene());
+    }
+
+    /// Queue a switch to `scene`, which the engine makes at the next bar or beat.
+    pub fn switch_scene(&mut self, scene: usize) {
+        let quantise = self.scene_quantise();
+        self.edit("Switch scene", false, |project| {
+            project.switch_scene(scene, quantise)
+        });
+        self.after_history_change(None);
+    }
+
+    pub fn scene_quantise(&mut self) -> SceneQuantise {
+        self.gui_state.as_ref().borrow().scene_quantise
+    }
+
+    pub fn set_scene_quantise(&mut self, quantise: SceneQuantise) {
+        self.gui_state.as_ref().borrow_mut().scene_quantise = quantise;
+    }
+
+    fn request_transport(&mut self, request: TransportRequest) {
+        self.gui_state
+            .as_ref()
+            .borrow_mut()
+            .transport_requests
+            .push(request);
+    }
+
+    pub fn start_transport(&mut self) {
+        self.request_transport(TransportRequest::Start);
+    }
+
+    pub fn stop_transport(&mut self) {
+        self.request_transport(TransportRequest::Stop);
+    }
+
+    pub fn locate_bar(&mut self) -> u32 {
+        self.gui_state.as_ref().borrow().locate_bar
+    }
+
+    pub fn set_locate_bar(&mut self, bar: u32) {
+        self.gui_state.as_ref().borrow_mut().locate_bar = bar;
+    }
+
+    /// Move the transport to the start of the locate bar.
+    pub fn locate(&mut self) {
+        let bar = self.locate_bar();
+        let tatum = self
             .project_state
             .as_ref()
             .read()
             .unwrap()
-            .chord_sequence
-            .clone();
+            .time
+            .time_signatures
+            .bar_start_tatum(bar);
+        self.request_transport(TransportRequest::Locate(MusicalPosition::from_tatums(
+            tatum,
+        )));
+    }
+
+    pub fn sync_source(&mut self) -> SyncSource {
+        self.project_state.as_ref().read().unwrap().sync_source
+    }
+
+    pub fn set_sync_source(&mut self, sync_source: SyncSource) {
+        // Called every frame, so avoid taking the write lock unless it actually changed
+        if self.sync_source() != sync_source {
+            self.project_state.as_ref().write().unwrap().sync_source = sync_source;
+        }
+    }
+
+    pub fn trigger_layout(&mut self) -> TriggerLayout {
+        self.project_state.as_ref().read().unwrap().trigger_layout
+    }
+
+    pub fn set_trigger_layout(&mut self, trigger_layout: TriggerLayout) {
+        // Called every frame, so avoid taking the write lock unless it actually changed
+        if self.trigger_layout() != trigger_layout {
+            self.project_state.as_ref().write().unwrap().trigger_layout = trigger_layout;
+        }
+    }
+
+    pub fn output_settings(&mut self, output: NoteOutput) -> OutputSettings {
+        self.project_state.as_ref().read().unwrap().output(output)
+    }
+
+    pub fn set_output_settings(&mut self, output: NoteOutput, settings: OutputSettings) {
+        self.edit("Set output", true, |project| {
+            project.set_output(output, settings)
+        });
+    }
+
+    pub fn record_mode(&mut self) -> RecordMode {
+        self.gui_state.as_ref().borrow().record_mode
+    }
+
+    pub fn set_record_mode(&mut self, record_mode: RecordMode) {
+        let mut gui_state = self.gui_state.as_ref().borrow_mut();
+        if gui_state.record_mode != record_mode {
+            gui_state.record_mode = record_mode;
+            // Half a chord played before switching should not end up in the next one
+            gui_state.chord_capture = ChordCapture::default();
+        }
+    }
+
+    /// Follow a key from the keyboard input, writing each chord played into the selected lane
+    /// as the record mode says.
+    pub fn record_key(&mut self, key: RecordedKey) {
+        let record_mode = self.record_mode();
+        if record_mode == RecordMode::Off {
+            return;
+        }
+        let Some(chord) = self.gui_state.as_ref().borrow_mut().chord_capture.key(key) else {
+            return;
+        };
+        let Some(chord_degree) = recognise_chord(&chord.notes) else {
+            self.gui_state.as_ref().borrow_mut().status_message =
+                Some("Not a chord in the key".to_string());
+            return;
+        };
+        let selected_lane = self.selected_lane();
+        match record_mode {
+            RecordMode::Step => {
+                let selected_chord = self.selected_chord();
+                self.edit("Step record", false, |project| {
+                    project.update_chord_sequence(selected_lane, selected_chord, Some(chord_degree))
+                });
+                self.move_right();
+            }
+            // Only while rolling, as the position stands still otherwise
+            RecordMode::Loop if chord.rolling => {
+                let steps = self.chord_sequence().steps() as u64;
+                let step = (chord.at.tatums().round() as u64 % steps) as usize;
+                self.edit("Record", false, |project| {
+                    project.update_chord_sequence(
+                        selected_lane,
+                        Tatum::try_from(step).unwrap(),
+                        Some(chord_degree),
+                    )
+                });
+            }
+            _ => {}
+        }
+    }
+
+    pub fn timebase_master(&mut self) -> bool {
+        self.gui_state.as_ref().borrow().timebase_master
+    }
+
+    pub fn set_timebase_master(&mut self, timebase_master: bool) {
+        self.gui_state.as_ref().borrow_mut().timebase_master = timebase_master;
+    }
+
+    pub fn undo(&mut self) {
+        let undone = {
+            let mut project_state = self.project_state.as_ref().write().unwrap();
+            self.gui_state
+                .as_ref()
+                .borrow_mut()
+                .history
+                .undo(&mut project_state)
+        };
+        self.after_history_change(undone.map(|description| format!("Undid {description}")));
+    }
+
+    pub fn redo(&mut self) {
+        let redone = {
+            let mut project_state = self.project_state.as_ref().write().unwrap();
+            self.gui_state
+                .as_ref()
+                .borrow_mut()
+                .history
+                .redo(&mut project_state)
+        };
+        self.after_history_change(redone.map(|description| format!("Redid {description}")));
+    }
+
+    pub fn can_undo(&mut self) -> bool {
+        self.gui_state.as_ref().borrow().history.can_undo()
+    }
+
+    pub fn can_redo(&mut self) -> bool {
+        self.gui_state.as_ref().borrow().history.can_redo()
+    }
+
+    /// Stop the next edit joining the last one, such as when a drag is released.
+    pub fn end_edit_group(&mut self) {
+        self.gui_state.as_ref().borrow_mut().history.end_group();
+    }
+
+    pub fn lengthen_lane(&mut self) {
+        self.change_lane_steps(1);
+    }
+    pub fn shorten_lane(&mut self) {
+        self.change_lane_steps(-1);
+    }
+
+    pub fn euclidean_settings(&mut self) -> EuclideanSettings {
+        self.gui_state.as_ref().borrow().euclidean.clone()
+    }
+
+    pub fn set_euclidean_settings(&mut self, settings: EuclideanSettings) {
+        self.gui_state.as_ref().borrow_mut().euclidean = settings;
+    }
+
+    /// Replace the selected lane with the progression spread over a euclidean rhythm.
+    pub fn fill_euclidean(&mut self) {
+        let result = self.try_fill_euclidean();
+        self.gui_state.as_ref().borrow_mut().status_message =
+            result.err().map(|message| message.to_string());
+    }
+
+    /// Replace the selected lane with a transformed copy of it.
+    pub fn transform(&mut self, transform: Transform) {
+        let selected_lane = self.selected_lane();
+        let transformed = transform.apply(&self.chord_sequence());
+        self.edit("Transform", false, |project| {
+            project.replace_lane(selected_lane, transformed)
+        });
+        self.clamp_selected_chord();
+    }
+
+    pub fn generation_constraints(&mut self) -> ProgressionConstraints {
+        self.gui_state.as_ref().borrow().generation.clone()
+    }
+
+    pub fn set_generation_constraints(&mut self, constraints: ProgressionConstraints) {
+        self.gui_state.as_ref().borrow_mut().generation = constraints;
+    }
+
+    /// Fill the selected lane with a progression satisfying the constraints, keeping the
+    /// result in the project so it can be recalled later.
+    pub fn generate(&mut self) {
+        let result = self.try_generate();
+        self.gui_state.as_ref().borrow_mut().status_message =
+            result.err().map(|message| message.to_string());
+    }
+
+    pub fn reroll_seed(&mut self) {
+        let mut gui_state = self.gui_state.as_ref().borrow_mut();
+        gui_state.generation.seed = SeededRandom::new(gui_state.generation.seed).next_u64();
+    }
+
+    pub fn generated_takes(&mut self) -> Vec<GeneratedTake> {
+        self.project_state
+            .as_ref()
+            .read()
+            .unwrap()
+            .generated_takes
+            .clone()
+    }
+
+    pub fn recall_take(&mut self, take_index: usize) {
+        let take = self.generated_takes()[take_index].clone();
+        let selected_lane = self.selected_lane();
+        self.edit("Recall take", false, |project| {
+            project.replace_lane(selected_lane, take.sequence)
+        });
+        self.set_generation_constraints(take.constraints);
+        self.clamp_selected_chord();
+    }
+
+    pub fn status_message(&mut self) -> Option<String> {
+        self.gui_state.as_ref().borrow().status_message.clone()
+    }
+
+    pub fn chord_sequence(&mut self) -> ChordSequence {
+        // TODO: why do we have to clone the sequence, ideally want to extend the lifetime of this reference
+        let selected_lane = self.selected_lane();
+        self.project_state.as_ref().read().unwrap().lanes[selected_lane].clone()
+    }
+
+    pub fn lanes(&mut self) -> Vec<ChordSequence> {
+        self.project_state.as_ref().read().unwrap().lanes.clone()
+    }
+
+    pub fn selected_lane(&mut self) -> usize {
+        self.gui_state.as_ref().borrow().selected_lane
     }
 
     pub fn selected_chord(&mut self) -> Tatum {
         self.gui_state.as_ref().borrow().selected_chord
     }
 
+    fn edit<T>(
+        &mut self,
+        description: &'static str,
+        grouped: bool,
+        edit: impl FnOnce(&mut ProjectState) -> T,
+    ) -> T {
+        edit_project(
+            &self.project_state,
+            &self.gui_state,
+            description,
+            grouped,
+            edit,
+        )
+    }
+
+    /// Undo, redo and switching scenes can remove lanes or shorten them, so keep the cursor
+    /// on the project.
+    fn after_history_change(&mut self, message: Option<String>) {
+        let lane_count = self.project_state.as_ref().read().unwrap().lanes.len();
+        {
+            let mut gui_state = self.gui_state.as_ref().borrow_mut();
+            gui_state.selected_lane = gui_state.selected_lane.min(lane_count - 1);
+            gui_state.status_message = message;
+            gui_state.selection_anchor = None;
+        }
+        self.clamp_selected_chord();
+    }
+
+    fn paste_clip(&mut self, at: Tatum, clip: &Clip) {
+        let selected_lane = self.selected_lane();
+        let pasted = self.edit("Paste", false, |project| {
+            project.paste_clip(selected_lane, at, clip)
+        });
+        let end = Tatum::try_from(usize::from(at) + pasted - 1).unwrap();
+        self.select_steps(selected_lane, at, end);
+    }
+
+    fn start_selection(&mut self) {
+        let mut gui_state = self.gui_state.as_ref().borrow_mut();
+        if gui_state.selection_anchor.is_none() {
+            gui_state.selection_anchor = Some(gui_state.selected_chord);
+        }
+    }
+
+    fn clear_selection(&mut self) {
+        self.gui_state.as_ref().borrow_mut().selection_anchor = None;
+    }
+
     fn change_chord(&mut self, delta: i32) {
-        let new_selected_modulo_chord = self.gui_state.as_ref().borrow().selected_chord.add(delta);
+        let steps = self.chord_sequence().steps();
+        let new_selected_modulo_chord = self
+            .gui_state
+            .as_ref()
+            .borrow()
+            .selected_chord
+            .add_within(delta, steps);
         self.gui_state.as_ref().borrow_mut().selected_chord = new_selected_modulo_chord;
     }
+
+    fn change_lane(&mut self, delta: i32) {
+        let lane_count = self.project_state.as_ref().read().unwrap().lanes.len();
+        let new_lane = (self.selected_lane() as i32 + delta).rem_euclid(lane_count as i32) as usize;
+        self.clear_selection();
+        self.gui_state.as_ref().borrow_mut().selected_lane = new_lane;
+        self.clamp_selected_chord();
+    }
+
+    fn change_lane_steps(&mut self, delta: i32) {
+        let selected_lane = self.selected_lane();
+        let new_steps = self.chord_sequence().steps() as i32 + delta;
+        if new_steps < 1 {
+            return;
+        }
+        // Lanes have a maximum length, so ignore attempts to go past that
+        let _ = self.edit("Change lane length", true, |project| {
+            project.set_lane_steps(selected_lane, new_steps as usize)
+        });
+        self.clamp_selected_chord();
+    }
+
+    fn try_fill_euclidean(&mut self) -> Result<(), &'static str> {
+        let settings = self.euclidean_settings();
+        let progression = settings
+            .progression
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<ChordDegree>, _>>()?;
+        let sequence = euclidean_sequence(
+            &progression,
+            settings.pulses,
+            settings.steps,
+            settings.rotation,
+        )?;
+        let selected_lane = self.selected_lane();
+        self.edit("Euclidean fill", false, |project| {
+            project.replace_lane(selected_lane, sequence)
+        });
+        self.clamp_selected_chord();
+        Ok(())
+    }
+
+    fn try_generate(&mut self) -> Result<(), &'static str> {
+        let constraints = self.generation_constraints();
+        let steps = self.chord_sequence().steps();
+        let sequence = generate_sequence(&constraints, steps)?;
+        let selected_lane = self.selected_lane();
+        self.edit("Generate", false, |project| {
+            project.replace_lane(selected_lane, sequence.clone());
+            project.add_generated_take(GeneratedTake {
+                constraints,
+                sequence,
+            });
+        });
+        Ok(())
+    }
+
+    fn clamp_selected_chord(&mut self) {
+        let steps = self.chord_sequence().steps();
+        let mut gui_state = self.gui_state.as_ref().borrow_mut();
+        let last_step = Tatum::try_from(steps - 1).unwrap();
+        gui_state.selected_chord = gui_state.selected_chord.min(last_step);
+        gui_state.selection_anchor = gui_state.selection_anchor.map(|a| a.min(last_step));
+    }
 }
 
 #[cfg(test)]
@@ -72,8 +730,28 @@ mod tests {
     };
 
     use crate::{
-        data_types::{chord_degree::ChordDegree, tatum::Tatum},
-        model::{chord_sequence::ChordSequence, make_application_state},
+        data_types::{
+            beats_per_minute::BeatsPerMinute,
+            chord_degree::ChordDegree,
+            midi_channel::MidiChannel,
+            musical_position::MusicalPosition,
+            note::Note,
+            tatum::{Tatum, MAX_TATUMS_PER_LANE},
+            time_signature::TimeSignature,
+        },
+        generators::{random_progression::ProgressionConstraints, transforms::Transform},
+        model::{
+            chord_sequence::ChordSequence,
+            gui_state::EuclideanSettings,
+            make_application_state,
+            output_settings::{NoteOutput, OutputSettings, ProgramChange},
+            recording::{RecordMode, RecordedKey},
+            step_condition::StepCondition,
+            step_timing::StepTiming,
+            sync_source::SyncSource,
+            tempo_map::{TempoChange, TempoCurve},
+            transport_request::TransportRequest,
+        },
         view_model::chord_sequencer_vm::ChordSequencerVm,
     };
 
@@ -115,31 +793,684 @@ mod tests {
         vm.move_right();
         vm.set_chord(Some(ChordDegree::II));
         assert_eq!(
-            vm.project_state.as_ref().read().unwrap().chord_sequence[Tatum::try_from(1).unwrap()],
+            vm.project_state.as_ref().read().unwrap().lanes[0][Tatum::try_from(1).unwrap()],
             Some(ChordDegree::II)
         );
     }
 
     #[test]
-    fn get_chord_sequence() {
-        let (mut project_state, gui_state) = make_application_state();
-        let chord_sequence = ChordSequence::new(Vec::from([Some(ChordDegree::II)])).unwrap();
-        project_state.chord_sequence = chord_sequence.clone();
+    fn test_set_chord_in_second_lane() {
+        let (project_state, gui_state) = make_application_state();
         let mut vm = ChordSequencerVm::new(
             Rc::new(RefCell::new(gui_state)),
             Arc::new(RwLock::new(project_state)),
         );
-        assert_eq!(&vm.chord_sequence(), &chord_sequence);
+        vm.add_lane();
+        vm.set_chord(Some(ChordDegree::IV));
+        let project_state = vm.project_state.as_ref().read().unwrap();
+        assert_eq!(project_state.lanes[0][Tatum::try_from(0).unwrap()], None);
+        assert_eq!(
+            project_state.lanes[1][Tatum::try_from(0).unwrap()],
+            Some(ChordDegree::IV)
+        );
     }
 
     #[test]
-    fn get_selected_chord() {
-        let (project_state, mut gui_state) = crate::model::make_application_state();
-        gui_state.selected_chord = Tatum::try_from(10).unwrap();
+    fn test_move_left_wraps_within_short_lane() {
+        let (project_state, gui_state) = make_application_state();
         let mut vm = ChordSequencerVm::new(
             Rc::new(RefCell::new(gui_state)),
             Arc::new(RwLock::new(project_state)),
         );
-        assert_eq!(vm.selected_chord(), Tatum::try_from(10).unwrap());
+        for _ in 0..4 {
+            vm.shorten_lane();
+        }
+        vm.move_left();
+        assert_eq!(vm.selected_chord(), Tatum::try_from(11).unwrap());
+    }
+
+    #[test]
+    fn test_shorten_lane_keeps_selection_inside_lane() {
+        let (project_state, mut gui_state) = make_application_state();
+        gui_state.selected_chord = Tatum::try_from(15).unwrap();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.shorten_lane();
+        assert_eq!(vm.chord_sequence().steps(), 15);
+        assert_eq!(vm.selected_chord(), Tatum::try_from(14).unwrap());
+    }
+
+    #[test]
+    fn test_lengthen_lane_stops_at_max_length() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        for _ in 0..MAX_TATUMS_PER_LANE {
+            vm.lengthen_lane();
+        }
+        assert_eq!(vm.chord_sequence().steps(), MAX_TATUMS_PER_LANE);
+    }
+
+    #[test]
+    fn test_set_opening_time_signature_resizes_lane() {
+        let (project_state, mut gui_state) = make_application_state();
+        gui_state.selected_chord = Tatum::try_from(15).unwrap();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_time_signature(0, TimeSignature::new(3, 4).unwrap());
+        assert_eq!(vm.chord_sequence().steps(), 12);
+        assert_eq!(vm.selected_chord(), Tatum::try_from(11).unwrap());
+        assert_eq!(vm.time_signature_changes().len(), 1);
+    }
+
+    #[test]
+    fn test_time_signature_longer_than_a_lane_is_refused() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.enter_time_signature(0, 9, 4);
+        assert!(vm.status_message().is_some());
+        assert_eq!(vm.chord_sequence().steps(), 16);
+        assert_eq!(
+            vm.time_signatures().time_signature_at_bar(0),
+            TimeSignature::default()
+        );
+        vm.enter_time_signature(0, 8, 4);
+        assert_eq!(vm.status_message(), None);
+        assert_eq!(vm.chord_sequence().steps(), 32);
+    }
+
+    #[test]
+    fn test_tempo_changes() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_tempo_change(TempoChange {
+            bar: 8,
+            bpm: BeatsPerMinute::from(60),
+            curve: TempoCurve::Step,
+        });
+        assert_eq!(vm.tempo_changes().len(), 2);
+        vm.remove_tempo_change(8);
+        vm.remove_tempo_change(0);
+        assert_eq!(vm.tempo_changes().len(), 1);
+    }
+
+    #[test]
+    fn test_tap_tempo_sets_opening_tempo() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        for tap in [10.0, 10.6, 11.2] {
+            vm.tap_tempo(tap);
+        }
+        assert_eq!(f32::from(vm.tempo_changes()[0].bpm), 100.0);
+        vm.nudge_tempo(0.5);
+        assert_eq!(f32::from(vm.tempo_changes()[0].bpm), 100.5);
+    }
+
+    #[test]
+    fn test_automation_lanes_belong_to_selected_lane() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.add_automation_lane();
+        vm.set_automation_point(0, 3, Some(100));
+        vm.add_lane();
+        assert!(vm.automation_lanes().is_empty());
+        vm.move_up();
+        let automation = vm.automation_lanes();
+        assert_eq!(automation.len(), 1);
+        assert_eq!(automation[0].1.point(3), Some(100));
+    }
+
+    #[test]
+    fn test_automation_value_out_of_range_is_reported() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.add_automation_lane();
+        vm.set_automation_point(0, 0, Some(200));
+        assert!(vm.status_message().is_some());
+        assert_eq!(vm.automation_lanes()[0].1.point(0), None);
+    }
+
+    #[test]
+    fn test_move_down_wraps_lanes() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.add_lane();
+        vm.move_down();
+        assert_eq!(vm.selected_lane(), 0);
+        vm.move_up();
+        assert_eq!(vm.selected_lane(), 1);
+    }
+
+    #[test]
+    fn test_extend_selection_then_move_clears_it() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.move_right();
+        vm.move_right();
+        vm.extend_selection_left();
+        vm.extend_selection_left();
+        assert_eq!(
+            vm.selection(),
+            (Tatum::try_from(0).unwrap(), Tatum::try_from(2).unwrap())
+        );
+        vm.move_right();
+        assert_eq!(
+            vm.selection(),
+            (Tatum::try_from(1).unwrap(), Tatum::try_from(1).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_cut_and_paste_moves_steps() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_chord(Some(ChordDegree::IV));
+        vm.extend_selection_right();
+        vm.set_chord(Some(ChordDegree::V));
+        let text = vm.cut();
+        assert_eq!(text, "IV V");
+        assert_eq!(vm.chord_sequence()[Tatum::try_from(0).unwrap()], None);
+        vm.select_steps(0, Tatum::try_from(8).unwrap(), Tatum::try_from(8).unwrap());
+        vm.paste(&text);
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(9).unwrap()],
+            Some(ChordDegree::V)
+        );
+        assert_eq!(
+            vm.selection(),
+            (Tatum::try_from(8).unwrap(), Tatum::try_from(9).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_paste_reports_bad_text() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.paste("IV banana");
+        assert!(vm.status_message().is_some());
+        assert_eq!(vm.chord_sequence()[Tatum::try_from(0).unwrap()], None);
+    }
+
+    #[test]
+    fn test_duplicate_copies_after_selection() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_chord(Some(ChordDegree::VI));
+        vm.extend_selection_right();
+        vm.duplicate();
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(2).unwrap()],
+            Some(ChordDegree::VI)
+        );
+        assert_eq!(
+            vm.selection(),
+            (Tatum::try_from(2).unwrap(), Tatum::try_from(3).unwrap())
+        );
+        vm.select_steps(
+            0,
+            Tatum::try_from(15).unwrap(),
+            Tatum::try_from(15).unwrap(),
+        );
+        vm.duplicate();
+        assert!(vm.status_message().is_some());
+    }
+
+    #[test]
+    fn test_undo_and_redo_chord() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_chord(Some(ChordDegree::IV));
+        vm.undo();
+        assert_eq!(vm.chord_sequence()[Tatum::try_from(0).unwrap()], None);
+        assert_eq!(vm.status_message(), Some("Undid Set chord".to_string()));
+        vm.redo();
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(0).unwrap()],
+            Some(ChordDegree::IV)
+        );
+    }
+
+    #[test]
+    fn test_undo_add_lane_moves_cursor_back() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.add_lane();
+        assert_eq!(vm.selected_lane(), 1);
+        vm.undo();
+        assert_eq!(vm.lanes().len(), 1);
+        assert_eq!(vm.selected_lane(), 0);
+    }
+
+    #[test]
+    fn test_nudges_undo_together() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.nudge_tempo(1.0);
+        vm.nudge_tempo(1.0);
+        vm.undo();
+        assert_eq!(vm.tempo_changes()[0].bpm, BeatsPerMinute::from(120));
+        assert!(!vm.can_undo());
+    }
+
+    #[test]
+    fn test_half_time_lengthens_selected_lane() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.move_right();
+        vm.set_chord(Some(ChordDegree::V));
+        vm.transform(Transform::HalfTime);
+        assert_eq!(vm.chord_sequence().steps(), 32);
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(2).unwrap()],
+            Some(ChordDegree::V)
+        );
+        vm.transform(Transform::DoubleTime);
+        assert_eq!(vm.chord_sequence().steps(), 16);
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(1).unwrap()],
+            Some(ChordDegree::V)
+        );
+    }
+
+    #[test]
+    fn test_switch_scene_keeps_cursor_in_scene() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.add_scene();
+        vm.add_lane();
+        vm.set_chord(Some(ChordDegree::III));
+        vm.switch_scene(1);
+        assert_eq!(vm.scene(), 1);
+        assert_eq!(vm.lanes().len(), 1);
+        assert_eq!(vm.selected_lane(), 0);
+        vm.switch_scene(0);
+        assert_eq!(
+            vm.lanes()[1][Tatum::try_from(0).unwrap()],
+            Some(ChordDegree::III)
+        );
+    }
+
+    #[test]
+    fn get_chord_sequence() {
+        let (mut project_state, gui_state) = make_application_state();
+        let chord_sequence = ChordSequence::new(Vec::from([Some(ChordDegree::II)])).unwrap();
+        project_state.lanes = vec![chord_sequence.clone()];
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        assert_eq!(&vm.chord_sequence(), &chord_sequence);
+    }
+
+    #[test]
+    fn get_selected_chord() {
+        let (project_state, mut gui_state) = crate::model::make_application_state();
+        gui_state.selected_chord = Tatum::try_from(10).unwrap();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        assert_eq!(vm.selected_chord(), Tatum::try_from(10).unwrap());
+    }
+
+    #[test]
+    fn test_fill_euclidean_replaces_selected_lane() {
+        let (project_state, mut gui_state) = make_application_state();
+        gui_state.selected_chord = Tatum::try_from(12).unwrap();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_euclidean_settings(EuclideanSettings {
+            pulses: 3,
+            steps: 8,
+            rotation: 0,
+            progression: "I vi".to_string(),
+        });
+        vm.fill_euclidean();
+        let chords: Vec<Option<ChordDegree>> = vm.chord_sequence().iter().copied().collect();
+        assert_eq!(
+            chords,
+            vec![
+                Some(ChordDegree::I),
+                None,
+                None,
+                Some(ChordDegree::VI),
+                None,
+                None,
+                Some(ChordDegree::I),
+                None
+            ]
+        );
+        assert_eq!(vm.selected_chord(), Tatum::try_from(7).unwrap());
+        assert_eq!(vm.status_message(), None);
+    }
+
+    #[test]
+    fn test_fill_euclidean_with_bad_progression_keeps_lane() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_chord(Some(ChordDegree::II));
+        vm.set_euclidean_settings(EuclideanSettings {
+            progression: "I IX".to_string(),
+            ..EuclideanSettings::default()
+        });
+        vm.fill_euclidean();
+        assert_eq!(vm.chord_sequence().steps(), 16);
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(0).unwrap()],
+            Some(ChordDegree::II)
+        );
+        assert!(vm.status_message().is_some());
+    }
+
+    #[test]
+    fn test_generate_fills_lane_and_keeps_take() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.generate();
+        let takes = vm.generated_takes();
+        assert_eq!(takes.len(), 1);
+        assert_eq!(takes[0].sequence, vm.chord_sequence());
+        assert_eq!(takes[0].constraints, vm.generation_constraints());
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(0).unwrap()],
+            Some(ChordDegree::I)
+        );
+    }
+
+    #[test]
+    fn test_recall_take_restores_sequence_and_seed() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_generation_constraints(ProgressionConstraints {
+            density: 8,
+            ..ProgressionConstraints::default()
+        });
+        vm.generate();
+        let first_take = vm.chord_sequence();
+        let first_seed = vm.generation_constraints().seed;
+        vm.reroll_seed();
+        vm.generate();
+        assert_ne!(vm.generation_constraints().seed, first_seed);
+
+        vm.recall_take(0);
+        assert_eq!(vm.chord_sequence(), first_take);
+        assert_eq!(vm.generation_constraints().seed, first_seed);
+    }
+
+    #[test]
+    fn test_generate_with_impossible_constraints_reports_error() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_generation_constraints(ProgressionConstraints {
+            allowed_degrees: vec![],
+            ..ProgressionConstraints::default()
+        });
+        vm.generate();
+        assert!(vm.status_message().is_some());
+        assert!(vm.generated_takes().is_empty());
+    }
+
+    #[test]
+    fn test_set_condition_on_selected_step() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.move_right();
+        vm.set_condition(StepCondition::FirstLoop);
+        assert_eq!(vm.selected_condition(), StepCondition::FirstLoop);
+        assert_eq!(
+            vm.chord_sequence().condition(Tatum::try_from(0).unwrap()),
+            StepCondition::Always
+        );
+    }
+
+    #[test]
+    fn test_set_timing_on_selected_step_undoes_in_one_go() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        for nudge in [10, 20, 30] {
+            vm.set_timing(StepTiming::new(2, nudge).unwrap());
+        }
+        assert_eq!(vm.selected_timing(), StepTiming::new(2, 30).unwrap());
+        vm.undo();
+        assert!(vm.selected_timing().is_default());
+    }
+
+    #[test]
+    fn test_set_fill() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_fill(true);
+        assert!(vm.project_state.as_ref().read().unwrap().fill);
+    }
+
+    /// Press then let go of `notes` on the keyboard input at `tatum`.
+    fn play_chord(vm: &mut ChordSequencerVm, notes: [u8; 3], tatum: u64, rolling: bool) {
+        for pressed in [true, false] {
+            for note in notes {
+                vm.record_key(RecordedKey {
+                    note: Note::from(note),
+                    pressed,
+                    rolling,
+                    at: MusicalPosition::from_tatums(tatum),
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn test_step_record_writes_chord_and_moves_on() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        play_chord(&mut vm, [60, 64, 67], 0, false);
+        assert!(vm.chord_sequence()[Tatum::try_from(0).unwrap()].is_none());
+
+        vm.set_record_mode(RecordMode::Step);
+        play_chord(&mut vm, [57, 60, 64], 0, false);
+        play_chord(&mut vm, [59, 62, 67], 0, false);
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(0).unwrap()],
+            Some(ChordDegree::VI)
+        );
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(1).unwrap()],
+            Some(ChordDegree::V)
+        );
+        assert_eq!(vm.selected_chord(), Tatum::try_from(2).unwrap());
+
+        play_chord(&mut vm, [60, 61, 62], 0, false);
+        assert!(vm.status_message().is_some());
+        assert_eq!(vm.selected_chord(), Tatum::try_from(2).unwrap());
+    }
+
+    #[test]
+    fn test_loop_record_quantises_into_lane_while_rolling() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_record_mode(RecordMode::Loop);
+        // Three loops of the sixteen step lane in, and a little late
+        for pressed in [true, false] {
+            vm.record_key(RecordedKey {
+                note: Note::from(65),
+                pressed,
+                rolling: true,
+                at: MusicalPosition::from_ticks((3 * 16 + 4) * 240 + 100),
+            });
+            for note in [69, 72] {
+                vm.record_key(RecordedKey {
+                    note: Note::from(note),
+                    pressed,
+                    rolling: true,
+                    at: MusicalPosition::from_ticks((3 * 16 + 5) * 240),
+                });
+            }
+        }
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(4).unwrap()],
+            Some(ChordDegree::IV)
+        );
+        // Placed by the first key down, which is late enough to round up
+        for (note, ticks) in [(60, 9 * 240 + 130), (64, 10 * 240), (67, 10 * 240 + 50)] {
+            vm.record_key(RecordedKey {
+                note: Note::from(note),
+                pressed: true,
+                rolling: true,
+                at: MusicalPosition::from_ticks(ticks),
+            });
+        }
+        for note in [60, 64, 67] {
+            vm.record_key(RecordedKey {
+                note: Note::from(note),
+                pressed: false,
+                rolling: true,
+                at: MusicalPosition::from_tatums(11),
+            });
+        }
+        assert_eq!(
+            vm.chord_sequence()[Tatum::try_from(10).unwrap()],
+            Some(ChordDegree::I)
+        );
+        // Nothing is written while the transport stands still
+        play_chord(&mut vm, [67, 71, 74], 12, false);
+        assert!(vm.chord_sequence()[Tatum::try_from(12).unwrap()].is_none());
+        assert_eq!(vm.selected_chord(), Tatum::try_from(0).unwrap());
+    }
+
+    #[test]
+    fn test_sync_source_is_not_undone() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_sync_source(SyncSource::MidiClock);
+        assert!(!vm.can_undo());
+        assert_eq!(vm.sync_source(), SyncSource::MidiClock);
+    }
+
+    #[test]
+    fn test_output_settings_are_undone() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        let settings = OutputSettings {
+            channel: MidiChannel::try_from(9).unwrap(),
+            program: Some(ProgramChange::new(None, 4).unwrap()),
+            ..vm.output_settings(NoteOutput::Melody)
+        };
+        vm.set_output_settings(NoteOutput::Melody, settings);
+        assert_eq!(vm.output_settings(NoteOutput::Melody), settings);
+        assert_eq!(
+            vm.output_settings(NoteOutput::Chords),
+            OutputSettings::default()
+        );
+        vm.undo();
+        assert_ne!(vm.output_settings(NoteOutput::Melody), settings);
+    }
+
+    #[test]
+    fn test_transport_requests_are_queued_in_order() {
+        let (mut project_state, gui_state) = make_application_state();
+        project_state
+            .time
+            .time_signatures
+            .set_time_signature(1, TimeSignature::new(3, 4).unwrap());
+        let mut vm = ChordSequencerVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.start_transport();
+        vm.set_locate_bar(2);
+        vm.locate();
+        vm.stop_transport();
+        assert_eq!(
+            vm.gui_state.as_ref().borrow().transport_requests,
+            vec![
+                TransportRequest::Start,
+                TransportRequest::Locate(MusicalPosition::from_tatums(16 + 12)),
+                TransportRequest::Stop,
+            ]
+        );
     }
 }
@@ -0,0 +1,168 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    data_types::{
+        musical_position::{MusicalPosition, TICKS_PER_TATUM},
+        note::Note,
+    },
+    model::{
+        edit_history::edit_project,
+        gui_state::{GuiState, MelodySettings},
+        melody_lane::{quantise, MelodyLane, MelodyNote},
+        project_state::ProjectState,
+    },
+    music_theory::chords::snap_to_chord_tones,
+};
+
+pub(crate) struct MelodyVm {
+    gui_state: Rc<RefCell<GuiState>>,
+    project_state: Arc<RwLock<ProjectState>>,
+}
+
+impl MelodyVm {
+    pub fn new(
+        gui_state: Rc<RefCell<GuiState>>,
+        project_state: Arc<RwLock<ProjectState>>,
+    ) -> MelodyVm {
+        MelodyVm {
+            gui_state,
+            project_state,
+        }
+    }
+
+    pub fn melody(&mut self) -> MelodyLane {
+        self.project_state.as_ref().read().unwrap().melody.clone()
+    }
+
+    pub fn settings(&mut self) -> MelodySettings {
+        self.gui_state.as_ref().borrow().melody.clone()
+    }
+
+    pub fn set_settings(&mut self, settings: MelodySettings) {
+        self.gui_state.as_ref().borrow_mut().melody = settings;
+    }
+
+    pub fn set_steps(&mut self, steps: usize) {
+        let result = self.edit("Set melody length", true, |project| {
+            project.set_melody_steps(steps)
+        });
+        self.gui_state.as_ref().borrow_mut().status_message =
+            result.err().map(|message| message.to_string());
+    }
+
+    /// Remove the note of `pitch` under `position`, or add one there if there isn't one.
+    pub fn toggle_note(&mut self, pitch: Note, position: MusicalPosition) {
+        let removed = self.edit("Remove note", false, |project| {
+            project.remove_melody_note(pitch, position)
+        });
+        if removed {
+            return;
+        }
+        let result = self.add_note(pitch, position);
+        self.gui_state.as_ref().borrow_mut().status_message =
+            result.err().map(|message| message.to_string());
+    }
+
+    fn add_note(&mut self, pitch: Note, position: MusicalPosition) -> Result<(), &'static str> {
+        let settings = self.settings();
+        let selected_lane = self.gui_state.as_ref().borrow().selected_lane;
+        let start = quantise(position, settings.quantise);
+        self.edit("Add note", false, |project| {
+            let note = if settings.snap_to_chords {
+                let step = (start.ticks() / TICKS_PER_TATUM) as usize;
+                let chord = project.lanes[selected_lane].latest_chord_at(step);
+                snap_to_chord_tones(pitch, chord)
+            } else {
+                pitch
+            };
+            project.add_melody_note(MelodyNote {
+                note,
+                start,
+                length: MusicalPosition::from_tatums(settings.length as u64),
+                velocity: project.melody_output.velocity,
+            })
+        })
+    }
+
+    fn edit<T>(
+        &mut self,
+        description: &'static str,
+        grouped: bool,
+        edit: impl FnOnce(&mut ProjectState) -> T,
+    ) -> T {
+        edit_project(
+            &self.project_state,
+            &self.gui_state,
+            description,
+            grouped,
+            edit,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        rc::Rc,
+        sync::{Arc, RwLock},
+    };
+
+    use crate::{
+        data_types::{
+            chord_degree::ChordDegree, musical_position::MusicalPosition, note::Note, tatum::Tatum,
+        },
+        model::{gui_state::MelodySettings, make_application_state},
+        view_model::melody_vm::MelodyVm,
+    };
+
+    #[test]
+    fn toggle_note_adds_then_removes() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = MelodyVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.toggle_note(Note::from(60), MusicalPosition::from_tatums(4));
+        assert_eq!(vm.melody().notes().len(), 1);
+        vm.toggle_note(Note::from(60), MusicalPosition::from_tatums(5));
+        assert!(vm.melody().notes().is_empty());
+    }
+
+    #[test]
+    fn new_notes_are_quantised_and_snapped() {
+        let (mut project_state, gui_state) = make_application_state();
+        project_state.lanes[0][Tatum::try_from(0).unwrap()] = Some(ChordDegree::V);
+        let mut vm = MelodyVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.toggle_note(Note::from(72), MusicalPosition::from_ticks(1100));
+        let note = vm.melody().notes()[0];
+        assert_eq!(note.start, MusicalPosition::from_tatums(5));
+        // C is not in the dominant, and B is the nearest tone that is
+        assert_eq!(note.note, Note::from(71));
+    }
+
+    #[test]
+    fn off_grid_notes_keep_their_position_and_pitch() {
+        let (project_state, gui_state) = make_application_state();
+        let mut vm = MelodyVm::new(
+            Rc::new(RefCell::new(gui_state)),
+            Arc::new(RwLock::new(project_state)),
+        );
+        vm.set_settings(MelodySettings {
+            quantise: false,
+            snap_to_chords: false,
+            ..MelodySettings::default()
+        });
+        vm.toggle_note(Note::from(61), MusicalPosition::from_ticks(1100));
+        let note = vm.melody().notes()[0];
+        assert_eq!(note.start, MusicalPosition::from_ticks(1100));
+        assert_eq!(note.note, Note::from(61));
+    }
+}
@@ -1 +1,2 @@
 pub mod chord_sequencer_vm;
+pub mod melody_vm;